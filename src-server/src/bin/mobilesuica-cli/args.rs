@@ -0,0 +1,169 @@
+use std::path::PathBuf;
+
+pub const USAGE: &str = "\
+Usage: mobilesuica-cli [OPTIONS]
+
+モバイルSuicaにログインして利用履歴を標準出力に書き出します。
+
+Options:
+  --format <csv|json>                 出力形式（既定: csv）
+  --image <auto|kitty|sixel|file>     キャプチャ画像の表示方法（既定: auto）
+  --image-path <PATH>                 fileのときの保存先（既定: 一時ディレクトリ）
+  --scale <1-8>                       キャプチャ画像の拡大倍率（既定: 3）
+  --include-green                     使用済みのSuicaグリーン券の購入も履歴に含める
+  --exclude-commuter-pass             定期券の区間の乗車を履歴から除く
+  --rules <PATH>                      区分とプロジェクトを付けるルールのJSONファイル
+  -h, --help                          このヘルプを表示する
+
+Environment:
+  MOBILESUICA_CREDENTIALS                   ログイン情報の取得先（env, file:<PATH>, secret-service）
+  MOBILESUICA_EMAIL, MOBILESUICA_PASSWORD   未設定の場合は入力を求めます
+  MOBILESUICA_CAPTCHA_TEMPLATES             キャプチャを自動で読み取る見本のディレクトリ
+                                            （手入力でログインできたキャプチャを見本として保存します）
+";
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum OutputFormat {
+    #[default]
+    Csv,
+    Json,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum ImageMode {
+    // kittyの端末ならkitty、それ以外はファイルに保存する
+    #[default]
+    Auto,
+    Kitty,
+    Sixel,
+    File,
+}
+
+#[derive(Debug, PartialEq)]
+pub struct Args {
+    pub format: OutputFormat,
+    pub image: ImageMode,
+    pub image_path: Option<PathBuf>,
+    pub scale: u32,
+    pub include_green: bool,
+    pub exclude_commuter_pass: bool,
+    pub rules: Option<PathBuf>,
+    pub help: bool,
+}
+
+impl Default for Args {
+    fn default() -> Self {
+        Args {
+            format: OutputFormat::default(),
+            image: ImageMode::default(),
+            image_path: None,
+            scale: 3,
+            include_green: false,
+            exclude_commuter_pass: false,
+            rules: None,
+            help: false,
+        }
+    }
+}
+
+impl Args {
+    pub fn parse<I>(args: I) -> Result<Self, String>
+    where
+        I: IntoIterator<Item = String>,
+    {
+        let mut parsed = Args::default();
+        let mut args = args.into_iter();
+
+        while let Some(arg) = args.next() {
+            // --format=json の形式も受け付ける
+            let (name, inline_value) = match arg.split_once('=') {
+                Some((name, value)) if name.starts_with("--") => {
+                    (name.to_string(), Some(value.to_string()))
+                }
+                _ => (arg, None),
+            };
+
+            let mut value = || {
+                inline_value
+                    .clone()
+                    .or_else(|| args.next())
+                    .ok_or(format!("{} の値がありません。", name))
+            };
+
+            match name.as_str() {
+                "--format" => {
+                    parsed.format = match value()?.as_str() {
+                        "csv" => OutputFormat::Csv,
+                        "json" => OutputFormat::Json,
+                        other => return Err(format!("不明な出力形式です: {}", other)),
+                    }
+                }
+                "--image" => {
+                    parsed.image = match value()?.as_str() {
+                        "auto" => ImageMode::Auto,
+                        "kitty" => ImageMode::Kitty,
+                        "sixel" => ImageMode::Sixel,
+                        "file" => ImageMode::File,
+                        other => return Err(format!("不明な表示方法です: {}", other)),
+                    }
+                }
+                "--image-path" => parsed.image_path = Some(PathBuf::from(value()?)),
+                "--scale" => {
+                    parsed.scale = value()?
+                        .parse::<u32>()
+                        .ok()
+                        .filter(|scale| (1..=8).contains(scale))
+                        .ok_or("--scale は1〜8で指定してください。".to_string())?
+                }
+                "--include-green" => parsed.include_green = true,
+                "--exclude-commuter-pass" => parsed.exclude_commuter_pass = true,
+                "--rules" => parsed.rules = Some(PathBuf::from(value()?)),
+                "-h" | "--help" => parsed.help = true,
+                other => return Err(format!("不明なオプションです: {}", other)),
+            }
+        }
+
+        Ok(parsed)
+    }
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::*;
+
+    fn parse(args: &[&str]) -> Result<Args, String> {
+        Args::parse(args.iter().map(|arg| arg.to_string()))
+    }
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(parse(&[]).unwrap(), Args::default());
+
+        let args = parse(&["--format", "json", "--image=file", "--scale", "4"]).unwrap();
+
+        assert_eq!(args.format, OutputFormat::Json);
+        assert_eq!(args.image, ImageMode::File);
+        assert_eq!(args.scale, 4);
+
+        assert!(parse(&["--include-green"]).unwrap().include_green);
+        assert!(
+            parse(&["--exclude-commuter-pass"])
+                .unwrap()
+                .exclude_commuter_pass
+        );
+        assert_eq!(
+            parse(&["--rules", "rules.json"]).unwrap().rules,
+            Some(PathBuf::from("rules.json"))
+        );
+        assert!(parse(&["--help"]).unwrap().help);
+    }
+
+    #[test]
+    fn test_parse_error() {
+        assert!(parse(&["--format", "xml"]).is_err());
+        assert!(parse(&["--format"]).is_err());
+        assert!(parse(&["--scale", "0"]).is_err());
+        assert!(parse(&["--unknown"]).is_err());
+    }
+}
@@ -0,0 +1,296 @@
+mod args;
+mod terminal_image;
+
+use std::env;
+use std::fs;
+use std::io::{self, BufRead, Write};
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+use std::sync::Arc;
+
+use args::{Args, ImageMode, OutputFormat, USAGE};
+use chrono::{FixedOffset, Utc};
+use mobilesuica_sheet_app_server::HttpClient::{
+    get_client, set_client_config, ClientConfig, MobilesuicaCookies,
+};
+use mobilesuica_sheet_app_server::MobilesuicaLogin::{
+    download_captcha, fetch_login_page, get_login_error_message, login, LoginError,
+};
+use mobilesuica_sheet_app_server::{
+    apply_tag_rules, captcha_solver_from_env, convert_captcha_image, credentials_provider_from_env,
+    fetch_commuter_pass, fetch_green_tickets, fetch_usage_history, get_credentials_error_message,
+    get_tag_rule_error_message, merge_green_tickets, sniff_mime_type, validate_tag_rules,
+    write_usage_csv, write_usage_json, CaptchaImageOptions, CaptchaSolver, CommuterPassError,
+    Credentials, GreenTicketError, TagRule, UsageHistoryError,
+};
+
+const EMAIL_ENV: &str = "MOBILESUICA_EMAIL";
+const PASSWORD_ENV: &str = "MOBILESUICA_PASSWORD";
+
+// キャプチャを間違えたときに取り直す回数
+const LOGIN_ATTEMPTS: usize = 3;
+
+// 標準出力は履歴の書き出しに使うので、案内はすべて標準エラー出力に出す
+fn prompt(message: &str) -> io::Result<String> {
+    eprint!("{}", message);
+    io::stderr().flush()?;
+
+    let mut line = String::new();
+    io::stdin().lock().read_line(&mut line)?;
+
+    Ok(line.trim().to_string())
+}
+
+fn prompt_credentials() -> io::Result<Credentials> {
+    let email = match env::var(EMAIL_ENV) {
+        Ok(email) if !email.is_empty() => email,
+        _ => prompt("メールアドレス: ")?,
+    };
+
+    let password = match env::var(PASSWORD_ENV) {
+        Ok(password) if !password.is_empty() => password,
+        _ => rpassword::prompt_password("パスワード: ")?,
+    };
+
+    Ok(Credentials { email, password })
+}
+
+// MOBILESUICA_CREDENTIALSが設定されていればそこから読み、なければ入力を求める
+fn read_credentials() -> Result<Credentials, String> {
+    match credentials_provider_from_env().map_err(|e| get_credentials_error_message(&e))? {
+        Some(provider) => provider
+            .credentials()
+            .map_err(|e| get_credentials_error_message(&e)),
+        None => prompt_credentials().map_err(|e| e.to_string()),
+    }
+}
+
+fn captcha_image_path(args: &Args, captcha_image: &[u8]) -> PathBuf {
+    let extension = match sniff_mime_type(captcha_image) {
+        Some("image/png") => "png",
+        Some("image/jpeg") => "jpg",
+        _ => "gif",
+    };
+
+    args.image_path
+        .clone()
+        .unwrap_or_else(|| env::temp_dir().join(format!("mobilesuica-captcha.{}", extension)))
+}
+
+// キャプチャ画像を端末に表示するか、ファイルに保存して場所を案内する
+fn show_captcha(args: &Args, captcha_image: &[u8]) -> io::Result<()> {
+    let mode = match args.image {
+        ImageMode::Auto if terminal_image::is_kitty() => ImageMode::Kitty,
+        ImageMode::Auto => ImageMode::File,
+        mode => mode,
+    };
+
+    let options = CaptchaImageOptions {
+        scale: args.scale,
+        ..Default::default()
+    };
+
+    let png = match mode {
+        ImageMode::Kitty | ImageMode::Sixel => convert_captcha_image(captcha_image, &options).ok(),
+        _ => None,
+    };
+
+    let mut stderr = io::stderr();
+
+    match (mode, png) {
+        (ImageMode::Kitty, Some(png)) => {
+            stderr.write_all(terminal_image::encode_kitty(&png).as_bytes())?
+        }
+        (ImageMode::Sixel, Some(png)) => {
+            let image = image::load_from_memory(&png)
+                .map_err(io::Error::other)?
+                .to_rgba8();
+
+            stderr.write_all(terminal_image::encode_sixel(&image).as_bytes())?;
+            stderr.write_all(b"\n")?;
+        }
+        // 端末に表示できない場合もファイルに保存する
+        _ => {
+            let path = captcha_image_path(args, captcha_image);
+
+            fs::write(&path, captcha_image)?;
+            eprintln!("キャプチャ画像を保存しました: {}", path.display());
+        }
+    }
+
+    stderr.flush()
+}
+
+async fn login_with_captcha(
+    client: &reqwest::Client,
+    solver: &dyn CaptchaSolver,
+    args: &Args,
+    credentials: &Credentials,
+) -> Result<(), String> {
+    let to_message = |e: LoginError| get_login_error_message(&e);
+
+    for attempt in 1..=LOGIN_ATTEMPTS {
+        let login_page = fetch_login_page(client).await.map_err(to_message)?;
+
+        let captcha_image = download_captcha(client, &login_page.captcha_url)
+            .await
+            .map_err(to_message)?;
+
+        let (captcha, is_typed) = match solver.solve(&captcha_image) {
+            Some(answer) => (answer, false),
+            None => {
+                show_captcha(args, &captcha_image).map_err(|e| e.to_string())?;
+                (prompt("画像の文字: ").map_err(|e| e.to_string())?, true)
+            }
+        };
+
+        let mut mobilesuica_form_params = login_page.mobilesuica_form_params;
+
+        mobilesuica_form_params
+            .set_mail_address(&credentials.email)
+            .set_password(&credentials.password)
+            .set_captcha(&captcha);
+
+        match login(client, &login_page.action_url, &mobilesuica_form_params).await {
+            Err(LoginError::InvalidCaptcha) if attempt < LOGIN_ATTEMPTS => {
+                eprintln!("画像の文字が正しくありません。もう一度入力してください。");
+            }
+            Ok(()) => {
+                // 入力した文字でログインできた画像を次回からの見本にする
+                if is_typed {
+                    solver.remember(&captcha_image, &captcha);
+                }
+
+                return Ok(());
+            }
+            result => return result.map_err(to_message),
+        }
+    }
+
+    Err(get_login_error_message(&LoginError::InvalidCaptcha))
+}
+
+fn get_usage_history_error_message(error: &UsageHistoryError) -> String {
+    match error {
+        UsageHistoryError::RequestFailed => "利用履歴の取得に失敗しました。",
+        UsageHistoryError::SessionExpired => "ログインの有効期限が切れました。",
+        UsageHistoryError::Maintenance(_) => "モバイルSuicaはメンテナンス中です。",
+    }
+    .to_string()
+}
+
+fn get_green_ticket_error_message(error: &GreenTicketError) -> String {
+    match error {
+        GreenTicketError::RequestFailed => "グリーン券の購入履歴の取得に失敗しました。",
+        GreenTicketError::SessionExpired => "ログインの有効期限が切れました。",
+        GreenTicketError::Maintenance(_) => "モバイルSuicaはメンテナンス中です。",
+    }
+    .to_string()
+}
+
+fn get_commuter_pass_error_message(error: &CommuterPassError) -> String {
+    match error {
+        CommuterPassError::RequestFailed => "定期券情報の取得に失敗しました。",
+        CommuterPassError::SessionExpired => "ログインの有効期限が切れました。",
+        CommuterPassError::Maintenance(_) => "モバイルSuicaはメンテナンス中です。",
+    }
+    .to_string()
+}
+
+// ログインの前に読んでおき、ファイルの誤りで入力がむだにならないようにする
+fn read_tag_rules(path: &Path) -> Result<Vec<TagRule>, String> {
+    let json = fs::read_to_string(path)
+        .map_err(|e| format!("ルールを読み込めません: {}: {}", path.display(), e))?;
+
+    let rules = serde_json::from_str::<Vec<TagRule>>(&json)
+        .map_err(|e| format!("ルールの形式が正しくありません: {}", e))?;
+
+    validate_tag_rules(&rules).map_err(|e| get_tag_rule_error_message(&e))?;
+
+    Ok(rules)
+}
+
+async fn run(args: Args) -> Result<(), String> {
+    let client_config = ClientConfig::from_env().map_err(|e| format!("{:?}", e))?;
+    // 起動直後なので設定済みになることはない
+    let _ = set_client_config(client_config);
+
+    let solver = captcha_solver_from_env().map_err(|e| e.to_string())?;
+
+    let rules = match &args.rules {
+        Some(path) => read_tag_rules(path)?,
+        None => vec![],
+    };
+
+    let credentials = read_credentials()?;
+
+    let cookies = Arc::new(MobilesuicaCookies::default());
+    let client = get_client(cookies).await.map_err(|e| e.to_string())?;
+
+    login_with_captcha(&client, solver.as_ref(), &args, &credentials).await?;
+
+    let records = fetch_usage_history(&client)
+        .await
+        .map_err(|e| get_usage_history_error_message(&e))?;
+
+    // 履歴の日付は日本時間
+    let jst = FixedOffset::east_opt(9 * 60 * 60).unwrap();
+    let today = Utc::now().with_timezone(&jst).date_naive();
+
+    let mut records = match args.include_green {
+        true => {
+            let green_tickets = fetch_green_tickets(&client)
+                .await
+                .map_err(|e| get_green_ticket_error_message(&e))?;
+
+            merge_green_tickets(&records, &green_tickets, today)
+        }
+        false => records,
+    };
+
+    // 定期券の区間の乗車は精算しないので書き出さない
+    if args.exclude_commuter_pass {
+        let commuter_pass = fetch_commuter_pass(&client)
+            .await
+            .map_err(|e| get_commuter_pass_error_message(&e))?;
+
+        if let Some(commuter_pass) = commuter_pass {
+            records.retain(|record| !commuter_pass.covers(record, today));
+        }
+    }
+
+    apply_tag_rules(&mut records, &rules, today);
+
+    let mut stdout = io::stdout().lock();
+
+    match args.format {
+        OutputFormat::Csv => write_usage_csv(&records, &mut stdout).map_err(|e| e.to_string()),
+        OutputFormat::Json => write_usage_json(&records, &mut stdout)
+            .map_err(|e| e.to_string())
+            .and_then(|_| writeln!(stdout).map_err(|e| e.to_string())),
+    }
+}
+
+#[tokio::main]
+async fn main() -> ExitCode {
+    let args = match Args::parse(env::args().skip(1)) {
+        Ok(args) => args,
+        Err(message) => {
+            eprintln!("{}\n\n{}", message, USAGE);
+            return ExitCode::from(2);
+        }
+    };
+
+    if args.help {
+        print!("{}", USAGE);
+        return ExitCode::SUCCESS;
+    }
+
+    match run(args).await {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(message) => {
+            eprintln!("{}", message);
+            ExitCode::FAILURE
+        }
+    }
+}
@@ -0,0 +1,163 @@
+use base64::{engine::general_purpose::STANDARD, Engine as _};
+use image::RgbaImage;
+use std::collections::BTreeSet;
+use std::env;
+use std::fmt::Write;
+
+// kittyのグラフィックプロトコルで1回に送れるbase64の長さ
+const KITTY_CHUNK_SIZE: usize = 4096;
+
+pub fn is_kitty() -> bool {
+    env::var_os("KITTY_WINDOW_ID").is_some()
+        || env::var("TERM").is_ok_and(|term| term.contains("kitty"))
+}
+
+// PNGをkittyのグラフィックプロトコルで表示するエスケープシーケンス
+pub fn encode_kitty(png: &[u8]) -> String {
+    let encoded = STANDARD.encode(png);
+    let chunks = encoded
+        .as_bytes()
+        .chunks(KITTY_CHUNK_SIZE)
+        .collect::<Vec<_>>();
+
+    let mut output = String::new();
+
+    for (index, chunk) in chunks.iter().enumerate() {
+        let more = (index + 1 < chunks.len()) as u8;
+        let chunk = std::str::from_utf8(chunk).unwrap();
+
+        match index {
+            0 => write!(output, "\x1b_Gf=100,a=T,m={};{}\x1b\\", more, chunk),
+            _ => write!(output, "\x1b_Gm={};{}\x1b\\", more, chunk),
+        }
+        .unwrap();
+    }
+
+    output.push('\n');
+    output
+}
+
+// 6x6x6の色に減色したときの番号
+fn color_index(pixel: &image::Rgba<u8>) -> usize {
+    // 透明部分は白にする
+    let [r, g, b] = match pixel[3] {
+        0..=127 => [255, 255, 255],
+        _ => [pixel[0], pixel[1], pixel[2]],
+    };
+
+    let level = |value: u8| (value as usize * 5 + 127) / 255;
+
+    level(r) * 36 + level(g) * 6 + level(b)
+}
+
+fn push_run(output: &mut String, sixel: u8, count: usize) {
+    let c = (sixel + 63) as char;
+
+    match count {
+        0 => {}
+        1..=3 => (0..count).for_each(|_| output.push(c)),
+        _ => write!(output, "!{}{}", count, c).unwrap(),
+    }
+}
+
+// 画像をsixelのエスケープシーケンスにする
+pub fn encode_sixel(image: &RgbaImage) -> String {
+    let (width, height) = image.dimensions();
+
+    let indices = image.pixels().map(color_index).collect::<Vec<usize>>();
+
+    let mut output = String::from("\x1bPq");
+    write!(output, "\"1;1;{};{}", width, height).unwrap();
+
+    // 使っている色だけ定義する（RGBは0〜100で指定）
+    let palette = indices.iter().copied().collect::<BTreeSet<usize>>();
+    for &index in &palette {
+        let percent = |level: usize| level * 100 / 5;
+        write!(
+            output,
+            "#{};2;{};{};{}",
+            index,
+            percent(index / 36),
+            percent(index / 6 % 6),
+            percent(index % 6)
+        )
+        .unwrap();
+    }
+
+    for band in (0..height).step_by(6) {
+        let rows = band..(band + 6).min(height);
+
+        let band_colors = palette
+            .iter()
+            .copied()
+            .filter(|&color| {
+                rows.clone()
+                    .any(|y| (0..width).any(|x| indices[(y * width + x) as usize] == color))
+            })
+            .collect::<Vec<usize>>();
+
+        for (position, color) in band_colors.iter().enumerate() {
+            write!(output, "#{}", color).unwrap();
+
+            let (mut current, mut count) = (0u8, 0usize);
+
+            for x in 0..width {
+                let sixel = rows
+                    .clone()
+                    .filter(|&y| indices[(y * width + x) as usize] == *color)
+                    .fold(0u8, |bits, y| bits | 1 << (y - band));
+
+                if sixel != current && count > 0 {
+                    push_run(&mut output, current, count);
+                    count = 0;
+                }
+                current = sixel;
+                count += 1;
+            }
+            push_run(&mut output, current, count);
+
+            // 同じ帯の次の色は行頭に戻って重ねる
+            if position + 1 < band_colors.len() {
+                output.push('$');
+            }
+        }
+
+        output.push('-');
+    }
+
+    output.push_str("\x1b\\");
+    output
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::*;
+    use image::Rgba;
+
+    #[test]
+    fn test_encode_kitty() {
+        let encoded = encode_kitty(b"png");
+
+        assert_eq!(encoded, "\x1b_Gf=100,a=T,m=0;cG5n\x1b\\\n");
+
+        let encoded = encode_kitty(&[0u8; KITTY_CHUNK_SIZE]);
+
+        assert!(encoded.starts_with("\x1b_Gf=100,a=T,m=1;"));
+        assert!(encoded.contains("\x1b\\\x1b_Gm=0;"));
+    }
+
+    #[test]
+    fn test_encode_sixel() {
+        // 上半分が黒、下半分が白の4x6
+        let image = RgbaImage::from_fn(4, 6, |_, y| match y < 3 {
+            true => Rgba([0, 0, 0, 255]),
+            false => Rgba([255, 255, 255, 255]),
+        });
+
+        assert_eq!(
+            encode_sixel(&image),
+            "\x1bPq\"1;1;4;6#0;2;0;0;0#215;2;100;100;100#0!4F$#215!4w-\x1b\\"
+        );
+    }
+}
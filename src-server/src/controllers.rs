@@ -1,2 +1,7 @@
+pub mod accounts;
 pub mod auth;
 pub mod captcha;
+pub mod card;
+pub mod receipt;
+pub mod status;
+pub mod users;
@@ -0,0 +1,522 @@
+use std::collections::BTreeMap;
+use std::sync::Arc;
+
+use axum::{
+    extract::{Path, Query, State},
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    Json,
+};
+use chrono::{Local, NaiveDate, TimeZone};
+use mobilesuica_sheet_app_server::HttpClient::{get_client, MobilesuicaCookies};
+use mobilesuica_sheet_app_server::{
+    fetch_usage_history, group_journeys, Journey, Maintenance, UsageHistoryError, UsageRecord,
+};
+use serde::{Deserialize, Serialize};
+use tokio::task::JoinSet;
+
+use crate::controllers::card::today;
+use crate::controllers::status::maintenance_response;
+use crate::controllers::users::{apply_user_tag_rules, current_user};
+use crate::AppState;
+
+// ラベルを指定せずにログインした場合のアカウント
+pub const DEFAULT_ACCOUNT_LABEL: &str = "default";
+
+const AUTH_COOKIES_KEY: &str = "auth_cookies";
+const MAX_LABEL_LENGTH: usize = 32;
+
+// ログイン済みのアカウント。個人用と法人用など複数のカードをラベルで分ける
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct Account {
+    email: String,
+    cookies: MobilesuicaCookies,
+    // UNIX時間の秒
+    logged_in_at: i64,
+}
+
+type Accounts = BTreeMap<String, Account>;
+
+#[derive(Debug, PartialEq)]
+enum AccountError {
+    AccountNotFound,
+    RequestFailed,
+    SessionExpired,
+    Maintenance(Maintenance),
+}
+
+fn get_account_error_message(error: &AccountError) -> String {
+    match error {
+        AccountError::AccountNotFound => "アカウントが見つかりません。",
+        AccountError::RequestFailed => "利用履歴の取得に失敗しました。",
+        AccountError::SessionExpired => {
+            "ログインの有効期限が切れました。再度ログインしてください。"
+        }
+        AccountError::Maintenance(_) => "モバイルSuicaはメンテナンス中です。",
+    }
+    .to_string()
+}
+
+fn get_account_error_code(error: &AccountError) -> String {
+    match error {
+        AccountError::AccountNotFound => "account_not_found",
+        AccountError::RequestFailed => "request_failed",
+        AccountError::SessionExpired => "session_expired",
+        AccountError::Maintenance(_) => "maintenance",
+    }
+    .to_string()
+}
+
+// 空の場合はdefaultにする。使えない文字を含む場合はNone
+pub fn normalize_label(label: &str) -> Option<String> {
+    let label = label.trim();
+
+    match label {
+        "" => Some(DEFAULT_ACCOUNT_LABEL.to_string()),
+        _ if label.chars().count() > MAX_LABEL_LENGTH || label.contains('/') => None,
+        _ => Some(label.to_string()),
+    }
+}
+
+fn get_accounts(state: &AppState) -> Accounts {
+    let session = state.session.lock().unwrap();
+
+    session
+        .get::<Accounts>(AUTH_COOKIES_KEY)
+        .unwrap_or_default()
+}
+
+// ラベルのアカウントのCookie。ログインしていない場合はNone
+pub fn get_account_cookies(state: &AppState, label: &str) -> Option<MobilesuicaCookies> {
+    get_accounts(state)
+        .remove(label)
+        .map(|account| account.cookies)
+}
+
+// ログインに成功したアカウントを追加する。同じラベルの場合は上書き
+// アプリにログインしている場合はユーザーにも紐付ける
+pub fn save_account(state: &AppState, label: &str, email: &str, cookies: &MobilesuicaCookies) {
+    {
+        let mut session = state.session.lock().unwrap();
+
+        let mut accounts = session
+            .get::<Accounts>(AUTH_COOKIES_KEY)
+            .unwrap_or_default();
+
+        accounts.insert(
+            label.to_string(),
+            Account {
+                email: email.to_string(),
+                cookies: cookies.clone(),
+                logged_in_at: Local::now().timestamp(),
+            },
+        );
+
+        session.set(AUTH_COOKIES_KEY, &accounts);
+    }
+
+    if let Some((store, user_id)) = current_user(state) {
+        if store.link_account(user_id, label, email).is_err() {
+            tracing::warn!("failed to link account {} to user {}", label, user_id);
+        }
+    }
+}
+
+pub fn update_account_cookies(state: &AppState, label: &str, cookies: &MobilesuicaCookies) {
+    let mut session = state.session.lock().unwrap();
+
+    let mut accounts = session
+        .get::<Accounts>(AUTH_COOKIES_KEY)
+        .unwrap_or_default();
+
+    if let Some(account) = accounts.get_mut(label) {
+        account.cookies = cookies.clone();
+        session.set(AUTH_COOKIES_KEY, &accounts);
+    }
+}
+
+fn remove_account(state: &AppState, label: &str) -> bool {
+    let removed = {
+        let mut session = state.session.lock().unwrap();
+
+        let mut accounts = session
+            .get::<Accounts>(AUTH_COOKIES_KEY)
+            .unwrap_or_default();
+
+        let removed = accounts.remove(label).is_some();
+        session.set(AUTH_COOKIES_KEY, &accounts);
+
+        removed
+    };
+
+    // セッションに残っていなくてもユーザーとの紐付けは解除する
+    let unlinked = current_user(state)
+        .is_some_and(|(store, user_id)| store.unlink_account(user_id, label).unwrap_or(false));
+
+    removed || unlinked
+}
+
+#[derive(Serialize, Debug)]
+pub struct AccountsJson<T: Serialize> {
+    ok: bool,
+    result: T,
+}
+
+#[derive(Serialize, Debug)]
+struct AccountErrorResult {
+    code: String,
+    message: String,
+}
+
+#[derive(Serialize, Debug, PartialEq)]
+struct AccountSummary {
+    label: String,
+    email: String,
+    logged_in_at: Option<String>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct AccountList {
+    accounts: Vec<AccountSummary>,
+}
+
+#[derive(Serialize, Debug)]
+struct RemovedAccount {
+    label: String,
+}
+
+#[derive(Serialize, Debug, PartialEq)]
+struct AccountRecord {
+    // どのアカウントの履歴か
+    account: String,
+    #[serde(flatten)]
+    record: UsageRecord,
+}
+
+#[derive(Serialize, Debug, PartialEq)]
+struct AccountHistoryError {
+    account: String,
+    code: String,
+    message: String,
+}
+
+#[derive(Serialize, Debug, Default)]
+pub struct AccountHistory {
+    records: Vec<AccountRecord>,
+    // 取得できなかったアカウント。他のアカウントの履歴は返す
+    errors: Vec<AccountHistoryError>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct AccountJourneys {
+    account: String,
+    journeys: Vec<Journey>,
+}
+
+fn create_error_response(status: StatusCode, error: AccountError) -> Response {
+    let body = Json(AccountsJson {
+        ok: false,
+        result: AccountErrorResult {
+            code: get_account_error_code(&error),
+            message: get_account_error_message(&error),
+        },
+    });
+
+    (status, body).into_response()
+}
+
+fn create_account_list(accounts: &Accounts) -> AccountList {
+    AccountList {
+        accounts: accounts
+            .iter()
+            .map(|(label, account)| AccountSummary {
+                label: label.clone(),
+                email: account.email.clone(),
+                logged_in_at: Local
+                    .timestamp_opt(account.logged_in_at, 0)
+                    .single()
+                    .map(|logged_in_at| logged_in_at.to_rfc3339()),
+            })
+            .collect(),
+    }
+}
+
+async fn fetch_account_history(
+    state: &AppState,
+    label: &str,
+    account: Account,
+) -> Result<Vec<UsageRecord>, AccountError> {
+    let cookies = Arc::new(account.cookies);
+
+    let client = get_client(cookies.clone())
+        .await
+        .map_err(|_| AccountError::RequestFailed)?;
+
+    let mut records = fetch_usage_history(&client).await.map_err(|e| match e {
+        UsageHistoryError::RequestFailed => AccountError::RequestFailed,
+        UsageHistoryError::SessionExpired => AccountError::SessionExpired,
+        UsageHistoryError::Maintenance(maintenance) => AccountError::Maintenance(maintenance),
+    })?;
+
+    // 取得時に更新されたCookieを保存しておく
+    update_account_cookies(state, label, &cookies);
+
+    if let Some((store, user_id)) = current_user(state) {
+        if store.archive_history(user_id, label, &records).is_err() {
+            tracing::warn!(
+                "failed to archive history of {} for user {}",
+                label,
+                user_id
+            );
+        }
+    }
+
+    apply_user_tag_rules(state, &mut records);
+
+    Ok(records)
+}
+
+// アカウントごとの結果を1つの履歴にまとめる
+fn merge_history(
+    results: Vec<(String, Result<Vec<UsageRecord>, AccountError>)>,
+    today: NaiveDate,
+) -> AccountHistory {
+    let mut history = AccountHistory::default();
+
+    for (account, result) in results {
+        match result {
+            Ok(records) => {
+                history
+                    .records
+                    .extend(records.into_iter().map(|record| AccountRecord {
+                        account: account.clone(),
+                        record,
+                    }))
+            }
+            Err(e) => history.errors.push(AccountHistoryError {
+                account,
+                code: get_account_error_code(&e),
+                message: get_account_error_message(&e),
+            }),
+        }
+    }
+
+    // 年を補った日付の新しい順。同じ日の中では各アカウントの履歴の順を保つ
+    history
+        .records
+        .sort_by_key(|record| std::cmp::Reverse(record.record.infer_date(today)));
+
+    history
+}
+
+pub async fn list_handler(State(state): State<AppState>) -> Json<AccountsJson<AccountList>> {
+    Json(AccountsJson {
+        ok: true,
+        result: create_account_list(&get_accounts(&state)),
+    })
+}
+
+pub async fn delete_handler(State(state): State<AppState>, Path(label): Path<String>) -> Response {
+    match remove_account(&state, &label) {
+        true => Json(AccountsJson {
+            ok: true,
+            result: RemovedAccount { label },
+        })
+        .into_response(),
+        false => create_error_response(StatusCode::NOT_FOUND, AccountError::AccountNotFound),
+    }
+}
+
+// アカウントを指定して利用履歴を取得する
+pub async fn history_handler(State(state): State<AppState>, Path(label): Path<String>) -> Response {
+    let Some(account) = get_accounts(&state).remove(&label) else {
+        return create_error_response(StatusCode::NOT_FOUND, AccountError::AccountNotFound);
+    };
+
+    match fetch_account_history(&state, &label, account).await {
+        Ok(records) => Json(AccountsJson {
+            ok: true,
+            result: merge_history(vec![(label, Ok(records))], today()),
+        })
+        .into_response(),
+        Err(AccountError::Maintenance(maintenance)) => maintenance_response(maintenance),
+        Err(e) => create_error_response(StatusCode::OK, e),
+    }
+}
+
+#[derive(Deserialize, Debug, Default)]
+pub struct JourneysQuery {
+    // 乗り継ぎらしい乗車を1つの移動にまとめる。時刻がわからないので既定ではまとめない
+    #[serde(default)]
+    merge_transfers: bool,
+}
+
+// アカウントの利用履歴を移動ごとに返す（Journeyを参照）
+pub async fn journeys_handler(
+    State(state): State<AppState>,
+    Path(label): Path<String>,
+    Query(query): Query<JourneysQuery>,
+) -> Response {
+    let Some(account) = get_accounts(&state).remove(&label) else {
+        return create_error_response(StatusCode::NOT_FOUND, AccountError::AccountNotFound);
+    };
+
+    match fetch_account_history(&state, &label, account).await {
+        Ok(records) => Json(AccountsJson {
+            ok: true,
+            result: AccountJourneys {
+                account: label,
+                journeys: group_journeys(&records, query.merge_transfers),
+            },
+        })
+        .into_response(),
+        Err(AccountError::Maintenance(maintenance)) => maintenance_response(maintenance),
+        Err(e) => create_error_response(StatusCode::OK, e),
+    }
+}
+
+// ログイン済みの全アカウントの利用履歴を並行して取得し、まとめる
+pub async fn merged_history_handler(
+    State(state): State<AppState>,
+) -> Json<AccountsJson<AccountHistory>> {
+    let mut tasks = JoinSet::new();
+
+    for (label, account) in get_accounts(&state) {
+        let state = state.clone();
+
+        tasks.spawn(async move {
+            let result = fetch_account_history(&state, &label, account).await;
+            (label, result)
+        });
+    }
+
+    let mut results = vec![];
+
+    while let Some(joined) = tasks.join_next().await {
+        match joined {
+            Ok(result) => results.push(result),
+            Err(e) => tracing::warn!("failed to fetch account history: {}", e),
+        }
+    }
+
+    Json(AccountsJson {
+        ok: true,
+        result: merge_history(results, today()),
+    })
+}
+
+#[cfg(test)]
+mod test {
+
+    use std::sync::Mutex;
+
+    use super::*;
+    use mobilesuica_sheet_app_server::Session;
+
+    fn get_state() -> AppState {
+        let session_id = Session::new();
+
+        AppState {
+            session: Arc::new(Mutex::new(Session::get_session(&session_id).unwrap())),
+        }
+    }
+
+    fn record(date: &str, amount: i64) -> UsageRecord {
+        UsageRecord {
+            date: date.to_string(),
+            amount: Some(amount),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_normalize_label() {
+        assert_eq!(normalize_label(""), Some("default".to_string()));
+        assert_eq!(normalize_label(" 法人 "), Some("法人".to_string()));
+        assert_eq!(normalize_label("a/b"), None);
+        assert_eq!(normalize_label(&"a".repeat(33)), None);
+    }
+
+    #[test]
+    fn test_save_and_remove_account() {
+        let state = get_state();
+
+        save_account(
+            &state,
+            "personal",
+            "personal@example.com",
+            &Default::default(),
+        );
+        save_account(
+            &state,
+            "corporate",
+            "corporate@example.com",
+            &Default::default(),
+        );
+
+        let list = create_account_list(&get_accounts(&state));
+
+        assert_eq!(list.accounts.len(), 2);
+        assert_eq!(list.accounts[0].label, "corporate");
+        assert_eq!(list.accounts[1].email, "personal@example.com");
+
+        assert!(remove_account(&state, "personal"));
+        assert!(!remove_account(&state, "personal"));
+
+        let accounts = get_accounts(&state);
+
+        assert_eq!(accounts.keys().collect::<Vec<_>>(), vec!["corporate"]);
+    }
+
+    #[test]
+    fn test_merge_history() {
+        let history = merge_history(
+            vec![
+                (
+                    "corporate".to_string(),
+                    Ok(vec![record("09/16", -208), record("09/14", -210)]),
+                ),
+                ("personal".to_string(), Ok(vec![record("09/15", 1000)])),
+                ("old".to_string(), Err(AccountError::SessionExpired)),
+            ],
+            NaiveDate::from_ymd_opt(2023, 9, 20).unwrap(),
+        );
+
+        let dates = history
+            .records
+            .iter()
+            .map(|record| (record.account.as_str(), record.record.date.as_str()))
+            .collect::<Vec<_>>();
+
+        assert_eq!(
+            dates,
+            vec![
+                ("corporate", "09/16"),
+                ("personal", "09/15"),
+                ("corporate", "09/14")
+            ]
+        );
+        assert_eq!(history.errors.len(), 1);
+        assert_eq!(history.errors[0].code, "session_expired");
+
+        let json = serde_json::to_value(&history.records[0]).unwrap();
+
+        assert_eq!(json["account"], "corporate");
+        assert_eq!(json["amount"], -208);
+    }
+
+    #[test]
+    fn test_merge_history_across_years() {
+        let history = merge_history(
+            vec![
+                ("corporate".to_string(), Ok(vec![record("12/28", -208)])),
+                ("personal".to_string(), Ok(vec![record("01/05", -170)])),
+            ],
+            NaiveDate::from_ymd_opt(2024, 1, 10).unwrap(),
+        );
+
+        // 12/28は前年なので01/05より古い
+        assert_eq!(history.records[0].record.date, "01/05");
+        assert_eq!(history.records[1].record.date, "12/28");
+    }
+}
@@ -1,76 +1,191 @@
 use axum::Form;
-use axum::{extract::State, Json};
-use mobilesuica_sheet_app_server::HttpClient::{
-    get_client, get_cookies, MobilesuicaCookies, BASE_URL,
+use axum::{
+    extract::State,
+    response::{IntoResponse, Response},
+    Json,
 };
-use reqwest::header::{HeaderValue, CONTENT_TYPE};
+use mobilesuica_sheet_app_server::HttpClient::{get_client, MobilesuicaCookies};
 use serde::{Deserialize, Serialize};
+use std::sync::Arc;
 
+use crate::controllers::accounts::{normalize_label, save_account};
+use crate::controllers::status::maintenance_response;
 use crate::AppState;
-use mobilesuica_sheet_app_server::{HtmlDocument, MobilesuicaFormParams};
+use mobilesuica_sheet_app_server::{
+    get_captcha_solver, get_credentials_error_message, get_credentials_provider, CaptchaSolver,
+    Credentials, CredentialsProvider, Maintenance, MobilesuicaFormParams,
+    MobilesuicaLogin::{download_captcha, fetch_login_page, login, LoginError},
+};
+
+// 自動ログインでキャプチャを取り直す回数
+const AUTO_LOGIN_ATTEMPTS: usize = 5;
 
+// email, passwordが空の場合は設定されたログイン情報の取得先から読む
 #[derive(Deserialize, Debug)]
 pub struct Payload {
+    #[serde(default)]
     email: String,
+    #[serde(default)]
     password: String,
+    // 空の場合はソルバーが読み取った文字列を使う
+    #[serde(default)]
     captcha: String,
+    // 複数のアカウントを使い分けるためのラベル。空の場合はdefault
+    #[serde(default)]
+    label: String,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct AutoPayload {
+    #[serde(default)]
+    email: String,
+    #[serde(default)]
+    password: String,
+    #[serde(default)]
+    label: String,
 }
 
 #[derive(Debug, PartialEq)]
 enum AuthError {
     SessionNotFound,
     RequestFailed,
-    LoginFailed,
+    InvalidCaptcha,
+    InvalidCredentials,
+    AccountLocked,
+    SolverUnavailable,
+    CaptchaUnsolved,
+    CredentialsUnavailable,
+    InvalidLabel,
+    Maintenance(Maintenance),
+    Unknown,
 }
 
-fn get_auth_error_message(error: AuthError) -> String {
+fn get_auth_error_message(error: &AuthError) -> String {
     match error {
         AuthError::SessionNotFound => {
             "ログイン情報が取得できません。キャプチャ画像を再取得してください。"
         }
-        AuthError::LoginFailed => "ログインに失敗しました。",
         AuthError::RequestFailed => "ログインリクエストに失敗しました。",
+        AuthError::InvalidCaptcha => {
+            "画像の文字が正しくありません。キャプチャ画像を再取得してください。"
+        }
+        AuthError::InvalidCredentials => "メールアドレスまたはパスワードが正しくありません。",
+        AuthError::AccountLocked => "アカウントがロックされています。",
+        AuthError::SolverUnavailable => {
+            "キャプチャを自動で読み取る見本がありません。一度手入力でログインしてください。"
+        }
+        AuthError::CaptchaUnsolved => "キャプチャ画像を読み取れませんでした。",
+        AuthError::CredentialsUnavailable => "メールアドレスとパスワードを入力してください。",
+        AuthError::InvalidLabel => "ラベルは32文字以内で、/を含めないでください。",
+        AuthError::Maintenance(_) => "モバイルSuicaはメンテナンス中です。",
+        AuthError::Unknown => "ログインに失敗しました。",
     }
     .to_string()
 }
 
-async fn login(
-    client: &reqwest::Client,
-    action_url: String,
-    form_params: &MobilesuicaFormParams,
-) -> Result<(bool, MobilesuicaCookies), reqwest::Error> {
-    let url = format!("{}{}", BASE_URL, action_url);
-
-    let form_body = form_params.serialize_into_sjis();
-
-    let response = client
-        .post(url)
-        .header(
-            CONTENT_TYPE,
-            HeaderValue::from_static("application/x-www-form-urlencoded"),
-        )
-        .body(form_body)
-        .send()
-        .await?;
-
-    let cookies = get_cookies(&response);
-    let html = response.text_with_charset("utf-8").await?;
-
-    let title = get_title(&html);
-
-    match title.as_str() {
-        "JR東日本：モバイルSuica＞会員メニュー" => Ok((true, cookies)),
-        _ => Ok((false, cookies)),
+fn get_auth_error_code(error: &AuthError) -> String {
+    match error {
+        AuthError::SessionNotFound => "session_not_found",
+        AuthError::RequestFailed => "request_failed",
+        AuthError::InvalidCaptcha => "invalid_captcha",
+        AuthError::InvalidCredentials => "invalid_credentials",
+        AuthError::AccountLocked => "account_locked",
+        AuthError::SolverUnavailable => "solver_unavailable",
+        AuthError::CaptchaUnsolved => "captcha_unsolved",
+        AuthError::CredentialsUnavailable => "credentials_unavailable",
+        AuthError::InvalidLabel => "invalid_label",
+        AuthError::Maintenance(_) => "maintenance",
+        AuthError::Unknown => "unknown",
+    }
+    .to_string()
+}
+
+impl From<LoginError> for AuthError {
+    fn from(error: LoginError) -> Self {
+        match error {
+            LoginError::RequestFailed => AuthError::RequestFailed,
+            LoginError::InvalidCaptcha => AuthError::InvalidCaptcha,
+            LoginError::InvalidCredentials => AuthError::InvalidCredentials,
+            LoginError::AccountLocked => AuthError::AccountLocked,
+            LoginError::Maintenance(maintenance) => AuthError::Maintenance(maintenance),
+            LoginError::Unknown => AuthError::Unknown,
+        }
     }
 }
 
-fn get_title(html: &str) -> String {
-    let document = HtmlDocument::new(html);
+fn entered_credentials(email: &str, password: &str) -> Option<Credentials> {
+    match email.is_empty() || password.is_empty() {
+        true => None,
+        false => Some(Credentials {
+            email: email.to_string(),
+            password: password.to_string(),
+        }),
+    }
+}
 
-    match document.query_selector("title") {
-        Some(element) => element.text().collect::<String>(),
-        None => "".to_string(),
+// 入力されたログイン情報がなければ取得先から読む。/auth/autoでのみ使う
+// Secret ServiceはD-Busを同期的に呼ぶので、非同期のハンドラーを止めないように別スレッドで読む
+async fn resolve_credentials(
+    email: &str,
+    password: &str,
+    provider: Option<&'static dyn CredentialsProvider>,
+) -> Result<Credentials, AuthError> {
+    if let Some(credentials) = entered_credentials(email, password) {
+        return Ok(credentials);
     }
+
+    let provider = provider.ok_or(AuthError::CredentialsUnavailable)?;
+
+    let result = tokio::task::spawn_blocking(move || provider.credentials())
+        .await
+        .map_err(|_| AuthError::CredentialsUnavailable)?;
+
+    result.map_err(|e| {
+        tracing::warn!(
+            "credentials provider {}: {}",
+            provider.name(),
+            get_credentials_error_message(&e)
+        );
+        AuthError::CredentialsUnavailable
+    })
+}
+
+// キャプチャをソルバーで読み取ってログインする。読み取れないか間違えた場合は取り直す
+async fn auto_login(
+    client: &reqwest::Client,
+    solver: &dyn CaptchaSolver,
+    credentials: &Credentials,
+) -> Result<(), AuthError> {
+    if solver.is_manual() {
+        return Err(AuthError::SolverUnavailable);
+    }
+
+    let mut last_error = AuthError::CaptchaUnsolved;
+
+    for _ in 0..AUTO_LOGIN_ATTEMPTS {
+        let login_page = fetch_login_page(client).await?;
+
+        let captcha_image = download_captcha(client, &login_page.captcha_url).await?;
+
+        let Some(captcha) = solver.solve(&captcha_image) else {
+            last_error = AuthError::CaptchaUnsolved;
+            continue;
+        };
+
+        let mut mobilesuica_form_params = login_page.mobilesuica_form_params;
+
+        mobilesuica_form_params
+            .set_mail_address(&credentials.email)
+            .set_password(&credentials.password)
+            .set_captcha(&captcha);
+
+        match login(client, &login_page.action_url, &mobilesuica_form_params).await {
+            Err(LoginError::InvalidCaptcha) => last_error = AuthError::InvalidCaptcha,
+            result => return result.map_err(AuthError::from),
+        }
+    }
+
+    Err(last_error)
 }
 
 fn get_session_items(
@@ -93,8 +208,43 @@ fn get_session_items(
     Ok((cookies, mobilesuica_form_params, action_url))
 }
 
-fn create_auth_response(success: bool, message: String) -> AuthMobilesuica {
-    AuthMobilesuica::new(success, AuthMobilesuicaResult { success, message })
+fn get_captcha_answer(state: &AppState) -> Option<String> {
+    let session = state.session.lock().unwrap();
+
+    session.get::<Option<String>>("captcha_answer").flatten()
+}
+
+// 利用者が入力した文字でログインできたキャプチャをソルバーの見本にする
+fn remember_captcha(state: &AppState, answer: &str) {
+    let captcha_image = {
+        let session = state.session.lock().unwrap();
+
+        session.get::<Vec<u8>>("captcha_image")
+    };
+
+    if let Some(captcha_image) = captcha_image {
+        get_captcha_solver().remember(&captcha_image, answer);
+    }
+}
+
+fn create_auth_response(result: Result<(), AuthError>) -> AuthMobilesuica {
+    let (success, code, message) = match result {
+        Ok(()) => (true, None, "ログイン成功".to_string()),
+        Err(e) => (
+            false,
+            Some(get_auth_error_code(&e)),
+            get_auth_error_message(&e),
+        ),
+    };
+
+    AuthMobilesuica::new(
+        success,
+        AuthMobilesuicaResult {
+            success,
+            code,
+            message,
+        },
+    )
 }
 
 #[derive(Serialize, Debug)]
@@ -106,6 +256,8 @@ pub struct AuthMobilesuica {
 #[derive(Serialize, Debug)]
 struct AuthMobilesuicaResult {
     success: bool,
+    // 失敗時の種類（invalid_captcha, invalid_credentials, account_locked, maintenance など）
+    code: Option<String>,
     message: String,
 }
 
@@ -115,88 +267,187 @@ impl AuthMobilesuica {
     }
 }
 
-pub async fn handler(
-    State(state): State<AppState>,
-    payload: Form<Payload>,
-) -> Json<AuthMobilesuica> {
+pub async fn handler(State(state): State<AppState>, payload: Form<Payload>) -> Response {
+    let Some(label) = normalize_label(&payload.label) else {
+        return Json(create_auth_response(Err(AuthError::InvalidLabel))).into_response();
+    };
+
+    // 利用者が入力したログイン情報のみを使う。サーバーに設定したログイン情報は使わない
+    let Some(credentials) = entered_credentials(&payload.email, &payload.password) else {
+        return Json(create_auth_response(Err(AuthError::CredentialsUnavailable))).into_response();
+    };
+
     let (cookies, mut mobilesuica_form_params, action_url) = match get_session_items(&state) {
         Ok(items) => items,
-        Err(e) => {
-            let message = get_auth_error_message(e);
-            return Json(create_auth_response(false, message));
-        }
+        Err(e) => return Json(create_auth_response(Err(e))).into_response(),
+    };
+
+    let captcha = match payload.captcha.is_empty() {
+        true => get_captcha_answer(&state).unwrap_or_default(),
+        false => payload.captcha.clone(),
     };
 
     // 入力値セット
     mobilesuica_form_params
-        .set_mail_address(&payload.email)
-        .set_password(&payload.password)
-        .set_captcha(&payload.captcha);
+        .set_mail_address(&credentials.email)
+        .set_password(&credentials.password)
+        .set_captcha(&captcha);
+
+    let cookies = Arc::new(cookies);
 
-    let client = match get_client(cookies).await {
+    let client = match get_client(cookies.clone()).await {
         Ok(client) => client,
-        Err(_) => {
-            let message = get_auth_error_message(AuthError::RequestFailed);
-            return Json(create_auth_response(false, message));
-        }
+        Err(_) => return Json(create_auth_response(Err(AuthError::RequestFailed))).into_response(),
     };
 
-    let result: Result<(bool, MobilesuicaCookies), AuthError> =
-        login(&client, action_url, &mobilesuica_form_params)
-            .await
-            .map_err(|_| AuthError::RequestFailed);
+    let result = login(&client, &action_url, &mobilesuica_form_params)
+        .await
+        .map_err(AuthError::from);
 
-    let (success, message) = match result {
-        Ok((success, auth_cookies)) => {
-            if success {
-                let mut session = state.session.lock().unwrap();
-                session.set("auth_cookies", auth_cookies);
-            }
+    if result.is_ok() && !payload.captcha.is_empty() {
+        remember_captcha(&state, &payload.captcha);
+    }
 
-            let message = match success {
-                true => "ログイン成功".to_string(),
-                false => get_auth_error_message(AuthError::LoginFailed),
-            };
+    finish_login(&state, &label, &credentials.email, &cookies, result)
+}
 
-            (success, message)
-        }
-        Err(e) => {
-            let message = get_auth_error_message(e);
-            return Json(create_auth_response(false, message));
-        }
+fn finish_login(
+    state: &AppState,
+    label: &str,
+    email: &str,
+    cookies: &MobilesuicaCookies,
+    result: Result<(), AuthError>,
+) -> Response {
+    if result.is_ok() {
+        save_account(state, label, email, cookies);
+    }
+
+    if let Err(AuthError::Maintenance(maintenance)) = result {
+        return maintenance_response(maintenance);
+    }
+
+    let auth_mobilesuica = create_auth_response(result);
+
+    Json(auth_mobilesuica).into_response()
+}
+
+// 夜間の同期など人がキャプチャを入力できない場合のログイン
+// 見本は同梱していないため、手入力のログインで見本が集まるまではSolverUnavailableになる
+pub async fn auto_handler(State(state): State<AppState>, payload: Form<AutoPayload>) -> Response {
+    let Some(label) = normalize_label(&payload.label) else {
+        return Json(create_auth_response(Err(AuthError::InvalidLabel))).into_response();
     };
 
-    let auth_mobilesuica = create_auth_response(success, message);
+    let credentials = match resolve_credentials(
+        &payload.email,
+        &payload.password,
+        get_credentials_provider(),
+    )
+    .await
+    {
+        Ok(credentials) => credentials,
+        Err(e) => return Json(create_auth_response(Err(e))).into_response(),
+    };
 
-    Json(auth_mobilesuica)
+    let cookies = Arc::new(MobilesuicaCookies::default());
+
+    let client = match get_client(cookies.clone()).await {
+        Ok(client) => client,
+        Err(_) => return Json(create_auth_response(Err(AuthError::RequestFailed))).into_response(),
+    };
+
+    let result = auto_login(&client, get_captcha_solver(), &credentials).await;
+
+    finish_login(&state, &label, &credentials.email, &cookies, result)
 }
 
 #[cfg(test)]
 mod test {
 
-    use std::{
-        collections::HashMap,
-        sync::{Arc, Mutex},
-    };
+    use std::sync::Mutex;
 
     use super::*;
-    use mobilesuica_sheet_app_server::Session;
+    use mobilesuica_sheet_app_server::{
+        HttpClient::BASE_URL, ManualSolver, MemorySecretStore, SecretItem, SecretServiceProvider,
+        Session,
+    };
+    use std::collections::HashMap;
+    use url::Url;
+
+    #[test]
+    fn test_from_login_error() {
+        assert_eq!(
+            AuthError::from(LoginError::InvalidCaptcha),
+            AuthError::InvalidCaptcha
+        );
+        assert_eq!(
+            AuthError::from(LoginError::RequestFailed),
+            AuthError::RequestFailed
+        );
+    }
+
+    #[test]
+    fn test_create_auth_response() {
+        let success = serde_json::to_value(create_auth_response(Ok(()))).unwrap();
+
+        assert_eq!(success["ok"], true);
+        assert_eq!(success["result"]["code"], serde_json::Value::Null);
+
+        let failure =
+            serde_json::to_value(create_auth_response(Err(AuthError::InvalidCaptcha))).unwrap();
+
+        assert_eq!(failure["ok"], false);
+        assert_eq!(failure["result"]["success"], false);
+        assert_eq!(failure["result"]["code"], "invalid_captcha");
+    }
+
+    #[tokio::test]
+    async fn test_auto_login_manual_solver() {
+        let client = reqwest::Client::new();
+        let credentials = Credentials {
+            email: "test@example.com".to_string(),
+            password: "password".to_string(),
+        };
+
+        assert_eq!(
+            auto_login(&client, &ManualSolver, &credentials).await,
+            Err(AuthError::SolverUnavailable)
+        );
+    }
+
+    #[tokio::test]
+    async fn test_resolve_credentials() {
+        let provider = SecretServiceProvider::new(MemorySecretStore::new(vec![SecretItem {
+            attributes: HashMap::from([
+                ("service".to_string(), "mobilesuica".to_string()),
+                ("username".to_string(), "stored@example.com".to_string()),
+            ]),
+            secret: b"stored".to_vec(),
+        }]));
+        let provider: &'static dyn CredentialsProvider = Box::leak(Box::new(provider));
+
+        // 入力があればそちらを優先する
+        let credentials = resolve_credentials("test@example.com", "password", Some(provider))
+            .await
+            .unwrap();
+        assert_eq!(credentials.email, "test@example.com");
+
+        let credentials = resolve_credentials("", "", Some(provider)).await.unwrap();
+        assert_eq!(credentials.email, "stored@example.com");
+        assert_eq!(credentials.password, "stored");
+
+        assert_eq!(
+            resolve_credentials("", "", None).await,
+            Err(AuthError::CredentialsUnavailable)
+        );
+    }
 
     #[test]
-    fn test_get_title() {
-        let html = r#"
-        <html>
-            <head>
-                <title>test</title>
-            </head>
-            <body>
-            </body>
-        </html>
-        "#;
-
-        let title = get_title(html);
-
-        assert_eq!(title, "test".to_string());
+    fn test_entered_credentials() {
+        assert!(entered_credentials("test@example.com", "password").is_some());
+        // 手入力のログインでは、空欄をサーバーのログイン情報で補わない
+        assert_eq!(entered_credentials("", ""), None);
+        assert_eq!(entered_credentials("test@example.com", ""), None);
     }
 
     fn get_state(session: &Session) -> AppState {
@@ -210,9 +461,9 @@ mod test {
         let session_id = Session::new();
         let mut session = Session::get_session(&session_id).unwrap();
 
-        let mut cookies: MobilesuicaCookies = HashMap::new();
+        let cookies = MobilesuicaCookies::default();
 
-        cookies.insert("test".to_string(), "test_cookie".to_string());
+        cookies.add_cookie_str("test=test_cookie", &Url::parse(BASE_URL).unwrap());
 
         let mut mobilesuica_form_params = MobilesuicaFormParams::default();
         mobilesuica_form_params.set_mail_address("test@example.com");
@@ -1,122 +1,599 @@
-use std::collections::HashMap;
+use std::sync::Arc;
 
-use axum::extract::State;
-use axum::{body::Body, response::Response};
-use mobilesuica_sheet_app_server::HtmlDocument;
+use axum::extract::{Query, State};
+use axum::{
+    body::Body,
+    response::{IntoResponse, Response},
+    Json,
+};
+use base64::{engine::general_purpose::STANDARD, Engine as _};
+use chrono::{TimeZone, Utc};
+use mobilesuica_sheet_app_server::get_captcha_solver;
+use mobilesuica_sheet_app_server::{convert_captcha_image, CaptchaImageOptions, ResizeFilter};
+use mobilesuica_sheet_app_server::{
+    detect_maintenance, Maintenance,
+    MobilesuicaLogin::{self as mobilesuica_login, fetch_login_page, LoginError, LoginPage},
+};
+use mobilesuica_sheet_app_server::{image_info, sniff_mime_type};
 use mobilesuica_sheet_app_server::{
-    HttpClient::{get_client, get_cookies, MobilesuicaCookies, BASE_URL},
-    MobilesuicaFormParams,
+    HttpClient::{get_client, get_text, MobilesuicaCookies, BASE_URL},
+    WebForm,
 };
+use reqwest::header::CONTENT_TYPE;
 use reqwest::StatusCode;
+use serde::{Deserialize, Serialize};
 
+use crate::controllers::status::maintenance_response;
 use crate::store::AppState;
 
 #[derive(Debug, PartialEq)]
 enum CaptchaError {
     FetchFailed,
     DownloadFailed,
+    SessionNotFound,
+    RefreshFailed,
+    InvalidImageOptions,
+    ConvertFailed,
+    Maintenance(Maintenance),
 }
 
 fn get_captcha_error_message(error: CaptchaError) -> String {
     match error {
         CaptchaError::FetchFailed => "キャプチャ画像の取得に失敗しました。",
         CaptchaError::DownloadFailed => "キャプチャ画像のダウンロードに失敗しました。",
+        CaptchaError::SessionNotFound => {
+            "ログイン画面の情報が取得できません。キャプチャ画像を再取得してください。"
+        }
+        CaptchaError::RefreshFailed => "キャプチャ画像の更新に失敗しました。",
+        CaptchaError::InvalidImageOptions => "画像の変換オプションが不正です。",
+        CaptchaError::ConvertFailed => "キャプチャ画像の変換に失敗しました。",
+        CaptchaError::Maintenance(_) => "モバイルSuicaはメンテナンス中です。",
     }
     .to_string()
 }
 
-fn get_captcha_imageurl(html: &str) -> String {
-    let document = HtmlDocument::new(html);
-
-    match document.query_selector(".igc_TrendyCaptchaImage") {
-        Some(element) => element.value().attr("src").unwrap_or(""),
-        None => "",
+fn get_captcha_error_code(error: &CaptchaError) -> String {
+    match error {
+        CaptchaError::FetchFailed => "fetch_failed",
+        CaptchaError::DownloadFailed => "download_failed",
+        CaptchaError::SessionNotFound => "session_not_found",
+        CaptchaError::RefreshFailed => "refresh_failed",
+        CaptchaError::InvalidImageOptions => "invalid_image_options",
+        CaptchaError::ConvertFailed => "convert_failed",
+        CaptchaError::Maintenance(_) => "maintenance",
     }
     .to_string()
 }
 
-fn get_action_url(html: &str) -> String {
-    let document = HtmlDocument::new(html);
+#[derive(Deserialize, Debug, Default)]
+pub struct CaptchaQuery {
+    // jsonの場合はdata URLにしてJSONで返す。未指定は画像をそのまま返す
+    format: Option<String>,
+    // 以下のいずれかを指定するとPNGに変換して返す
+    // 拡大倍率（1〜8）
+    scale: Option<u32>,
+    // nearest（既定）またはlanczos
+    filter: Option<String>,
+    // コントラストの増分（-100〜100）
+    contrast: Option<f32>,
+}
 
-    match document.get_element_by_id("form1") {
-        Some(element) => element.value().attr("action").unwrap_or(""),
-        None => "",
+impl CaptchaQuery {
+    fn is_json(&self) -> bool {
+        self.format.as_deref() == Some("json")
+    }
+
+    fn image_options(&self) -> Result<Option<CaptchaImageOptions>, CaptchaError> {
+        if self.scale.is_none() && self.filter.is_none() && self.contrast.is_none() {
+            return Ok(None);
+        }
+
+        let filter = match self.filter.as_deref() {
+            Some(filter) => ResizeFilter::parse(filter).ok_or(CaptchaError::InvalidImageOptions)?,
+            None => ResizeFilter::default(),
+        };
+
+        let options = CaptchaImageOptions {
+            scale: self.scale.unwrap_or(1),
+            filter,
+            contrast: self.contrast.unwrap_or(0.0),
+        };
+
+        match options.is_valid() {
+            true => Ok(Some(options)),
+            false => Err(CaptchaError::InvalidImageOptions),
+        }
+    }
+
+    fn convert(&self, captcha_image: Vec<u8>) -> Result<Vec<u8>, CaptchaError> {
+        match self.image_options()? {
+            Some(options) => convert_captcha_image(&captcha_image, &options)
+                .map_err(|_| CaptchaError::ConvertFailed),
+            None => Ok(captcha_image),
+        }
     }
-    .to_string()
 }
 
-async fn fetch_mobilesuica(
+#[derive(Serialize, Debug)]
+pub struct CaptchaJson {
+    ok: bool,
+    result: CaptchaJsonResult,
+}
+
+#[derive(Serialize, Debug)]
+#[serde(untagged)]
+enum CaptchaJsonResult {
+    Image {
+        data_url: String,
+        mime_type: String,
+        width: Option<u32>,
+        height: Option<u32>,
+        session_expired_at: Option<String>,
+        // ソルバーが読み取れた場合の文字列。captchaを空で送るとこれを使う
+        answer: Option<String>,
+    },
+    Error {
+        code: String,
+        message: String,
+    },
+}
+
+fn save_login_page(
+    login_page: LoginPage,
+    state: &AppState,
+    cookies: &MobilesuicaCookies,
+    captcha_image: &[u8],
+    captcha_answer: Option<String>,
+) {
+    let mut session = state.session.lock().unwrap();
+
+    session.set("action_url", login_page.action_url);
+    session.set(
+        "mobilesuica_form_params",
+        login_page.mobilesuica_form_params,
+    );
+    session.set("login_form", login_page.login_form);
+    session.set("cookies", cookies);
+    // ログインできたら入力された文字と合わせてソルバーに覚えさせる
+    session.set("captcha_image", captcha_image);
+    session.set("captcha_answer", captcha_answer);
+}
+
+async fn fetch_mobilesuica(client: &reqwest::Client) -> Result<LoginPage, CaptchaError> {
+    fetch_login_page(client).await.map_err(|e| match e {
+        LoginError::Maintenance(maintenance) => CaptchaError::Maintenance(maintenance),
+        _ => CaptchaError::FetchFailed,
+    })
+}
+
+// キャプチャのコントロール。画像右側の更新ボタン（RefreshButtonLink）はこのコントロールへのポストバックになる
+const CAPTCHA_CONTROL: &str = "WebCaptcha1";
+
+// 保存しておいたログインフォーム（VIEWSTATE）のままキャプチャのコントロールへポストバックし、画像だけを再発行させる
+// ログインボタンは押さないので、入力途中のメールアドレスやパスワードは送らない
+fn build_refresh_request(
     client: &reqwest::Client,
-) -> Result<(MobilesuicaFormParams, MobilesuicaCookies, String, String), reqwest::Error> {
-    let response = client.get(BASE_URL).send().await?;
-    let cookies = get_cookies(&response);
+    login_form: &WebForm,
+) -> Result<reqwest::Request, reqwest::Error> {
+    let mut form = login_form.clone();
+    form.set("MailAddress", "")
+        .set("Password", "")
+        .postback(CAPTCHA_CONTROL, "");
+
+    client
+        .post(format!("{}{}", BASE_URL, form.action()))
+        .header(CONTENT_TYPE, "application/x-www-form-urlencoded")
+        .body(form.serialize_into_sjis())
+        .build()
+}
 
-    let html = response.text_with_charset("utf-8").await?;
-    let mobilesuica_form_params = MobilesuicaFormParams::new(&html);
+// ポストバックの結果が同じログイン画面（同じpguid）で、キャプチャ画像がある場合のみ保存するログイン画面とする
+fn parse_refreshed_page(html: &str, login_form: &WebForm) -> Result<LoginPage, CaptchaError> {
+    if let Some(maintenance) = detect_maintenance(html) {
+        return Err(CaptchaError::Maintenance(maintenance));
+    }
 
-    let captcha_url = get_captcha_imageurl(&html);
-    let action_url = get_action_url(&html);
+    let login_page = LoginPage::new(html);
 
-    Ok((mobilesuica_form_params, cookies, captcha_url, action_url))
+    match login_page.login_form.is_some()
+        && !login_page.captcha_url.is_empty()
+        && login_page.action_url == login_form.action()
+    {
+        true => Ok(login_page),
+        false => Err(CaptchaError::RefreshFailed),
+    }
+}
+
+async fn refresh_mobilesuica(
+    client: &reqwest::Client,
+    login_form: &WebForm,
+) -> Result<LoginPage, CaptchaError> {
+    let html = async {
+        let request = build_refresh_request(client, login_form)?;
+        let response = client.execute(request).await?;
+
+        get_text(response).await
+    }
+    .await
+    .map_err(|_| CaptchaError::RefreshFailed)?;
+
+    parse_refreshed_page(&html, login_form)
 }
 
 async fn download_captcha(
     client: &reqwest::Client,
     captcha_url: &str,
-) -> Result<Vec<u8>, reqwest::Error> {
-    let url = format!("{}{}", BASE_URL, captcha_url);
+) -> Result<Vec<u8>, CaptchaError> {
+    mobilesuica_login::download_captcha(client, captcha_url)
+        .await
+        .map_err(|_| CaptchaError::DownloadFailed)
+}
+
+fn get_refresh_items(state: &AppState) -> Result<(MobilesuicaCookies, WebForm), CaptchaError> {
+    let session = state.session.lock().unwrap();
 
-    let response = client.get(url).send().await?;
+    let cookies = session
+        .get::<MobilesuicaCookies>("cookies")
+        .ok_or(CaptchaError::SessionNotFound)?;
 
-    let captcha_image = response.bytes().await?;
+    let login_form = session
+        .get::<Option<WebForm>>("login_form")
+        .flatten()
+        .ok_or(CaptchaError::SessionNotFound)?;
 
-    Ok(captcha_image.to_vec())
+    Ok((cookies, login_form))
 }
 
-pub async fn handler(State(state): State<AppState>) -> Response {
-    let cookies_default: MobilesuicaCookies = HashMap::new();
+fn create_captcha_json(
+    captcha_image: &[u8],
+    session_expired_at: u64,
+    answer: Option<String>,
+) -> CaptchaJson {
+    let info = image_info(captcha_image);
+    let mime_type = sniff_mime_type(captcha_image).unwrap_or("application/octet-stream");
 
-    let client = match get_client(cookies_default).await {
-        Ok(client) => client,
-        Err(_) => {
-            return Response::builder()
-                .status(StatusCode::INTERNAL_SERVER_ERROR)
-                .body(Body::from("Internal Server Error"))
-                .unwrap();
+    CaptchaJson {
+        ok: true,
+        result: CaptchaJsonResult::Image {
+            data_url: format!(
+                "data:{};base64,{}",
+                mime_type,
+                STANDARD.encode(captcha_image)
+            ),
+            mime_type: mime_type.to_string(),
+            width: info.as_ref().map(|info| info.width),
+            height: info.as_ref().map(|info| info.height),
+            session_expired_at: Utc
+                .timestamp_opt(session_expired_at as i64, 0)
+                .single()
+                .map(|expired_at| expired_at.to_rfc3339()),
+            answer,
+        },
+    }
+}
+
+fn create_captcha_json_response(
+    result: Result<Vec<u8>, CaptchaError>,
+    session_expired_at: u64,
+    answer: Option<String>,
+) -> Response {
+    let (status, error) = match result {
+        Ok(captcha_image) => {
+            let captcha_json = create_captcha_json(&captcha_image, session_expired_at, answer);
+
+            return Json(captcha_json).into_response();
+        }
+        Err(CaptchaError::Maintenance(maintenance)) => return maintenance_response(maintenance),
+        Err(e @ (CaptchaError::SessionNotFound | CaptchaError::InvalidImageOptions)) => {
+            (StatusCode::BAD_REQUEST, e)
         }
+        Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, e),
     };
 
-    let result: Result<Vec<u8>, CaptchaError> = (|| async {
-        let (mobilesuica_form_params, cookies, captcha_url, action_url) =
-            fetch_mobilesuica(&client)
-                .await
-                .map_err(|_| CaptchaError::FetchFailed)?;
+    let body = Json(CaptchaJson {
+        ok: false,
+        result: CaptchaJsonResult::Error {
+            code: get_captcha_error_code(&error),
+            message: get_captcha_error_message(error),
+        },
+    });
+
+    (status, body).into_response()
+}
+
+fn create_captcha_response(result: Result<Vec<u8>, CaptchaError>) -> Response {
+    match result {
+        Ok(captcha_image) => Response::builder()
+            .status(StatusCode::OK)
+            .header(
+                "Content-Type",
+                sniff_mime_type(&captcha_image).unwrap_or("application/octet-stream"),
+            )
+            .body(Body::from(captcha_image))
+            .unwrap(),
+
+        Err(CaptchaError::Maintenance(maintenance)) => maintenance_response(maintenance),
 
-        let captcha_image = download_captcha(&client, &captcha_url)
-            .await
-            .map_err(|_| CaptchaError::DownloadFailed)?;
+        Err(e @ (CaptchaError::SessionNotFound | CaptchaError::InvalidImageOptions)) => {
+            Response::builder()
+                .status(StatusCode::BAD_REQUEST)
+                .body(Body::from(get_captcha_error_message(e)))
+                .unwrap()
+        }
 
-        {
-            let mut session = state.session.lock().unwrap();
+        Err(e) => Response::builder()
+            .status(StatusCode::INTERNAL_SERVER_ERROR)
+            .body(Body::from(get_captcha_error_message(e)))
+            .unwrap(),
+    }
+}
+
+fn internal_server_error() -> Response {
+    Response::builder()
+        .status(StatusCode::INTERNAL_SERVER_ERROR)
+        .body(Body::from("Internal Server Error"))
+        .unwrap()
+}
 
-            session.set("action_url", action_url);
-            session.set("mobilesuica_form_params", mobilesuica_form_params);
-            session.set("cookies", cookies);
+fn respond(
+    state: &AppState,
+    query: &CaptchaQuery,
+    result: Result<Vec<u8>, CaptchaError>,
+) -> Response {
+    let result = result.and_then(|captcha_image| query.convert(captcha_image));
+
+    match query.is_json() {
+        true => {
+            let session = state.session.lock().unwrap();
+            let answer = session.get::<Option<String>>("captcha_answer").flatten();
+
+            create_captcha_json_response(result, session.expired_at(), answer)
         }
+        false => create_captcha_response(result),
+    }
+}
+
+pub async fn handler(State(state): State<AppState>, Query(query): Query<CaptchaQuery>) -> Response {
+    // 変換オプションが不正な場合はログイン画面を取りに行かない
+    if let Err(e) = query.image_options() {
+        return respond(&state, &query, Err(e));
+    }
+
+    let cookies = Arc::new(MobilesuicaCookies::default());
+
+    let client = match get_client(cookies.clone()).await {
+        Ok(client) => client,
+        Err(_) => return internal_server_error(),
+    };
+
+    let result: Result<Vec<u8>, CaptchaError> = (|| async {
+        let login_page = fetch_mobilesuica(&client).await?;
+
+        let captcha_image = download_captcha(&client, &login_page.captcha_url).await?;
+
+        // 読み取れなければ利用者が入力する
+        let captcha_answer = get_captcha_solver().solve(&captcha_image);
+
+        save_login_page(login_page, &state, &cookies, &captcha_image, captcha_answer);
 
         Ok(captcha_image)
     })()
     .await;
 
-    match result {
-        Ok(captcha_image) => Response::builder()
-            .status(StatusCode::OK)
-            .header("Content-Type", "image/gif")
-            .body(Body::from(captcha_image)),
+    respond(&state, &query, result)
+}
 
-        Err(e) => Response::builder()
-            .status(StatusCode::INTERNAL_SERVER_ERROR)
-            .body(Body::from(get_captcha_error_message(e))),
+// ログインの途中状態（Cookie, VIEWSTATE）を保ったままキャプチャ画像を取り直す
+pub async fn refresh_handler(
+    State(state): State<AppState>,
+    Query(query): Query<CaptchaQuery>,
+) -> Response {
+    if let Err(e) = query.image_options() {
+        return respond(&state, &query, Err(e));
+    }
+
+    let (cookies, login_form) = match get_refresh_items(&state) {
+        Ok(items) => items,
+        Err(e) => return respond(&state, &query, Err(e)),
+    };
+
+    let cookies = Arc::new(cookies);
+
+    let client = match get_client(cookies.clone()).await {
+        Ok(client) => client,
+        Err(_) => return internal_server_error(),
+    };
+
+    let result: Result<Vec<u8>, CaptchaError> = async {
+        let login_page = refresh_mobilesuica(&client, &login_form).await?;
+
+        let captcha_image = download_captcha(&client, &login_page.captcha_url).await?;
+
+        // 読み取れなければ利用者が入力する
+        let captcha_answer = get_captcha_solver().solve(&captcha_image);
+
+        save_login_page(login_page, &state, &cookies, &captcha_image, captcha_answer);
+
+        Ok(captcha_image)
+    }
+    .await;
+
+    respond(&state, &query, result)
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::*;
+    use mobilesuica_sheet_app_server::Session;
+    use std::sync::Mutex;
+
+    #[test]
+    fn test_get_refresh_items() {
+        let session_id = Session::new();
+        let session = Session::get_session(&session_id).unwrap();
+
+        let state = AppState {
+            session: Arc::new(Mutex::new(session)),
+        };
+
+        assert_eq!(
+            get_refresh_items(&state).err().unwrap(),
+            CaptchaError::SessionNotFound
+        );
+
+        let login_page = LoginPage::new(include_str!("../../test/login.html"));
+        let login_form = login_page.login_form.clone().unwrap();
+
+        save_login_page(
+            login_page,
+            &state,
+            &MobilesuicaCookies::default(),
+            &[],
+            None,
+        );
+
+        let (cookies, saved_login_form) = get_refresh_items(&state).unwrap();
+
+        assert!(cookies.is_empty());
+        assert_eq!(saved_login_form, login_form);
+    }
+
+    #[test]
+    fn test_build_refresh_request() {
+        let mut login_form = LoginPage::new(include_str!("../../test/login.html"))
+            .login_form
+            .unwrap();
+        login_form.set("MailAddress", "test@example.com");
+
+        let request = build_refresh_request(&reqwest::Client::new(), &login_form).unwrap();
+
+        assert_eq!(request.method(), reqwest::Method::POST);
+        assert_eq!(
+            request.url().as_str(),
+            "https://www.mobilesuica.com/ka/lg/LoginForm.aspx?pguid=af60fc73-558f-460c-a2b1-d830c426f307"
+        );
+
+        let body = std::str::from_utf8(request.body().unwrap().as_bytes().unwrap()).unwrap();
+        let fields = url::form_urlencoded::parse(body.as_bytes())
+            .into_owned()
+            .collect::<Vec<(String, String)>>();
+        let get = |name: &str| {
+            fields
+                .iter()
+                .find(|(key, _)| key == name)
+                .map(|(_, value)| value.as_str())
+        };
+
+        // 保存しておいたVIEWSTATEのままキャプチャのコントロールへポストバックする
+        assert_eq!(get("__EVENTTARGET"), Some("WebCaptcha1"));
+        assert_eq!(get("__VIEWSTATE"), login_form.get("__VIEWSTATE"));
+        // 入力途中のメールアドレスやパスワードは送らず、ログインボタンも押さない
+        assert_eq!(get("MailAddress"), Some(""));
+        assert_eq!(get("LOGIN"), None);
+    }
+
+    #[test]
+    fn test_parse_refreshed_page() {
+        let html = include_str!("../../test/login.html");
+        let login_form = LoginPage::new(html).login_form.unwrap();
+
+        let login_page = parse_refreshed_page(html, &login_form).unwrap();
+
+        assert!(login_page.login_form.is_some());
+
+        // ログインフォームのないページでは保存しているログイン画面を置き換えない
+        assert_eq!(
+            parse_refreshed_page(include_str!("../../test/history.html"), &login_form).err(),
+            Some(CaptchaError::RefreshFailed)
+        );
+
+        // 別のログイン画面に変わった場合も置き換えない
+        let other_page = html.replace(
+            "af60fc73-558f-460c-a2b1-d830c426f307",
+            "00000000-0000-0000-0000-000000000000",
+        );
+        assert_eq!(
+            parse_refreshed_page(&other_page, &login_form).err(),
+            Some(CaptchaError::RefreshFailed)
+        );
+    }
+
+    #[test]
+    fn test_create_captcha_json() {
+        // 論理画面サイズが175x60の透過GIF
+        let gif = b"GIF89a\xAF\x00\x3C\x00\x80\x00\x00\x00\x00\x00\xFF\xFF\xFF\x21\xF9\x04\x01\x00\x00\x00\x00\x2C\x00\x00\x00\x00\x01\x00\x01\x00\x00\x02\x02\x44\x01\x00\x3B";
+
+        let json = serde_json::to_value(create_captcha_json(
+            gif,
+            1694876400,
+            Some("AB12".to_string()),
+        ))
+        .unwrap();
+
+        assert_eq!(json["ok"], true);
+        assert_eq!(json["result"]["mime_type"], "image/gif");
+        assert_eq!(json["result"]["width"], 175);
+        assert_eq!(json["result"]["height"], 60);
+        assert_eq!(
+            json["result"]["data_url"],
+            "data:image/gif;base64,R0lGODlhrwA8AIAAAAAAAP///yH5BAEAAAAALAAAAAABAAEAAAICRAEAOw=="
+        );
+        assert_eq!(
+            json["result"]["session_expired_at"],
+            "2023-09-16T15:00:00+00:00"
+        );
+        assert_eq!(json["result"]["answer"], "AB12");
+    }
+
+    #[test]
+    fn test_captcha_query_image_options() {
+        assert_eq!(CaptchaQuery::default().image_options(), Ok(None));
+
+        let query = CaptchaQuery {
+            scale: Some(3),
+            filter: Some("lanczos".to_string()),
+            ..Default::default()
+        };
+
+        assert_eq!(
+            query.image_options(),
+            Ok(Some(CaptchaImageOptions {
+                scale: 3,
+                filter: ResizeFilter::Lanczos,
+                contrast: 0.0,
+            }))
+        );
+
+        let query = CaptchaQuery {
+            filter: Some("bicubic".to_string()),
+            ..Default::default()
+        };
+
+        assert_eq!(
+            query.image_options(),
+            Err(CaptchaError::InvalidImageOptions)
+        );
+
+        let query = CaptchaQuery {
+            scale: Some(0),
+            ..Default::default()
+        };
+
+        assert_eq!(
+            query.image_options(),
+            Err(CaptchaError::InvalidImageOptions)
+        );
+    }
+
+    #[test]
+    fn test_create_captcha_json_response_error() {
+        let response = create_captcha_json_response(Err(CaptchaError::SessionNotFound), 0, None);
+
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+
+        let response = create_captcha_json_response(Err(CaptchaError::DownloadFailed), 0, None);
+
+        assert_eq!(response.status(), StatusCode::INTERNAL_SERVER_ERROR);
     }
-    .unwrap()
 }
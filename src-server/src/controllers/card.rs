@@ -0,0 +1,381 @@
+use std::sync::Arc;
+
+use axum::{
+    extract::{Query, State},
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    Json,
+};
+use chrono::{FixedOffset, NaiveDate, Utc};
+use mobilesuica_sheet_app_server::HttpClient::{get_client, MobilesuicaCookies};
+use mobilesuica_sheet_app_server::{
+    charge_events, fetch_auto_charge_settings, fetch_card_summary, fetch_commuter_pass,
+    fetch_green_tickets, fetch_usage_history, AutoChargeError, AutoChargeSettings, CardSummary,
+    CardSummaryError, ChargeEvent, CommuterPass, CommuterPassError, GreenTicket, GreenTicketError,
+    Maintenance, UsageHistoryError,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::controllers::accounts::{
+    get_account_cookies, update_account_cookies, DEFAULT_ACCOUNT_LABEL,
+};
+use crate::controllers::status::maintenance_response;
+use crate::AppState;
+
+#[derive(Debug, PartialEq)]
+enum CardError {
+    AccountNotFound,
+    RequestFailed,
+    SessionExpired,
+    Maintenance(Maintenance),
+}
+
+fn get_card_error_message(error: &CardError) -> String {
+    match error {
+        CardError::AccountNotFound => "アカウントが見つかりません。ログインしてください。",
+        CardError::RequestFailed => "カード情報の取得に失敗しました。",
+        CardError::SessionExpired => "ログインの有効期限が切れました。再度ログインしてください。",
+        CardError::Maintenance(_) => "モバイルSuicaはメンテナンス中です。",
+    }
+    .to_string()
+}
+
+fn get_card_error_code(error: &CardError) -> String {
+    match error {
+        CardError::AccountNotFound => "account_not_found",
+        CardError::RequestFailed => "request_failed",
+        CardError::SessionExpired => "session_expired",
+        CardError::Maintenance(_) => "maintenance",
+    }
+    .to_string()
+}
+
+impl From<CardSummaryError> for CardError {
+    fn from(error: CardSummaryError) -> Self {
+        match error {
+            CardSummaryError::RequestFailed => CardError::RequestFailed,
+            CardSummaryError::SessionExpired => CardError::SessionExpired,
+            CardSummaryError::Maintenance(maintenance) => CardError::Maintenance(maintenance),
+        }
+    }
+}
+
+impl From<CommuterPassError> for CardError {
+    fn from(error: CommuterPassError) -> Self {
+        match error {
+            CommuterPassError::RequestFailed => CardError::RequestFailed,
+            CommuterPassError::SessionExpired => CardError::SessionExpired,
+            CommuterPassError::Maintenance(maintenance) => CardError::Maintenance(maintenance),
+        }
+    }
+}
+
+impl From<GreenTicketError> for CardError {
+    fn from(error: GreenTicketError) -> Self {
+        match error {
+            GreenTicketError::RequestFailed => CardError::RequestFailed,
+            GreenTicketError::SessionExpired => CardError::SessionExpired,
+            GreenTicketError::Maintenance(maintenance) => CardError::Maintenance(maintenance),
+        }
+    }
+}
+
+impl From<AutoChargeError> for CardError {
+    fn from(error: AutoChargeError) -> Self {
+        match error {
+            AutoChargeError::RequestFailed => CardError::RequestFailed,
+            AutoChargeError::SessionExpired => CardError::SessionExpired,
+            AutoChargeError::Maintenance(maintenance) => CardError::Maintenance(maintenance),
+        }
+    }
+}
+
+impl From<UsageHistoryError> for CardError {
+    fn from(error: UsageHistoryError) -> Self {
+        match error {
+            UsageHistoryError::RequestFailed => CardError::RequestFailed,
+            UsageHistoryError::SessionExpired => CardError::SessionExpired,
+            UsageHistoryError::Maintenance(maintenance) => CardError::Maintenance(maintenance),
+        }
+    }
+}
+
+#[derive(Deserialize, Debug, Default)]
+pub struct CardQuery {
+    // 省略時はラベルなしでログインしたアカウント
+    account: Option<String>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct CardJson {
+    ok: bool,
+    result: CardJsonResult,
+}
+
+#[derive(Serialize, Debug)]
+#[serde(untagged)]
+enum CardJsonResult {
+    Card {
+        account: String,
+        #[serde(flatten)]
+        summary: CardSummary,
+    },
+    CommuterPass {
+        account: String,
+        // 定期券が登録されていない場合はnull
+        commuter_pass: Option<CommuterPass>,
+        days_until_expiry: Option<i64>,
+        // 期限切れが近い場合にtrue
+        expiring: bool,
+    },
+    GreenTickets {
+        account: String,
+        green_tickets: Vec<GreenTicket>,
+    },
+    AutoCharge {
+        account: String,
+        auto_charge: AutoChargeSettings,
+        // 利用履歴のチャージを手動とオートチャージに分けたもの
+        charges: Vec<ChargeEvent>,
+    },
+    Error {
+        code: String,
+        message: String,
+    },
+}
+
+fn create_card_response(result: Result<CardJsonResult, CardError>) -> Response {
+    let (status, ok, result) = match result {
+        Ok(result) => (StatusCode::OK, true, result),
+        Err(CardError::Maintenance(maintenance)) => return maintenance_response(maintenance),
+        Err(e) => (
+            match e {
+                CardError::AccountNotFound => StatusCode::NOT_FOUND,
+                _ => StatusCode::OK,
+            },
+            false,
+            CardJsonResult::Error {
+                code: get_card_error_code(&e),
+                message: get_card_error_message(&e),
+            },
+        ),
+    };
+
+    (status, Json(CardJson { ok, result })).into_response()
+}
+
+// モバイルSuicaの日付は日本時間
+pub fn today() -> NaiveDate {
+    let jst = FixedOffset::east_opt(9 * 60 * 60).unwrap();
+
+    Utc::now().with_timezone(&jst).date_naive()
+}
+
+fn get_label(query: CardQuery) -> String {
+    query
+        .account
+        .filter(|account| !account.is_empty())
+        .unwrap_or(DEFAULT_ACCOUNT_LABEL.to_string())
+}
+
+async fn get_account_client(
+    state: &AppState,
+    label: &str,
+) -> Result<(reqwest::Client, Arc<MobilesuicaCookies>), CardError> {
+    let cookies = get_account_cookies(state, label).ok_or(CardError::AccountNotFound)?;
+    let cookies = Arc::new(cookies);
+
+    let client = get_client(cookies.clone())
+        .await
+        .map_err(|_| CardError::RequestFailed)?;
+
+    Ok((client, cookies))
+}
+
+async fn fetch_card(state: &AppState, label: &str) -> Result<CardSummary, CardError> {
+    let (client, cookies) = get_account_client(state, label).await?;
+
+    let summary = fetch_card_summary(&client).await?;
+
+    update_account_cookies(state, label, &cookies);
+
+    Ok(summary)
+}
+
+async fn fetch_pass(state: &AppState, label: &str) -> Result<Option<CommuterPass>, CardError> {
+    let (client, cookies) = get_account_client(state, label).await?;
+
+    let commuter_pass = fetch_commuter_pass(&client).await?;
+
+    update_account_cookies(state, label, &cookies);
+
+    Ok(commuter_pass)
+}
+
+async fn fetch_tickets(state: &AppState, label: &str) -> Result<Vec<GreenTicket>, CardError> {
+    let (client, cookies) = get_account_client(state, label).await?;
+
+    let green_tickets = fetch_green_tickets(&client).await?;
+
+    update_account_cookies(state, label, &cookies);
+
+    Ok(green_tickets)
+}
+
+async fn fetch_charges(
+    state: &AppState,
+    label: &str,
+) -> Result<(AutoChargeSettings, Vec<ChargeEvent>), CardError> {
+    let (client, cookies) = get_account_client(state, label).await?;
+
+    let settings = fetch_auto_charge_settings(&client).await?;
+    let records = fetch_usage_history(&client).await?;
+
+    update_account_cookies(state, label, &cookies);
+
+    let charges = charge_events(&records);
+
+    Ok((settings, charges))
+}
+
+fn create_commuter_pass_result(
+    account: String,
+    commuter_pass: Option<CommuterPass>,
+    today: NaiveDate,
+) -> CardJsonResult {
+    CardJsonResult::CommuterPass {
+        account,
+        days_until_expiry: commuter_pass
+            .as_ref()
+            .map(|commuter_pass| commuter_pass.days_until_expiry(today)),
+        expiring: commuter_pass
+            .as_ref()
+            .is_some_and(|commuter_pass| commuter_pass.is_expiring(today)),
+        commuter_pass,
+    }
+}
+
+// 現在の残額とカードの情報
+pub async fn handler(State(state): State<AppState>, Query(query): Query<CardQuery>) -> Response {
+    let account = get_label(query);
+
+    let result = fetch_card(&state, &account)
+        .await
+        .map(|summary| CardJsonResult::Card { account, summary });
+
+    create_card_response(result)
+}
+
+// 定期券の区間と有効期間。期限が近い場合はexpiringがtrueになる
+pub async fn commuter_pass_handler(
+    State(state): State<AppState>,
+    Query(query): Query<CardQuery>,
+) -> Response {
+    let account = get_label(query);
+
+    let result = fetch_pass(&state, &account)
+        .await
+        .map(|commuter_pass| create_commuter_pass_result(account, commuter_pass, today()));
+
+    create_card_response(result)
+}
+
+// Suicaグリーン券の購入履歴
+pub async fn green_tickets_handler(
+    State(state): State<AppState>,
+    Query(query): Query<CardQuery>,
+) -> Response {
+    let account = get_label(query);
+
+    let result =
+        fetch_tickets(&state, &account)
+            .await
+            .map(|green_tickets| CardJsonResult::GreenTickets {
+                account,
+                green_tickets,
+            });
+
+    create_card_response(result)
+}
+
+// オートチャージの設定と、利用履歴のチャージの内訳
+// クレジットカードの明細との突き合わせに使う
+pub async fn auto_charge_handler(
+    State(state): State<AppState>,
+    Query(query): Query<CardQuery>,
+) -> Response {
+    let account = get_label(query);
+
+    let result = fetch_charges(&state, &account)
+        .await
+        .map(|(auto_charge, charges)| CardJsonResult::AutoCharge {
+            account,
+            auto_charge,
+            charges,
+        });
+
+    create_card_response(result)
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::*;
+
+    #[test]
+    fn test_create_card_response() {
+        let summary = CardSummary {
+            suica_id: "JE* **** **** 1234".to_string(),
+            balance: Some(1528),
+            card_type: None,
+            registered_on: NaiveDate::from_ymd_opt(2020, 4, 1),
+        };
+
+        let card = CardJson {
+            ok: true,
+            result: CardJsonResult::Card {
+                account: "default".to_string(),
+                summary,
+            },
+        };
+        let json = serde_json::to_value(card).unwrap();
+
+        assert_eq!(json["result"]["account"], "default");
+        assert_eq!(json["result"]["balance"], 1528);
+        assert_eq!(json["result"]["registered_on"], "2020-04-01");
+
+        let response = create_card_response(Err(CardError::AccountNotFound));
+
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[test]
+    fn test_create_commuter_pass_result() {
+        let commuter_pass = CommuterPass {
+            from_station: "東京".to_string(),
+            to_station: "新宿".to_string(),
+            via: vec![],
+            valid_from: NaiveDate::from_ymd_opt(2023, 9, 1).unwrap(),
+            valid_until: NaiveDate::from_ymd_opt(2024, 2, 29).unwrap(),
+            pass_type: "通勤定期（6ヶ月）".to_string(),
+        };
+        let today = NaiveDate::from_ymd_opt(2024, 2, 20).unwrap();
+
+        let result = create_commuter_pass_result("default".to_string(), Some(commuter_pass), today);
+        let json = serde_json::to_value(result).unwrap();
+
+        assert_eq!(json["commuter_pass"]["from_station"], "東京");
+        assert_eq!(json["days_until_expiry"], 9);
+        assert_eq!(json["expiring"], true);
+
+        let json = serde_json::to_value(create_commuter_pass_result(
+            "default".to_string(),
+            None,
+            today,
+        ))
+        .unwrap();
+
+        assert_eq!(json["commuter_pass"], serde_json::Value::Null);
+        assert_eq!(json["expiring"], false);
+    }
+}
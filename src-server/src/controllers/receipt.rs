@@ -0,0 +1,261 @@
+use std::sync::Arc;
+
+use axum::{
+    extract::State,
+    http::{header, StatusCode},
+    response::{IntoResponse, Response},
+    Json,
+};
+use chrono::NaiveDate;
+use mobilesuica_sheet_app_server::HttpClient::get_client;
+use mobilesuica_sheet_app_server::{
+    fetch_usage_history, get_receipt_error_code, get_receipt_error_message, select_records,
+    Maintenance, Receipt, ReceiptError, UsageHistoryError, UsageRecord, DEFAULT_RECEIPT_NOTE,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::controllers::accounts::{
+    get_account_cookies, update_account_cookies, DEFAULT_ACCOUNT_LABEL,
+};
+use crate::controllers::card::today;
+use crate::controllers::status::maintenance_response;
+use crate::AppState;
+
+#[derive(Debug, PartialEq)]
+enum ReceiptRequestError {
+    AccountNotFound,
+    RequestFailed,
+    SessionExpired,
+    Maintenance(Maintenance),
+    Receipt(ReceiptError),
+}
+
+impl From<UsageHistoryError> for ReceiptRequestError {
+    fn from(error: UsageHistoryError) -> Self {
+        match error {
+            UsageHistoryError::RequestFailed => ReceiptRequestError::RequestFailed,
+            UsageHistoryError::SessionExpired => ReceiptRequestError::SessionExpired,
+            UsageHistoryError::Maintenance(maintenance) => {
+                ReceiptRequestError::Maintenance(maintenance)
+            }
+        }
+    }
+}
+
+impl From<ReceiptError> for ReceiptRequestError {
+    fn from(error: ReceiptError) -> Self {
+        ReceiptRequestError::Receipt(error)
+    }
+}
+
+fn get_receipt_request_error_message(error: &ReceiptRequestError) -> String {
+    match error {
+        ReceiptRequestError::AccountNotFound => {
+            "アカウントが見つかりません。ログインしてください。".to_string()
+        }
+        ReceiptRequestError::RequestFailed => "利用履歴の取得に失敗しました。".to_string(),
+        ReceiptRequestError::SessionExpired => {
+            "ログインの有効期限が切れました。再度ログインしてください。".to_string()
+        }
+        ReceiptRequestError::Maintenance(_) => "モバイルSuicaはメンテナンス中です。".to_string(),
+        ReceiptRequestError::Receipt(e) => get_receipt_error_message(e),
+    }
+}
+
+fn get_receipt_request_error_code(error: &ReceiptRequestError) -> String {
+    match error {
+        ReceiptRequestError::AccountNotFound => "account_not_found".to_string(),
+        ReceiptRequestError::RequestFailed => "request_failed".to_string(),
+        ReceiptRequestError::SessionExpired => "session_expired".to_string(),
+        ReceiptRequestError::Maintenance(_) => "maintenance".to_string(),
+        ReceiptRequestError::Receipt(e) => get_receipt_error_code(e),
+    }
+}
+
+#[derive(Deserialize, Debug)]
+pub struct Payload {
+    // 省略時はラベルなしでログインしたアカウント
+    account: Option<String>,
+    // 宛名。省略時は空欄
+    #[serde(default)]
+    addressee: String,
+    // 用途。省略時は交通費
+    note: Option<String>,
+    // 履歴から選択した行。記載する内容はサーバーで取得し直した利用履歴の行を使う
+    records: Vec<UsageRecord>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct ReceiptErrorJson {
+    ok: bool,
+    result: ReceiptErrorResult,
+}
+
+#[derive(Serialize, Debug)]
+struct ReceiptErrorResult {
+    code: String,
+    message: String,
+}
+
+async fn fetch_history(
+    state: &AppState,
+    label: &str,
+) -> Result<Vec<UsageRecord>, ReceiptRequestError> {
+    let cookies = get_account_cookies(state, label).ok_or(ReceiptRequestError::AccountNotFound)?;
+    let cookies = Arc::new(cookies);
+
+    let client = get_client(cookies.clone())
+        .await
+        .map_err(|_| ReceiptRequestError::RequestFailed)?;
+
+    let records = fetch_usage_history(&client).await?;
+
+    update_account_cookies(state, label, &cookies);
+
+    Ok(records)
+}
+
+// 選択した行をログイン中のアカウントの利用履歴と突き合わせ、利用履歴の行から明細を作る
+// 送られてきた行の金額や駅名はそのまま使わない
+fn create_receipt(
+    payload: &Payload,
+    history: &[UsageRecord],
+    issued_on: NaiveDate,
+) -> Result<Receipt, ReceiptRequestError> {
+    let note = payload
+        .note
+        .clone()
+        .filter(|note| !note.is_empty())
+        .unwrap_or(DEFAULT_RECEIPT_NOTE.to_string());
+
+    let entries = select_records(history, &payload.records)?
+        .into_iter()
+        .filter_map(|record| Some((record.infer_date(issued_on)?, record)))
+        .collect::<Vec<(NaiveDate, UsageRecord)>>();
+
+    Ok(Receipt::new(
+        &payload.addressee,
+        &note,
+        issued_on,
+        &entries,
+    )?)
+}
+
+fn create_error_response(error: ReceiptRequestError) -> Response {
+    let status = match error {
+        ReceiptRequestError::Maintenance(maintenance) => return maintenance_response(maintenance),
+        ReceiptRequestError::AccountNotFound => StatusCode::NOT_FOUND,
+        ReceiptRequestError::Receipt(_) => StatusCode::BAD_REQUEST,
+        _ => StatusCode::OK,
+    };
+
+    let body = Json(ReceiptErrorJson {
+        ok: false,
+        result: ReceiptErrorResult {
+            code: get_receipt_request_error_code(&error),
+            message: get_receipt_request_error_message(&error),
+        },
+    });
+
+    (status, body).into_response()
+}
+
+// 選択した利用履歴から利用明細のPDFを作る
+// アプリが作る明細で、交通事業者が発行する領収書ではない
+pub async fn handler(State(state): State<AppState>, Json(payload): Json<Payload>) -> Response {
+    let label = payload
+        .account
+        .clone()
+        .filter(|account| !account.is_empty())
+        .unwrap_or(DEFAULT_ACCOUNT_LABEL.to_string());
+    let issued_on = today();
+
+    let result = match fetch_history(&state, &label).await {
+        Ok(history) => create_receipt(&payload, &history, issued_on),
+        Err(e) => Err(e),
+    };
+
+    let receipt = match result {
+        Ok(receipt) => receipt,
+        Err(e) => return create_error_response(e),
+    };
+
+    let filename = format!("statement-{}.pdf", issued_on.format("%Y%m%d"));
+
+    (
+        [
+            (header::CONTENT_TYPE, "application/pdf".to_string()),
+            (
+                header::CONTENT_DISPOSITION,
+                format!("attachment; filename=\"{}\"", filename),
+            ),
+        ],
+        receipt.to_pdf(),
+    )
+        .into_response()
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::*;
+
+    fn history() -> Vec<UsageRecord> {
+        vec![
+            UsageRecord {
+                date: "09/16".to_string(),
+                entry_kind: "入".to_string(),
+                entry_place: "東京".to_string(),
+                exit_kind: "出".to_string(),
+                exit_place: "新宿".to_string(),
+                balance: Some(1528),
+                amount: Some(-208),
+                ..Default::default()
+            },
+            UsageRecord {
+                date: "09/15".to_string(),
+                entry_kind: "ｶｰﾄﾞ".to_string(),
+                entry_place: "ﾓﾊﾞｲﾙ".to_string(),
+                balance: Some(1736),
+                amount: Some(1000),
+                ..Default::default()
+            },
+        ]
+    }
+
+    #[test]
+    fn test_create_receipt() {
+        let history = history();
+        let issued_on = NaiveDate::from_ymd_opt(2023, 9, 20).unwrap();
+        let payload = Payload {
+            account: None,
+            addressee: "山田太郎".to_string(),
+            note: None,
+            records: history.clone(),
+        };
+
+        let receipt = create_receipt(&payload, &history, issued_on).unwrap();
+
+        assert_eq!(receipt.note, DEFAULT_RECEIPT_NOTE);
+        assert_eq!(receipt.total(), 208);
+
+        // 利用履歴にない金額の行は明細にしない
+        let payload = Payload {
+            records: vec![UsageRecord {
+                amount: Some(-20800),
+                ..history[0].clone()
+            }],
+            ..payload
+        };
+
+        assert_eq!(
+            create_receipt(&payload, &history, issued_on).unwrap_err(),
+            ReceiptRequestError::Receipt(ReceiptError::RecordNotFound)
+        );
+
+        let response =
+            create_error_response(ReceiptRequestError::Receipt(ReceiptError::RecordNotFound));
+
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    }
+}
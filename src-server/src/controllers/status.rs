@@ -0,0 +1,163 @@
+use std::sync::Arc;
+
+use axum::{
+    http::{header, StatusCode},
+    response::{IntoResponse, Response},
+    Json,
+};
+use chrono::Utc;
+use mobilesuica_sheet_app_server::{
+    detect_maintenance,
+    HttpClient::{get_client, get_text, BASE_URL},
+    Maintenance,
+};
+use serde::Serialize;
+
+#[derive(Serialize, Debug)]
+pub struct MaintenanceResponse {
+    ok: bool,
+    result: MaintenanceResult,
+}
+
+#[derive(Serialize, Debug)]
+struct MaintenanceResult {
+    code: String,
+    message: String,
+    end_at: Option<String>,
+}
+
+// メンテナンス中は503を返す。終了予定がわかる場合はRetry-Afterも付ける
+pub fn maintenance_response(maintenance: Maintenance) -> Response {
+    let retry_after = maintenance
+        .end_at
+        .map(|end_at| (end_at.with_timezone(&Utc) - Utc::now()).num_seconds())
+        .filter(|seconds| *seconds > 0);
+
+    let body = Json(MaintenanceResponse {
+        ok: false,
+        result: MaintenanceResult {
+            code: "maintenance".to_string(),
+            message: maintenance.message,
+            end_at: maintenance.end_at.map(|end_at| end_at.to_rfc3339()),
+        },
+    });
+
+    match retry_after {
+        Some(seconds) => (
+            StatusCode::SERVICE_UNAVAILABLE,
+            [(header::RETRY_AFTER, seconds.to_string())],
+            body,
+        )
+            .into_response(),
+        None => (StatusCode::SERVICE_UNAVAILABLE, body).into_response(),
+    }
+}
+
+#[derive(Serialize, Debug)]
+pub struct UpstreamStatus {
+    ok: bool,
+    result: UpstreamStatusResult,
+}
+
+#[derive(Serialize, Debug)]
+struct UpstreamStatusResult {
+    available: bool,
+    // available, maintenance, unreachable
+    status: String,
+    maintenance: Option<Maintenance>,
+}
+
+// メンテナンス中は503などでお知らせのページを返すため、ステータスコードにかかわらず本文を読む
+// 2xxかどうかと本文の組を返す
+async fn fetch_top_page() -> Result<(bool, String), reqwest::Error> {
+    let client = get_client(Arc::default()).await?;
+
+    let response = client.get(BASE_URL).send().await?;
+    let is_success = response.status().is_success();
+
+    Ok((is_success, get_text(response).await?))
+}
+
+fn create_upstream_status(top_page: Result<(bool, String), reqwest::Error>) -> UpstreamStatus {
+    let (status, maintenance) = match top_page {
+        Ok((is_success, html)) => match (detect_maintenance(&html), is_success) {
+            (Some(maintenance), _) => ("maintenance", Some(maintenance)),
+            (None, true) => ("available", None),
+            (None, false) => ("unreachable", None),
+        },
+        Err(_) => ("unreachable", None),
+    };
+
+    UpstreamStatus {
+        ok: true,
+        result: UpstreamStatusResult {
+            available: status == "available",
+            status: status.to_string(),
+            maintenance,
+        },
+    }
+}
+
+// モバイルSuicaが利用できるかどうか
+pub async fn handler() -> Json<UpstreamStatus> {
+    Json(create_upstream_status(fetch_top_page().await))
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::*;
+    use chrono::{DateTime, Duration};
+
+    #[test]
+    fn test_maintenance_response() {
+        let end_at = (Utc::now() + Duration::hours(1)).fixed_offset();
+
+        let response = maintenance_response(Maintenance {
+            message: "メンテナンス中".to_string(),
+            end_at: Some(end_at),
+        });
+
+        assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+
+        let retry_after = response.headers()[header::RETRY_AFTER]
+            .to_str()
+            .unwrap()
+            .parse::<i64>()
+            .unwrap();
+
+        assert!(retry_after > 3500 && retry_after <= 3600);
+
+        let response = maintenance_response(Maintenance {
+            message: "メンテナンス中".to_string(),
+            end_at: Some(DateTime::parse_from_rfc3339("2000-01-01T00:00:00+09:00").unwrap()),
+        });
+
+        assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+        assert!(response.headers().get(header::RETRY_AFTER).is_none());
+    }
+
+    #[test]
+    fn test_create_upstream_status() {
+        let login_page = include_str!("../../test/login.html").to_string();
+        let available = create_upstream_status(Ok((true, login_page.clone())));
+
+        assert!(available.result.available);
+        assert_eq!(available.result.status, "available");
+
+        // メンテナンスのお知らせは503で返る
+        let maintenance = create_upstream_status(Ok((
+            false,
+            "<html><body><p>ただいまシステムメンテナンス中です。</p></body></html>".to_string(),
+        )));
+
+        assert!(!maintenance.result.available);
+        assert_eq!(maintenance.result.status, "maintenance");
+        assert!(maintenance.result.maintenance.is_some());
+
+        let error = create_upstream_status(Ok((false, login_page)));
+
+        assert!(!error.result.available);
+        assert_eq!(error.result.status, "unreachable");
+    }
+}
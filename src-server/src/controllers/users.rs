@@ -0,0 +1,707 @@
+use std::collections::BTreeMap;
+use std::sync::Arc;
+
+use axum::{
+    extract::{Query, State},
+    http::{header, StatusCode},
+    response::{IntoResponse, Response},
+    Form, Json,
+};
+use chrono::NaiveDate;
+use mobilesuica_sheet_app_server::HttpClient::get_client;
+use mobilesuica_sheet_app_server::{
+    apply_tag_rules, fetch_commuter_pass, get_tag_rule_error_code, get_tag_rule_error_message,
+    get_user_store, get_user_store_error_code, get_user_store_error_message, validate_tag_rules,
+    write_usage_csv, ArchivedRecord, CommuterPass, LinkedAccount, MonthlyReport, TagRule,
+    TagRuleError, UsageRecord, User, UserStore, UserStoreError,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::controllers::accounts::{get_account_cookies, update_account_cookies};
+use crate::controllers::card::today;
+use crate::AppState;
+
+const USER_ID_KEY: &str = "user_id";
+
+// 利用履歴の出力形式。formatを指定しない場合に使う
+const EXPORT_FORMAT_KEY: &str = "export_format";
+
+// 保存できる設定と取りうる値
+const SETTINGS: [(&str, &[&str]); 1] = [(EXPORT_FORMAT_KEY, &["csv", "json"])];
+
+#[derive(Debug)]
+enum UserError {
+    StoreUnavailable,
+    NotLoggedIn,
+    InvalidSetting,
+    InvalidRule(TagRuleError),
+    InvalidReportMonth,
+    InvalidReportFormat,
+    InvalidExportFormat,
+    ReportAccountRequired,
+    CommuterPassUnavailable,
+    Store(UserStoreError),
+}
+
+impl From<UserStoreError> for UserError {
+    fn from(error: UserStoreError) -> Self {
+        UserError::Store(error)
+    }
+}
+
+impl From<TagRuleError> for UserError {
+    fn from(error: TagRuleError) -> Self {
+        UserError::InvalidRule(error)
+    }
+}
+
+fn get_user_error_message(error: &UserError) -> String {
+    match error {
+        UserError::StoreUnavailable => "ユーザー機能は利用できません。".to_string(),
+        UserError::NotLoggedIn => "ログインしてください。".to_string(),
+        UserError::InvalidSetting => "設定の項目または値が正しくありません。".to_string(),
+        UserError::InvalidRule(e) => get_tag_rule_error_message(e),
+        UserError::InvalidReportMonth => {
+            "対象月は2023-09のように年と月で指定してください。".to_string()
+        }
+        UserError::InvalidReportFormat => "出力形式はjsonかpdfで指定してください。".to_string(),
+        UserError::InvalidExportFormat => "出力形式はcsvかjsonで指定してください。".to_string(),
+        UserError::ReportAccountRequired => {
+            "定期券を除く場合は、紐付けたアカウントを指定してください。".to_string()
+        }
+        UserError::CommuterPassUnavailable => {
+            "定期券情報を取得できませんでした。再度ログインしてください。".to_string()
+        }
+        UserError::Store(e) => get_user_store_error_message(e),
+    }
+}
+
+fn get_user_error_code(error: &UserError) -> String {
+    match error {
+        UserError::StoreUnavailable => "store_unavailable".to_string(),
+        UserError::NotLoggedIn => "not_logged_in".to_string(),
+        UserError::InvalidSetting => "invalid_setting".to_string(),
+        UserError::InvalidRule(e) => get_tag_rule_error_code(e),
+        UserError::InvalidReportMonth => "invalid_report_month".to_string(),
+        UserError::InvalidReportFormat => "invalid_report_format".to_string(),
+        UserError::InvalidExportFormat => "invalid_export_format".to_string(),
+        UserError::ReportAccountRequired => "report_account_required".to_string(),
+        UserError::CommuterPassUnavailable => "commuter_pass_unavailable".to_string(),
+        UserError::Store(e) => get_user_store_error_code(e),
+    }
+}
+
+fn get_user_error_status(error: &UserError) -> StatusCode {
+    match error {
+        UserError::StoreUnavailable => StatusCode::SERVICE_UNAVAILABLE,
+        UserError::NotLoggedIn | UserError::Store(UserStoreError::InvalidCredentials) => {
+            StatusCode::UNAUTHORIZED
+        }
+        UserError::Store(UserStoreError::UsernameTaken) => StatusCode::CONFLICT,
+        UserError::InvalidSetting
+        | UserError::InvalidRule(_)
+        | UserError::InvalidReportMonth
+        | UserError::InvalidReportFormat
+        | UserError::InvalidExportFormat
+        | UserError::ReportAccountRequired
+        | UserError::Store(UserStoreError::InvalidUsername)
+        | UserError::Store(UserStoreError::WeakPassword) => StatusCode::BAD_REQUEST,
+        UserError::CommuterPassUnavailable => StatusCode::BAD_GATEWAY,
+        UserError::Store(_) => StatusCode::INTERNAL_SERVER_ERROR,
+    }
+}
+
+#[derive(Deserialize, Debug)]
+pub struct Payload {
+    username: String,
+    password: String,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct HistoryQuery {
+    account: Option<String>,
+    // csv か json。省略時は設定のexport_format
+    format: Option<String>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct ReportQuery {
+    // 2023-09 の形
+    month: String,
+    // 氏名。省略時はユーザー名
+    employee: Option<String>,
+    // json か pdf。省略時はjson
+    format: Option<String>,
+    account: Option<String>,
+    // accountの定期券の区間の乗車を除く
+    #[serde(default)]
+    exclude_commuter_pass: bool,
+}
+
+#[derive(Serialize, Debug)]
+pub struct UsersJson<T: Serialize> {
+    ok: bool,
+    result: T,
+}
+
+#[derive(Serialize, Debug)]
+struct UserErrorResult {
+    code: String,
+    message: String,
+}
+
+#[derive(Serialize, Debug)]
+pub struct UserProfile {
+    user: User,
+    accounts: Vec<LinkedAccount>,
+    settings: BTreeMap<String, String>,
+}
+
+fn create_error_response(error: UserError) -> Response {
+    let body = Json(UsersJson {
+        ok: false,
+        result: UserErrorResult {
+            code: get_user_error_code(&error),
+            message: get_user_error_message(&error),
+        },
+    });
+
+    (get_user_error_status(&error), body).into_response()
+}
+
+fn create_response<T: Serialize>(result: Result<T, UserError>) -> Response {
+    match result {
+        Ok(result) => Json(UsersJson { ok: true, result }).into_response(),
+        Err(e) => create_error_response(e),
+    }
+}
+
+pub fn get_user_id(state: &AppState) -> Option<i64> {
+    let session = state.session.lock().unwrap();
+
+    session.get::<Option<i64>>(USER_ID_KEY).flatten()
+}
+
+// ユーザーが替わるので、紐付けたモバイルSuicaのアカウント（auth_cookies）を含めてセッションを作り直す
+// 同じブラウザで別のユーザーがログインしても、前のユーザーの履歴を保存・紐付けしないようにする
+fn set_user_id(state: &AppState, user_id: Option<i64>) {
+    let mut session = state.session.lock().unwrap();
+
+    session.renew();
+    session.set(USER_ID_KEY, user_id);
+}
+
+// ログイン中のユーザー。ユーザー機能が無効かログインしていない場合はNone
+pub fn current_user(state: &AppState) -> Option<(&'static UserStore, i64)> {
+    Some((get_user_store()?, get_user_id(state)?))
+}
+
+// ログイン中のユーザーのルールで区分とプロジェクトを付ける
+pub fn apply_user_tag_rules(state: &AppState, records: &mut [UsageRecord]) {
+    let Some((store, user_id)) = current_user(state) else {
+        return;
+    };
+
+    match store.tag_rules(user_id) {
+        Ok(rules) => apply_tag_rules(records, &rules, today()),
+        Err(_) => tracing::warn!("failed to load tag rules for user {}", user_id),
+    }
+}
+
+fn require_user(state: &AppState) -> Result<(&'static UserStore, i64), UserError> {
+    let store = get_user_store().ok_or(UserError::StoreUnavailable)?;
+    let user_id = get_user_id(state).ok_or(UserError::NotLoggedIn)?;
+
+    Ok((store, user_id))
+}
+
+fn get_profile(store: &UserStore, user_id: i64) -> Result<UserProfile, UserError> {
+    // 削除されたユーザーのセッションはログインしていない扱い
+    let user = store.get_user(user_id)?.ok_or(UserError::NotLoggedIn)?;
+
+    Ok(UserProfile {
+        user,
+        accounts: store.linked_accounts(user_id)?,
+        settings: store.settings(user_id)?,
+    })
+}
+
+fn validate_settings(settings: &BTreeMap<String, String>) -> Result<(), UserError> {
+    let is_valid = |key: &str, value: &str| {
+        SETTINGS
+            .iter()
+            .any(|(name, values)| *name == key && values.contains(&value))
+    };
+
+    match settings.iter().all(|(key, value)| is_valid(key, value)) {
+        true => Ok(()),
+        false => Err(UserError::InvalidSetting),
+    }
+}
+
+fn save_tag_rules(
+    store: &UserStore,
+    user_id: i64,
+    rules: &[TagRule],
+) -> Result<Vec<TagRule>, UserError> {
+    validate_tag_rules(rules)?;
+
+    store.set_tag_rules(user_id, rules)?;
+
+    Ok(store.tag_rules(user_id)?)
+}
+
+#[derive(Debug, PartialEq)]
+enum ExportFormat {
+    Csv,
+    Json,
+}
+
+// 指定がない場合は設定のexport_format、それもない場合はjson
+fn get_export_format(
+    store: &UserStore,
+    user_id: i64,
+    format: Option<&str>,
+) -> Result<ExportFormat, UserError> {
+    let settings = store.settings(user_id)?;
+
+    match format.or(settings.get(EXPORT_FORMAT_KEY).map(String::as_str)) {
+        None | Some("json") => Ok(ExportFormat::Json),
+        Some("csv") => Ok(ExportFormat::Csv),
+        Some(_) => Err(UserError::InvalidExportFormat),
+    }
+}
+
+// 利用履歴のCSV。見出し行はUsageRecordのフィールド名で、アカウントは含めない
+fn create_csv_response(history: Vec<ArchivedRecord>) -> Response {
+    let records = history
+        .into_iter()
+        .map(|archived| archived.record)
+        .collect::<Vec<UsageRecord>>();
+
+    let mut csv = vec![];
+
+    if write_usage_csv(&records, &mut csv).is_err() {
+        return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+    }
+
+    (
+        [
+            (header::CONTENT_TYPE, "text/csv; charset=utf-8"),
+            (
+                header::CONTENT_DISPOSITION,
+                "attachment; filename=\"history.csv\"",
+            ),
+        ],
+        csv,
+    )
+        .into_response()
+}
+
+#[derive(Debug, PartialEq)]
+enum ReportFormat {
+    Json,
+    Pdf,
+}
+
+// 省略時はjson。それ以外の形式は受け付けない
+fn parse_report_format(format: Option<&str>) -> Result<ReportFormat, UserError> {
+    match format {
+        None | Some("json") => Ok(ReportFormat::Json),
+        Some("pdf") => Ok(ReportFormat::Pdf),
+        Some(_) => Err(UserError::InvalidReportFormat),
+    }
+}
+
+fn parse_report_month(month: &str) -> Option<(i32, u32)> {
+    let (year, month) = month.split_once('-')?;
+
+    Some((year.parse().ok()?, month.parse().ok()?))
+}
+
+// 精算書から除く定期券。指定がない場合はNone
+async fn fetch_report_commuter_pass(
+    state: &AppState,
+    store: &UserStore,
+    user_id: i64,
+    query: &ReportQuery,
+) -> Result<Option<CommuterPass>, UserError> {
+    if !query.exclude_commuter_pass {
+        return Ok(None);
+    }
+
+    // 定期券はカードごとなので、ユーザーに紐付けたアカウントに限る
+    let label = query
+        .account
+        .as_deref()
+        .ok_or(UserError::ReportAccountRequired)?;
+
+    if !store
+        .linked_accounts(user_id)?
+        .iter()
+        .any(|account| account.label == label)
+    {
+        return Err(UserError::ReportAccountRequired);
+    }
+
+    let cookies = get_account_cookies(state, label).ok_or(UserError::CommuterPassUnavailable)?;
+    let cookies = Arc::new(cookies);
+
+    let client = get_client(cookies.clone())
+        .await
+        .map_err(|_| UserError::CommuterPassUnavailable)?;
+
+    let commuter_pass = fetch_commuter_pass(&client)
+        .await
+        .map_err(|_| UserError::CommuterPassUnavailable)?;
+
+    update_account_cookies(state, label, &cookies);
+
+    Ok(commuter_pass)
+}
+
+fn create_report(
+    store: &UserStore,
+    user_id: i64,
+    query: &ReportQuery,
+    commuter_pass: Option<&CommuterPass>,
+) -> Result<MonthlyReport, UserError> {
+    let (year, month) = parse_report_month(&query.month).ok_or(UserError::InvalidReportMonth)?;
+    let user = store.get_user(user_id)?.ok_or(UserError::NotLoggedIn)?;
+    let employee = query
+        .employee
+        .clone()
+        .filter(|employee| !employee.trim().is_empty())
+        .unwrap_or(user.username);
+
+    let mut history = store.archived_history(user_id, query.account.as_deref())?;
+
+    // 保存した順（各取得の中では新しい順）なので、後に取得したものを先にして全体を新しい順にする
+    history.sort_by_key(|archived| std::cmp::Reverse(archived.archived_at));
+
+    let entries = history
+        .into_iter()
+        .filter_map(|archived| Some((archived.ride_date?, archived.record)))
+        .collect::<Vec<(NaiveDate, UsageRecord)>>();
+
+    MonthlyReport::new(
+        &employee,
+        year,
+        month,
+        today(),
+        &entries,
+        &store.tag_rules(user_id)?,
+        commuter_pass,
+    )
+    .ok_or(UserError::InvalidReportMonth)
+}
+
+fn save_settings(
+    store: &UserStore,
+    user_id: i64,
+    settings: &BTreeMap<String, String>,
+) -> Result<UserProfile, UserError> {
+    validate_settings(settings)?;
+
+    for (key, value) in settings {
+        store.set_setting(user_id, key, value)?;
+    }
+
+    get_profile(store, user_id)
+}
+
+pub async fn register_handler(State(state): State<AppState>, payload: Form<Payload>) -> Response {
+    let result = get_user_store()
+        .ok_or(UserError::StoreUnavailable)
+        .and_then(|store| Ok(store.register(&payload.username, &payload.password)?));
+
+    if let Ok(user) = &result {
+        set_user_id(&state, Some(user.id));
+    }
+
+    create_response(result)
+}
+
+pub async fn login_handler(State(state): State<AppState>, payload: Form<Payload>) -> Response {
+    let result = get_user_store()
+        .ok_or(UserError::StoreUnavailable)
+        .and_then(|store| Ok(store.authenticate(&payload.username, &payload.password)?));
+
+    if let Ok(user) = &result {
+        set_user_id(&state, Some(user.id));
+    }
+
+    create_response(result)
+}
+
+pub async fn logout_handler(State(state): State<AppState>) -> Response {
+    set_user_id(&state, None);
+
+    create_response::<Option<User>>(Ok(None))
+}
+
+// ログイン中のユーザーと紐付けたアカウント、設定
+pub async fn me_handler(State(state): State<AppState>) -> Response {
+    create_response(require_user(&state).and_then(|(store, user_id)| get_profile(store, user_id)))
+}
+
+pub async fn settings_handler(
+    State(state): State<AppState>,
+    Json(settings): Json<BTreeMap<String, String>>,
+) -> Response {
+    create_response(
+        require_user(&state).and_then(|(store, user_id)| save_settings(store, user_id, &settings)),
+    )
+}
+
+// 蓄積した利用履歴。accountを指定するとそのアカウントのみ
+pub async fn history_handler(
+    State(state): State<AppState>,
+    Query(query): Query<HistoryQuery>,
+) -> Response {
+    let result = require_user(&state).and_then(|(store, user_id)| {
+        let format = get_export_format(store, user_id, query.format.as_deref())?;
+        let mut history = store.archived_history(user_id, query.account.as_deref())?;
+        let rules = store.tag_rules(user_id)?;
+
+        // 今日を基準に年を補うと1年以上前の行の曜日がずれるので、保存時に補った日付を使う
+        for archived in history.iter_mut() {
+            let date = archived.ride_date.unwrap_or_else(today);
+
+            apply_tag_rules(std::slice::from_mut(&mut archived.record), &rules, date);
+        }
+
+        Ok::<(ExportFormat, Vec<ArchivedRecord>), UserError>((format, history))
+    });
+
+    match result {
+        Ok((ExportFormat::Csv, history)) => create_csv_response(history),
+        result => create_response(result.map(|(_, history)| history)),
+    }
+}
+
+// 区分とプロジェクトを付けるルール。上にあるものが優先される
+pub async fn rules_handler(State(state): State<AppState>) -> Response {
+    create_response(
+        require_user(&state)
+            .and_then(|(store, user_id)| Ok::<Vec<TagRule>, UserError>(store.tag_rules(user_id)?)),
+    )
+}
+
+// ルールをまとめて置き換える
+pub async fn update_rules_handler(
+    State(state): State<AppState>,
+    Json(rules): Json<Vec<TagRule>>,
+) -> Response {
+    create_response(
+        require_user(&state).and_then(|(store, user_id)| save_tag_rules(store, user_id, &rules)),
+    )
+}
+
+// 蓄積した利用履歴のうち、ルールで業務に区分した乗車の交通費精算書
+pub async fn report_handler(
+    State(state): State<AppState>,
+    Query(query): Query<ReportQuery>,
+) -> Response {
+    let result = async {
+        let format = parse_report_format(query.format.as_deref())?;
+        let (store, user_id) = require_user(&state)?;
+        let commuter_pass = fetch_report_commuter_pass(&state, store, user_id, &query).await?;
+
+        let report = create_report(store, user_id, &query, commuter_pass.as_ref())?;
+
+        Ok::<(ReportFormat, MonthlyReport), UserError>((format, report))
+    }
+    .await;
+
+    match result {
+        Ok((ReportFormat::Pdf, report)) => {
+            let filename = format!("report-{}{:02}.pdf", report.year, report.month);
+
+            (
+                [
+                    (header::CONTENT_TYPE, "application/pdf".to_string()),
+                    (
+                        header::CONTENT_DISPOSITION,
+                        format!("attachment; filename=\"{}\"", filename),
+                    ),
+                ],
+                report.to_pdf(),
+            )
+                .into_response()
+        }
+        result => create_response(result.map(|(_, report)| report)),
+    }
+}
+
+#[cfg(test)]
+mod test {
+
+    use std::sync::{Arc, Mutex};
+
+    use super::*;
+    use mobilesuica_sheet_app_server::Session;
+
+    #[test]
+    fn test_user_id() {
+        let session_id = Session::new();
+        let state = AppState {
+            session: Arc::new(Mutex::new(Session::get_session(&session_id).unwrap())),
+        };
+
+        assert_eq!(get_user_id(&state), None);
+
+        state
+            .session
+            .lock()
+            .unwrap()
+            .set("auth_cookies", BTreeMap::from([("corporate", "cookies")]));
+
+        set_user_id(&state, Some(1));
+        assert_eq!(get_user_id(&state), Some(1));
+
+        // 前のユーザーのアカウントとセッションIDは引き継がない
+        let session = state.session.lock().unwrap().clone();
+        assert_ne!(session.id(), session_id);
+        assert_eq!(
+            session.get::<BTreeMap<String, String>>("auth_cookies"),
+            None
+        );
+
+        set_user_id(&state, None);
+        assert_eq!(get_user_id(&state), None);
+    }
+
+    #[test]
+    fn test_save_settings() {
+        let store = UserStore::open_in_memory().unwrap();
+        let user = store.register("suica-user", "password1").unwrap();
+
+        let settings = BTreeMap::from([("export_format".to_string(), "json".to_string())]);
+        let profile = save_settings(&store, user.id, &settings).unwrap();
+
+        assert_eq!(profile.settings, settings);
+
+        let invalid = BTreeMap::from([("export_format".to_string(), "xml".to_string())]);
+
+        assert!(matches!(
+            save_settings(&store, user.id, &invalid),
+            Err(UserError::InvalidSetting)
+        ));
+
+        let unknown = BTreeMap::from([("theme".to_string(), "dark".to_string())]);
+
+        assert!(matches!(
+            save_settings(&store, user.id, &unknown),
+            Err(UserError::InvalidSetting)
+        ));
+    }
+
+    #[test]
+    fn test_get_export_format() {
+        let store = UserStore::open_in_memory().unwrap();
+        let user = store.register("suica-user", "password1").unwrap();
+
+        assert_eq!(
+            get_export_format(&store, user.id, None).unwrap(),
+            ExportFormat::Json
+        );
+
+        store
+            .set_setting(user.id, EXPORT_FORMAT_KEY, "csv")
+            .unwrap();
+
+        assert_eq!(
+            get_export_format(&store, user.id, None).unwrap(),
+            ExportFormat::Csv
+        );
+        // 指定した形式が設定より優先される
+        assert_eq!(
+            get_export_format(&store, user.id, Some("json")).unwrap(),
+            ExportFormat::Json
+        );
+        assert!(matches!(
+            get_export_format(&store, user.id, Some("xlsx")),
+            Err(UserError::InvalidExportFormat)
+        ));
+    }
+
+    #[test]
+    fn test_create_report() {
+        let store = UserStore::open_in_memory().unwrap();
+        let user = store.register("suica-user", "password1").unwrap();
+
+        let rules: Vec<TagRule> = serde_json::from_str(
+            r#"[{ "name": "A社打合せ", "match": { "stations": ["渋谷", "横浜"] }, "category": "business", "project": "PRJ-001" }]"#,
+        )
+        .unwrap();
+        store.set_tag_rules(user.id, &rules).unwrap();
+
+        let mut record = UsageRecord {
+            date: today().format("%m/%d").to_string(),
+            entry_kind: "入".to_string(),
+            entry_place: "渋谷".to_string(),
+            exit_kind: "出".to_string(),
+            exit_place: "横浜".to_string(),
+            amount: Some(-318),
+            ..Default::default()
+        };
+        record.resolve_stations();
+        store.archive_history(user.id, "main", &[record]).unwrap();
+
+        let query = ReportQuery {
+            month: today().format("%Y-%m").to_string(),
+            employee: None,
+            format: None,
+            account: None,
+            exclude_commuter_pass: false,
+        };
+        let report = create_report(&store, user.id, &query, None).unwrap();
+
+        assert_eq!(report.employee, "suica-user");
+        assert_eq!(report.lines.len(), 1);
+        assert_eq!(report.lines[0].purpose, "A社打合せ");
+        assert_eq!(report.total, 318);
+
+        let query = ReportQuery {
+            month: "2023/09".to_string(),
+            ..query
+        };
+
+        assert!(matches!(
+            create_report(&store, user.id, &query, None),
+            Err(UserError::InvalidReportMonth)
+        ));
+    }
+
+    #[test]
+    fn test_parse_report_format() {
+        assert_eq!(parse_report_format(None).unwrap(), ReportFormat::Json);
+        assert_eq!(
+            parse_report_format(Some("json")).unwrap(),
+            ReportFormat::Json
+        );
+        assert_eq!(parse_report_format(Some("pdf")).unwrap(), ReportFormat::Pdf);
+
+        let error = parse_report_format(Some("xlsx")).unwrap_err();
+
+        assert!(matches!(error, UserError::InvalidReportFormat));
+        assert_eq!(get_user_error_status(&error), StatusCode::BAD_REQUEST);
+    }
+
+    #[test]
+    fn test_get_user_error_status() {
+        assert_eq!(
+            get_user_error_status(&UserError::Store(UserStoreError::UsernameTaken)),
+            StatusCode::CONFLICT
+        );
+        assert_eq!(
+            get_user_error_status(&UserError::Store(UserStoreError::InvalidCredentials)),
+            StatusCode::UNAUTHORIZED
+        );
+        assert_eq!(
+            get_user_error_status(&UserError::NotLoggedIn),
+            StatusCode::UNAUTHORIZED
+        );
+    }
+}
@@ -1,11 +1,90 @@
 mod libs {
+    pub mod auto_charge;
+    pub mod captcha_image;
+    pub mod captcha_solver;
+    pub mod card_summary;
+    pub mod charset;
+    pub mod commuter_pass;
+    pub mod credentials;
+    pub mod export;
+    pub mod green_ticket;
     pub mod html_document;
     pub mod http_client;
+    pub mod image_info;
+    pub mod japanese_text;
+    pub mod journey;
+    pub mod maintenance;
+    pub mod mobilesuica_cookies;
     pub mod mobilesuica_form_params;
+    pub mod mobilesuica_login;
+    pub mod pdf;
+    pub mod receipt;
+    pub mod report;
     pub mod session;
+    pub mod sjis_urlencoded;
+    pub mod stations;
+    pub mod tag_rules;
+    pub mod usage_history;
+    pub mod user_store;
+    pub mod web_form;
 }
 
+pub use libs::auto_charge::{
+    charge_events, classify_charge, fetch_auto_charge_settings, parse_auto_charge_settings,
+    AutoChargeError, AutoChargeSettings, ChargeEvent, ChargeKind,
+};
+pub use libs::captcha_image::{
+    convert_captcha_image, CaptchaImageError, CaptchaImageOptions, ResizeFilter,
+};
+pub use libs::captcha_solver::{
+    captcha_solver_from_env, get_captcha_solver, set_captcha_solver, CaptchaSolver, ManualSolver,
+    TemplateSolver,
+};
+pub use libs::card_summary::{
+    fetch_card_summary, parse_card_summary, CardSummary, CardSummaryError,
+};
+pub use libs::commuter_pass::{
+    fetch_commuter_pass, parse_commuter_pass, CommuterPass, CommuterPassError,
+};
+#[cfg(feature = "secret-service")]
+pub use libs::credentials::DbusSecretStore;
+pub use libs::credentials::{
+    credentials_provider_from_env, get_credentials_error_message, get_credentials_provider,
+    set_credentials_provider, shared_credentials_allowed, Credentials, CredentialsError,
+    CredentialsProvider, EnvProvider, FileProvider, MemorySecretStore, SecretItem,
+    SecretServiceProvider, SecretStore,
+};
+pub use libs::export::{merge_green_tickets, write_usage_csv, write_usage_json};
+pub use libs::green_ticket::{
+    fetch_green_tickets, parse_green_tickets, GreenTicket, GreenTicketError, GreenTicketStatus,
+};
 pub use libs::html_document::HtmlDocument;
 pub use libs::http_client as HttpClient;
+pub use libs::image_info::{image_info, sniff_mime_type, ImageInfo};
+pub use libs::japanese_text::{
+    element_text, format_yen, normalize_text, parse_date, parse_month_day, parse_yen,
+};
+pub use libs::journey::{group_journeys, Journey, Transfer};
+pub use libs::maintenance::{detect_maintenance, Maintenance};
 pub use libs::mobilesuica_form_params::MobilesuicaFormParams;
+pub use libs::mobilesuica_login as MobilesuicaLogin;
+pub use libs::receipt::{
+    get_receipt_error_code, get_receipt_error_message, select_records, Receipt, ReceiptError,
+    ReceiptLine, DEFAULT_RECEIPT_NOTE, RECEIPT_TITLE,
+};
+pub use libs::report::{MonthlyReport, ProjectSubtotal, ReportLine, DEFAULT_REPORT_PURPOSE};
 pub use libs::session::Session;
+pub use libs::sjis_urlencoded as SjisUrlencoded;
+pub use libs::stations::{find_station_by_code, get_stations, normalize_station, Station};
+pub use libs::tag_rules::{
+    apply_tag_rules, get_tag_rule_error_code, get_tag_rule_error_message, validate_tag_rules,
+    RuleMatcher, TagCategory, TagRule, TagRuleError, MAX_TAG_RULES,
+};
+pub use libs::usage_history::{
+    fetch_usage_history, parse_usage_history, UsageHistoryError, UsageRecord,
+};
+pub use libs::user_store::{
+    get_user_store, get_user_store_error_code, get_user_store_error_message, set_user_store,
+    user_store_from_env, ArchivedRecord, LinkedAccount, User, UserStore, UserStoreError,
+};
+pub use libs::web_form::WebForm;
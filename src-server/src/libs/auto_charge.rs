@@ -0,0 +1,228 @@
+use crate::libs::card_summary::{collect_fields, find_field};
+use crate::libs::http_client::{get_text, BASE_URL};
+use crate::libs::japanese_text::{normalize_text, parse_yen};
+use crate::libs::maintenance::{detect_maintenance, Maintenance};
+use crate::libs::mobilesuica_login::get_title;
+use crate::libs::usage_history::UsageRecord;
+use crate::HtmlDocument;
+
+use serde::Serialize;
+
+// オートチャージ設定
+pub const AUTO_CHARGE_PATH: &str = "iq/ir/SuicaDisp.aspx?returnId=SFRCMMEPC09";
+
+// 利用履歴の種別（正規化後の全角カナ）
+const AUTO_CHARGE_KINDS: [&str; 1] = ["オート"];
+const MANUAL_CHARGE_KINDS: [&str; 4] = ["カード", "現金", "チャージ", "ビュー"];
+
+#[derive(Debug, PartialEq)]
+pub enum AutoChargeError {
+    RequestFailed,
+    // ログイン画面に戻された
+    SessionExpired,
+    Maintenance(Maintenance),
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Serialize)]
+pub struct AutoChargeSettings {
+    pub enabled: bool,
+    // 残額がこの金額以下で改札を入場するとチャージされる
+    pub threshold: Option<i64>,
+    pub amount: Option<i64>,
+    // ビューカード(VISA) など
+    pub credit_card_brand: Option<String>,
+    // 末尾4桁以外は伏せられている
+    pub credit_card_number: Option<String>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ChargeKind {
+    Manual,
+    Auto,
+    // 種別から判断できない入金。払戻しなどチャージでない場合もある
+    Unknown,
+}
+
+// 利用履歴のチャージ1件
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct ChargeEvent {
+    pub date: String,
+    pub amount: i64,
+    pub balance: Option<i64>,
+    pub kind: ChargeKind,
+    // 利用履歴の種別そのまま
+    pub method: String,
+}
+
+// カード番号の部分とブランド名の部分に分ける
+fn split_credit_card(text: &str) -> (Option<String>, Option<String>) {
+    let number_start = text.find('*');
+
+    let (brand, number) = match number_start {
+        Some(index) => (&text[..index], Some(&text[index..])),
+        None => (text, None),
+    };
+
+    let brand = Some(brand.trim().to_string()).filter(|brand| !brand.is_empty());
+    let number = number.map(|number| number.trim().to_string());
+
+    (brand, number)
+}
+
+// オートチャージ設定のページを読み取る。設定の表がない場合はNone
+pub fn parse_auto_charge_settings(html: &str) -> Option<AutoChargeSettings> {
+    let document = HtmlDocument::new(html);
+    let fields = collect_fields(&document);
+
+    let status = find_field(&fields, "オートチャージ")?;
+
+    let (credit_card_brand, credit_card_number) = find_field(&fields, "クレジットカード")
+        .map(|text| split_credit_card(text))
+        .unwrap_or_default();
+
+    Some(AutoChargeSettings {
+        enabled: !status.contains("なし") && !status.contains("未設定"),
+        threshold: find_field(&fields, "残額").and_then(|value| parse_yen(value)),
+        amount: find_field(&fields, "チャージ金額").and_then(|value| parse_yen(value)),
+        credit_card_brand,
+        credit_card_number,
+    })
+}
+
+// 入金の行を種別で手動とオートチャージに分ける。入金でない行はNone
+// 種別から判断できない行は金額などから推測せずUnknownにする
+pub fn classify_charge(record: &UsageRecord) -> Option<ChargeKind> {
+    record.amount.filter(|amount| *amount > 0)?;
+
+    // 正規化前に保存された履歴は半角カナのままなので、そろえてから比べる
+    let kind = normalize_text(&record.entry_kind);
+
+    if AUTO_CHARGE_KINDS.contains(&kind.as_str()) {
+        Some(ChargeKind::Auto)
+    } else if MANUAL_CHARGE_KINDS.contains(&kind.as_str()) {
+        Some(ChargeKind::Manual)
+    } else {
+        Some(ChargeKind::Unknown)
+    }
+}
+
+// 利用履歴から入金の行だけを取り出す
+pub fn charge_events(records: &[UsageRecord]) -> Vec<ChargeEvent> {
+    records
+        .iter()
+        .filter_map(|record| {
+            Some(ChargeEvent {
+                kind: classify_charge(record)?,
+                date: record.date.clone(),
+                amount: record.amount?,
+                balance: record.balance,
+                method: record.entry_kind.clone(),
+            })
+        })
+        .collect()
+}
+
+// ログイン済みのクライアントでオートチャージ設定を取得する
+pub async fn fetch_auto_charge_settings(
+    client: &reqwest::Client,
+) -> Result<AutoChargeSettings, AutoChargeError> {
+    let url = format!("{}{}", BASE_URL, AUTO_CHARGE_PATH);
+
+    let html = async {
+        let response = client.get(url).send().await?;
+
+        get_text(response).await
+    }
+    .await
+    .map_err(|_| AutoChargeError::RequestFailed)?;
+
+    if let Some(maintenance) = detect_maintenance(&html) {
+        return Err(AutoChargeError::Maintenance(maintenance));
+    }
+
+    if !get_title(&html).contains("オートチャージ") {
+        return Err(AutoChargeError::SessionExpired);
+    }
+
+    // 申し込んでいない場合は設定の表がない
+    Ok(parse_auto_charge_settings(&html).unwrap_or_default())
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::*;
+    use crate::libs::usage_history::parse_usage_history;
+
+    fn charge(entry_kind: &str, balance: i64, amount: i64) -> UsageRecord {
+        UsageRecord {
+            date: "09/16".to_string(),
+            entry_kind: entry_kind.to_string(),
+            balance: Some(balance),
+            amount: Some(amount),
+            ..Default::default()
+        }
+    }
+
+    fn settings() -> AutoChargeSettings {
+        parse_auto_charge_settings(include_str!("../../test/auto_charge.html")).unwrap()
+    }
+
+    #[test]
+    fn test_parse_auto_charge_settings() {
+        assert_eq!(
+            settings(),
+            AutoChargeSettings {
+                enabled: true,
+                threshold: Some(1000),
+                amount: Some(3000),
+                credit_card_brand: Some("ビューカード(VISA)".to_string()),
+                credit_card_number: Some("****-****-****-1234".to_string()),
+            }
+        );
+
+        assert_eq!(
+            parse_auto_charge_settings(include_str!("../../test/login.html")),
+            None
+        );
+    }
+
+    #[test]
+    fn test_classify_charge() {
+        assert_eq!(
+            classify_charge(&charge("ｵｰﾄ", 3800, 3000)),
+            Some(ChargeKind::Auto)
+        );
+        assert_eq!(
+            classify_charge(&charge("ｶｰﾄﾞ", 2000, 1000)),
+            Some(ChargeKind::Manual)
+        );
+        // ビューカードからのチャージは半角でも全角でも手動
+        assert_eq!(
+            classify_charge(&charge("ﾋﾞｭｰ", 3800, 3000)),
+            Some(ChargeKind::Manual)
+        );
+        assert_eq!(
+            classify_charge(&charge("ビュー", 3800, 3000)),
+            Some(ChargeKind::Manual)
+        );
+        // 種別が分からない入金は推測せず、取り除きもしない
+        assert_eq!(
+            classify_charge(&charge("ｴｷ", 3800, 3000)),
+            Some(ChargeKind::Unknown)
+        );
+        // 利用は対象外
+        assert_eq!(classify_charge(&charge("入", 1528, -208)), None);
+    }
+
+    #[test]
+    fn test_charge_events() {
+        let records = parse_usage_history(include_str!("../../test/history.html")).unwrap();
+        let events = charge_events(&records);
+
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].amount, 1000);
+        assert_eq!(events[0].kind, ChargeKind::Manual);
+    }
+}
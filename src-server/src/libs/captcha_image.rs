@@ -0,0 +1,190 @@
+use image::imageops::FilterType;
+use image::{DynamicImage, ImageOutputFormat};
+use std::io::Cursor;
+
+// 拡大しすぎるとレスポンスが大きくなるので上限を設ける
+pub const MAX_SCALE: u32 = 8;
+pub const MAX_CONTRAST: f32 = 100.0;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum ResizeFilter {
+    // 文字の輪郭がぼやけないのでキャプチャ向き
+    #[default]
+    Nearest,
+    Lanczos,
+}
+
+impl ResizeFilter {
+    pub fn parse(name: &str) -> Option<Self> {
+        match name.to_ascii_lowercase().as_str() {
+            "nearest" => Some(ResizeFilter::Nearest),
+            "lanczos" | "lanczos3" => Some(ResizeFilter::Lanczos),
+            _ => None,
+        }
+    }
+
+    fn filter_type(&self) -> FilterType {
+        match self {
+            ResizeFilter::Nearest => FilterType::Nearest,
+            ResizeFilter::Lanczos => FilterType::Lanczos3,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct CaptchaImageOptions {
+    pub scale: u32,
+    pub filter: ResizeFilter,
+    // 0で変更なし。正の値でコントラストを上げる
+    pub contrast: f32,
+}
+
+impl Default for CaptchaImageOptions {
+    fn default() -> Self {
+        CaptchaImageOptions {
+            scale: 1,
+            filter: ResizeFilter::default(),
+            contrast: 0.0,
+        }
+    }
+}
+
+impl CaptchaImageOptions {
+    pub fn is_valid(&self) -> bool {
+        (1..=MAX_SCALE).contains(&self.scale)
+            && self.contrast.is_finite()
+            && self.contrast.abs() <= MAX_CONTRAST
+    }
+}
+
+#[derive(Debug)]
+pub enum CaptchaImageError {
+    InvalidOptions,
+    DecodeFailed(image::ImageError),
+    EncodeFailed(image::ImageError),
+}
+
+fn transform(image: DynamicImage, options: &CaptchaImageOptions) -> DynamicImage {
+    let image = match options.scale {
+        1 => image,
+        scale => image.resize_exact(
+            image.width() * scale,
+            image.height() * scale,
+            options.filter.filter_type(),
+        ),
+    };
+
+    if options.contrast == 0.0 {
+        return image;
+    }
+
+    image.adjust_contrast(options.contrast)
+}
+
+// キャプチャ画像（主にGIF）をデコードして拡大・コントラスト調整し、PNGにする
+pub fn convert_captcha_image(
+    bytes: &[u8],
+    options: &CaptchaImageOptions,
+) -> Result<Vec<u8>, CaptchaImageError> {
+    if !options.is_valid() {
+        return Err(CaptchaImageError::InvalidOptions);
+    }
+
+    let image = image::load_from_memory(bytes).map_err(CaptchaImageError::DecodeFailed)?;
+
+    let mut png = Vec::new();
+
+    transform(image, options)
+        .write_to(&mut Cursor::new(&mut png), ImageOutputFormat::Png)
+        .map_err(CaptchaImageError::EncodeFailed)?;
+
+    Ok(png)
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::*;
+    use crate::libs::image_info::image_info;
+    use image::{Rgba, RgbaImage};
+
+    fn gif(width: u32, height: u32) -> Vec<u8> {
+        let image = RgbaImage::from_fn(width, height, |x, y| match (x + y) % 2 {
+            0 => Rgba([0, 0, 0, 255]),
+            _ => Rgba([200, 200, 200, 255]),
+        });
+
+        let mut gif = Vec::new();
+        DynamicImage::ImageRgba8(image)
+            .write_to(&mut Cursor::new(&mut gif), ImageOutputFormat::Gif)
+            .unwrap();
+
+        gif
+    }
+
+    #[test]
+    fn test_resize_filter_parse() {
+        assert_eq!(ResizeFilter::parse("nearest"), Some(ResizeFilter::Nearest));
+        assert_eq!(ResizeFilter::parse("Lanczos"), Some(ResizeFilter::Lanczos));
+        assert_eq!(ResizeFilter::parse("bicubic"), None);
+    }
+
+    #[test]
+    fn test_convert_captcha_image() {
+        let png = convert_captcha_image(
+            &gif(20, 10),
+            &CaptchaImageOptions {
+                scale: 3,
+                filter: ResizeFilter::Nearest,
+                contrast: 20.0,
+            },
+        )
+        .unwrap();
+
+        let info = image_info(&png).unwrap();
+
+        assert_eq!(info.mime_type, "image/png");
+        assert_eq!((info.width, info.height), (60, 30));
+
+        // 最近傍法なので3x3のブロックが同じ色になる
+        let image = image::load_from_memory(&png).unwrap().to_rgba8();
+        assert_eq!(image.get_pixel(0, 0), image.get_pixel(2, 2));
+        assert_ne!(image.get_pixel(0, 0), image.get_pixel(3, 0));
+        // コントラストを上げたので明るい色はより明るくなる
+        assert!(image.get_pixel(3, 0)[0] > 200);
+    }
+
+    #[test]
+    fn test_convert_captcha_image_lanczos() {
+        let png = convert_captcha_image(
+            &gif(20, 10),
+            &CaptchaImageOptions {
+                scale: 2,
+                filter: ResizeFilter::Lanczos,
+                contrast: 0.0,
+            },
+        )
+        .unwrap();
+
+        let info = image_info(&png).unwrap();
+
+        assert_eq!((info.width, info.height), (40, 20));
+    }
+
+    #[test]
+    fn test_convert_captcha_image_error() {
+        let invalid_scale = CaptchaImageOptions {
+            scale: MAX_SCALE + 1,
+            ..Default::default()
+        };
+
+        assert!(matches!(
+            convert_captcha_image(&gif(2, 2), &invalid_scale),
+            Err(CaptchaImageError::InvalidOptions)
+        ));
+        assert!(matches!(
+            convert_captcha_image(b"<html></html>", &CaptchaImageOptions::default()),
+            Err(CaptchaImageError::DecodeFailed(_))
+        ));
+    }
+}
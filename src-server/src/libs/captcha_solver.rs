@@ -0,0 +1,645 @@
+use image::{GrayImage, Luma};
+use std::env;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::{OnceLock, RwLock};
+
+const CAPTCHA_TEMPLATES_ENV: &str = "MOBILESUICA_CAPTCHA_TEMPLATES";
+
+// 文字を比較するときの大きさ
+const GLYPH_WIDTH: u32 = 16;
+const GLYPH_HEIGHT: u32 = 24;
+
+// これより細い塊はノイズとみなす
+const MIN_GLYPH_WIDTH: u32 = 2;
+
+const DEFAULT_THRESHOLD: f32 = 0.6;
+
+// キャプチャ画像から文字列を読み取る
+// 読み取れない場合はNoneを返し、利用者が入力する
+pub trait CaptchaSolver: Send + Sync {
+    fn name(&self) -> &'static str;
+
+    fn solve(&self, captcha_image: &[u8]) -> Option<String>;
+
+    // 利用者の入力が必要かどうか
+    fn is_manual(&self) -> bool {
+        false
+    }
+
+    // 利用者が入力してログインできたキャプチャを覚える。覚えないソルバーは何もしない
+    fn remember(&self, _captcha_image: &[u8], _answer: &str) {}
+}
+
+// これまでどおり利用者が画像を見て入力する
+#[derive(Debug, Default)]
+pub struct ManualSolver;
+
+impl CaptchaSolver for ManualSolver {
+    fn name(&self) -> &'static str {
+        "manual"
+    }
+
+    fn solve(&self, _captcha_image: &[u8]) -> Option<String> {
+        None
+    }
+
+    fn is_manual(&self) -> bool {
+        true
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+struct Glyph {
+    label: char,
+    // GLYPH_WIDTH x GLYPH_HEIGHTの2値画像。trueが文字の部分
+    pixels: Vec<bool>,
+}
+
+impl Glyph {
+    // 文字部分の重なり具合（0.0〜1.0）
+    fn similarity(&self, pixels: &[bool]) -> f32 {
+        let (intersection, union) =
+            self.pixels
+                .iter()
+                .zip(pixels)
+                .fold((0, 0), |(intersection, union), (&a, &b)| {
+                    (intersection + (a && b) as u32, union + (a || b) as u32)
+                });
+
+        match union {
+            0 => 0.0,
+            _ => intersection as f32 / union as f32,
+        }
+    }
+
+    // 保存するファイル名。同じ見本は何度保存しても同じ名前になる
+    fn file_name(&self) -> String {
+        // FNV-1a
+        let hash = self
+            .pixels
+            .iter()
+            .fold(0xcbf29ce484222325u64, |hash, &pixel| {
+                (hash ^ pixel as u64).wrapping_mul(0x100000001b3)
+            });
+
+        format!("{}_{:016x}.png", self.label, hash)
+    }
+
+    fn to_image(&self) -> GrayImage {
+        GrayImage::from_fn(GLYPH_WIDTH, GLYPH_HEIGHT, |x, y| {
+            match self.pixels[(y * GLYPH_WIDTH + x) as usize] {
+                true => Luma([0]),
+                false => Luma([255]),
+            }
+        })
+    }
+}
+
+// 2値化した画像
+struct Bitmap {
+    width: u32,
+    height: u32,
+    pixels: Vec<bool>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct Rect {
+    x: u32,
+    y: u32,
+    width: u32,
+    height: u32,
+}
+
+// 大津の方法でしきい値を決める
+fn otsu_threshold(image: &GrayImage) -> u8 {
+    let mut histogram = [0u64; 256];
+    for pixel in image.pixels() {
+        histogram[pixel[0] as usize] += 1;
+    }
+
+    let total = image.pixels().len() as f64;
+    let sum = histogram
+        .iter()
+        .enumerate()
+        .map(|(value, count)| value as f64 * *count as f64)
+        .sum::<f64>();
+
+    let (mut weight_background, mut sum_background) = (0.0, 0.0);
+    let (mut best_threshold, mut best_variance) = (0, 0.0);
+
+    for (value, count) in histogram.iter().enumerate() {
+        weight_background += *count as f64;
+        if weight_background == 0.0 {
+            continue;
+        }
+
+        let weight_foreground = total - weight_background;
+        if weight_foreground == 0.0 {
+            break;
+        }
+
+        sum_background += value as f64 * *count as f64;
+
+        let mean_background = sum_background / weight_background;
+        let mean_foreground = (sum - sum_background) / weight_foreground;
+        let variance =
+            weight_background * weight_foreground * (mean_background - mean_foreground).powi(2);
+
+        if variance > best_variance {
+            best_variance = variance;
+            best_threshold = value as u8;
+        }
+    }
+
+    best_threshold
+}
+
+impl Bitmap {
+    fn new(image: &GrayImage) -> Self {
+        let threshold = otsu_threshold(image);
+
+        let mut pixels = image
+            .pixels()
+            .map(|pixel| pixel[0] <= threshold)
+            .collect::<Vec<bool>>();
+
+        // 文字は背景より少ないはずなので、多い方を背景として反転する
+        if pixels.iter().filter(|&&ink| ink).count() * 2 > pixels.len() {
+            pixels.iter_mut().for_each(|ink| *ink = !*ink);
+        }
+
+        Bitmap {
+            width: image.width(),
+            height: image.height(),
+            pixels,
+        }
+    }
+
+    fn get(&self, x: u32, y: u32) -> bool {
+        self.pixels[(y * self.width + x) as usize]
+    }
+
+    // 指定した列の範囲で文字がある行の範囲
+    fn vertical_bounds(&self, x: u32, width: u32) -> Option<(u32, u32)> {
+        let rows = (0..self.height)
+            .filter(|&y| (x..x + width).any(|x| self.get(x, y)))
+            .collect::<Vec<u32>>();
+
+        Some((*rows.first()?, *rows.last()?))
+    }
+
+    fn bounding_box(&self) -> Option<Rect> {
+        let columns = (0..self.width)
+            .filter(|&x| (0..self.height).any(|y| self.get(x, y)))
+            .collect::<Vec<u32>>();
+
+        let (first, last) = (*columns.first()?, *columns.last()?);
+        let (top, bottom) = self.vertical_bounds(first, last - first + 1)?;
+
+        Some(Rect {
+            x: first,
+            y: top,
+            width: last - first + 1,
+            height: bottom - top + 1,
+        })
+    }
+
+    // 縦方向の投影で文字ごとに切り分ける
+    fn segment(&self) -> Vec<Rect> {
+        let mut rects = vec![];
+        let mut start: Option<u32> = None;
+
+        for x in 0..=self.width {
+            let has_ink = x < self.width && (0..self.height).any(|y| self.get(x, y));
+
+            match (start, has_ink) {
+                (None, true) => start = Some(x),
+                (Some(first), false) => {
+                    start = None;
+
+                    let width = x - first;
+                    if width < MIN_GLYPH_WIDTH {
+                        continue;
+                    }
+
+                    if let Some((top, bottom)) = self.vertical_bounds(first, width) {
+                        rects.push(Rect {
+                            x: first,
+                            y: top,
+                            width,
+                            height: bottom - top + 1,
+                        });
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        rects
+    }
+
+    // 縦横比を保ったまま比較用の大きさの中央に収める
+    fn normalize(&self, rect: Rect) -> Vec<bool> {
+        let scale = f32::max(
+            rect.width as f32 / GLYPH_WIDTH as f32,
+            rect.height as f32 / GLYPH_HEIGHT as f32,
+        );
+        let offset_x = (GLYPH_WIDTH as f32 - rect.width as f32 / scale) / 2.0;
+        let offset_y = (GLYPH_HEIGHT as f32 - rect.height as f32 / scale) / 2.0;
+
+        let mut pixels = vec![false; (GLYPH_WIDTH * GLYPH_HEIGHT) as usize];
+
+        for ty in 0..GLYPH_HEIGHT {
+            for tx in 0..GLYPH_WIDTH {
+                let sx = ((tx as f32 + 0.5 - offset_x) * scale).floor();
+                let sy = ((ty as f32 + 0.5 - offset_y) * scale).floor();
+
+                if sx < 0.0 || sy < 0.0 || sx >= rect.width as f32 || sy >= rect.height as f32 {
+                    continue;
+                }
+
+                pixels[(ty * GLYPH_WIDTH + tx) as usize] =
+                    self.get(rect.x + sx as u32, rect.y + sy as u32);
+            }
+        }
+
+        pixels
+    }
+}
+
+fn load_bitmap(image: &[u8]) -> Option<Bitmap> {
+    let image = image::load_from_memory(image).ok()?.to_luma8();
+
+    Some(Bitmap::new(&image))
+}
+
+// 文字ごとの見本画像と照らし合わせて読み取る
+// 見本は同梱していないので、利用者が入力してログインできたキャプチャをrememberで集めて保存する
+// 見本が集まるまでは手入力と同じ扱いで、自動ログインはできない
+#[derive(Debug)]
+pub struct TemplateSolver {
+    glyphs: RwLock<Vec<Glyph>>,
+    // 一文字でもこれを下回ったら読み取れなかったことにする
+    threshold: f32,
+    // rememberで覚えた見本の保存先
+    dir: Option<PathBuf>,
+}
+
+impl Default for TemplateSolver {
+    fn default() -> Self {
+        TemplateSolver {
+            glyphs: RwLock::new(vec![]),
+            threshold: DEFAULT_THRESHOLD,
+            dir: None,
+        }
+    }
+}
+
+impl TemplateSolver {
+    // ディレクトリ内の「<文字>.png」「<文字>_<任意>.png」を見本として読み込む
+    // ディレクトリがまだない場合は見本なしで始め、覚えた見本を保存するときに作る
+    pub fn load(dir: impl AsRef<Path>) -> io::Result<Self> {
+        let dir = dir.as_ref();
+        let mut solver = TemplateSolver {
+            dir: Some(dir.to_path_buf()),
+            ..Default::default()
+        };
+
+        if !dir.exists() {
+            return Ok(solver);
+        }
+
+        let glyphs = solver.glyphs.get_mut().unwrap();
+
+        let mut paths = fs::read_dir(dir)?
+            .map(|entry| entry.map(|entry| entry.path()))
+            .collect::<io::Result<Vec<_>>>()?;
+        paths.sort();
+
+        for path in paths {
+            let label = path
+                .file_stem()
+                .and_then(|stem| stem.to_str())
+                .and_then(|stem| stem.split('_').next())
+                .filter(|label| label.chars().count() == 1)
+                .and_then(|label| label.chars().next());
+
+            let Some(label) = label else {
+                continue;
+            };
+
+            let bitmap = match load_bitmap(&fs::read(&path)?) {
+                Some(bitmap) => bitmap,
+                None => continue,
+            };
+
+            // saveで書いた見本は比較する大きさのまま読む。それ以外は切り出して大きさをそろえる
+            let pixels = if bitmap.width == GLYPH_WIDTH && bitmap.height == GLYPH_HEIGHT {
+                bitmap.pixels
+            } else {
+                match bitmap.bounding_box() {
+                    Some(rect) => bitmap.normalize(rect),
+                    None => continue,
+                }
+            };
+
+            if !glyphs
+                .iter()
+                .any(|g| g.label == label && g.pixels == pixels)
+            {
+                glyphs.push(Glyph { label, pixels });
+            }
+        }
+
+        Ok(solver)
+    }
+
+    pub fn with_threshold(mut self, threshold: f32) -> Self {
+        self.threshold = threshold;
+
+        self
+    }
+
+    pub fn len(&self) -> usize {
+        self.glyphs.read().unwrap().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.glyphs.read().unwrap().is_empty()
+    }
+
+    // 正解がわかっている画像から見本を追加する
+    // 切り分けた文字数が正解と合わない場合は何もしない
+    pub fn learn(&self, captcha_image: &[u8], answer: &str) -> bool {
+        let Some(bitmap) = load_bitmap(captcha_image) else {
+            return false;
+        };
+
+        let rects = bitmap.segment();
+        let labels = answer.chars().collect::<Vec<char>>();
+
+        if rects.len() != labels.len() || labels.iter().any(|c| !c.is_ascii_alphanumeric()) {
+            return false;
+        }
+
+        let mut glyphs = self.glyphs.write().unwrap();
+
+        for (label, rect) in labels.into_iter().zip(rects) {
+            let pixels = bitmap.normalize(rect);
+
+            if !glyphs
+                .iter()
+                .any(|g| g.label == label && g.pixels == pixels)
+            {
+                glyphs.push(Glyph { label, pixels });
+            }
+        }
+
+        true
+    }
+
+    // 見本の画素から決めた名前で保存する。保存済みの見本は書き直さない
+    pub fn save(&self, dir: impl AsRef<Path>) -> io::Result<()> {
+        let dir = dir.as_ref();
+
+        fs::create_dir_all(dir)?;
+
+        for glyph in self.glyphs.read().unwrap().iter() {
+            let path = dir.join(glyph.file_name());
+
+            if path.exists() {
+                continue;
+            }
+
+            glyph.to_image().save(&path).map_err(io::Error::other)?;
+        }
+
+        Ok(())
+    }
+
+    fn best_match(glyphs: &[Glyph], pixels: &[bool]) -> Option<(char, f32)> {
+        glyphs
+            .iter()
+            .map(|glyph| (glyph.label, glyph.similarity(pixels)))
+            .max_by(|(_, a), (_, b)| a.total_cmp(b))
+    }
+}
+
+impl CaptchaSolver for TemplateSolver {
+    fn name(&self) -> &'static str {
+        "template"
+    }
+
+    fn solve(&self, captcha_image: &[u8]) -> Option<String> {
+        let glyphs = self.glyphs.read().unwrap();
+
+        if glyphs.is_empty() {
+            return None;
+        }
+
+        let bitmap = load_bitmap(captcha_image)?;
+        let rects = bitmap.segment();
+
+        if rects.is_empty() {
+            return None;
+        }
+
+        rects
+            .into_iter()
+            .map(|rect| {
+                TemplateSolver::best_match(&glyphs, &bitmap.normalize(rect))
+                    .filter(|(_, similarity)| *similarity >= self.threshold)
+                    .map(|(label, _)| label)
+            })
+            .collect()
+    }
+
+    fn is_manual(&self) -> bool {
+        self.is_empty()
+    }
+
+    fn remember(&self, captcha_image: &[u8], answer: &str) {
+        if !self.learn(captcha_image, answer) {
+            return;
+        }
+
+        if let Some(dir) = &self.dir {
+            if let Err(e) = self.save(dir) {
+                tracing::warn!("failed to save captcha templates: {}", e);
+            }
+        }
+    }
+}
+
+// 環境変数に見本のディレクトリがあればテンプレート照合、なければ手入力
+pub fn captcha_solver_from_env() -> io::Result<Box<dyn CaptchaSolver>> {
+    match env::var(CAPTCHA_TEMPLATES_ENV) {
+        Ok(dir) if !dir.is_empty() => Ok(Box::new(TemplateSolver::load(dir)?)),
+        _ => Ok(Box::new(ManualSolver)),
+    }
+}
+
+static CAPTCHA_SOLVER: OnceLock<Box<dyn CaptchaSolver>> = OnceLock::new();
+
+// 起動時に一度だけ設定する。設定済みの場合は渡したものを返す
+pub fn set_captcha_solver(solver: Box<dyn CaptchaSolver>) -> Result<(), Box<dyn CaptchaSolver>> {
+    CAPTCHA_SOLVER.set(solver)
+}
+
+pub fn get_captcha_solver() -> &'static dyn CaptchaSolver {
+    CAPTCHA_SOLVER
+        .get_or_init(|| Box::new(ManualSolver))
+        .as_ref()
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::*;
+    use image::{DynamicImage, ImageOutputFormat};
+    use std::io::Cursor;
+
+    // 5x7の簡易フォント
+    fn font(label: char) -> [&'static str; 7] {
+        match label {
+            'A' => [
+                ".###.", "#...#", "#...#", "#####", "#...#", "#...#", "#...#",
+            ],
+            'L' => [
+                "#....", "#....", "#....", "#....", "#....", "#....", "#####",
+            ],
+            'T' => [
+                "#####", "..#..", "..#..", "..#..", "..#..", "..#..", "..#..",
+            ],
+            '7' => [
+                "#####", "....#", "...#.", "..#..", ".#...", ".#...", ".#...",
+            ],
+            _ => unreachable!(),
+        }
+    }
+
+    // 文字を3倍に拡大して並べたGIF。背景は明るい灰色
+    fn captcha(text: &str) -> Vec<u8> {
+        let scale = 3;
+        let width = (text.chars().count() as u32 * 8 + 4) * scale;
+        let height = 13 * scale;
+
+        let image = GrayImage::from_fn(width, height, |x, y| {
+            let (x, y) = (x / scale, y / scale);
+            let index = (x.saturating_sub(2) / 8) as usize;
+            let (gx, gy) = (x.saturating_sub(2) % 8, y.saturating_sub(3));
+
+            let ink = x >= 2
+                && gx < 5
+                && (3..10).contains(&y)
+                && text
+                    .chars()
+                    .nth(index)
+                    .is_some_and(|c| font(c)[gy as usize].as_bytes()[gx as usize] == b'#');
+
+            match ink {
+                true => Luma([40]),
+                false => Luma([220]),
+            }
+        });
+
+        let mut gif = Vec::new();
+        DynamicImage::ImageLuma8(image)
+            .write_to(&mut Cursor::new(&mut gif), ImageOutputFormat::Gif)
+            .unwrap();
+
+        gif
+    }
+
+    #[test]
+    fn test_manual_solver() {
+        assert_eq!(ManualSolver.solve(&captcha("AL")), None);
+        assert!(ManualSolver.is_manual());
+    }
+
+    #[test]
+    fn test_segment() {
+        let bitmap = load_bitmap(&captcha("AL7")).unwrap();
+
+        let rects = bitmap.segment();
+
+        assert_eq!(rects.len(), 3);
+        assert_eq!(
+            rects[0],
+            Rect {
+                x: 6,
+                y: 9,
+                width: 15,
+                height: 21,
+            }
+        );
+    }
+
+    #[test]
+    fn test_template_solver() {
+        let solver = TemplateSolver::default();
+
+        assert_eq!(solver.solve(&captcha("AL")), None);
+
+        assert!(solver.learn(&captcha("ALT7"), "ALT7"));
+        // 文字数が合わない場合は覚えない
+        assert!(!solver.learn(&captcha("AL"), "A"));
+        assert_eq!(solver.len(), 4);
+
+        assert_eq!(solver.solve(&captcha("7TLA")), Some("7TLA".to_string()));
+        assert_eq!(solver.solve(&captcha("TA")), Some("TA".to_string()));
+    }
+
+    #[test]
+    fn test_template_solver_unknown_glyph() {
+        let solver = TemplateSolver::default();
+
+        solver.learn(&captcha("L"), "L");
+
+        // 見本にない文字があれば読み取らない
+        assert_eq!(solver.solve(&captcha("LA")), None);
+    }
+
+    #[test]
+    fn test_template_solver_remember() {
+        let dir = env::temp_dir().join(format!("captcha_remember_{}", std::process::id()));
+
+        // 見本がない間は手入力と同じ
+        let solver = TemplateSolver::load(&dir).unwrap();
+        assert!(solver.is_manual());
+
+        solver.remember(&captcha("ALT7"), "ALT7");
+        assert!(!solver.is_manual());
+
+        let loaded = TemplateSolver::load(&dir).unwrap();
+
+        // 読み込み直してから覚えても同じ見本は増えない
+        loaded.remember(&captcha("TA"), "TA");
+        let files = fs::read_dir(&dir).unwrap().count();
+        let reloaded = TemplateSolver::load(&dir).unwrap();
+
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(loaded.len(), 4);
+        assert_eq!(files, 4);
+        assert_eq!(reloaded.len(), 4);
+    }
+
+    #[test]
+    fn test_template_solver_save_and_load() {
+        let dir = env::temp_dir().join(format!("captcha_templates_{}", std::process::id()));
+
+        let solver = TemplateSolver::default();
+        solver.learn(&captcha("ALT7"), "ALT7");
+        solver.save(&dir).unwrap();
+
+        let loaded = TemplateSolver::load(&dir).unwrap();
+
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(loaded.len(), 4);
+        assert_eq!(loaded.solve(&captcha("LAT")), Some("LAT".to_string()));
+    }
+}
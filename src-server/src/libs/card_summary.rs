@@ -0,0 +1,123 @@
+use crate::libs::http_client::{get_text, BASE_URL};
+use crate::libs::japanese_text::{element_text, parse_date, parse_yen};
+use crate::libs::maintenance::{detect_maintenance, Maintenance};
+use crate::HtmlDocument;
+
+use chrono::NaiveDate;
+use scraper::Selector;
+use serde::Serialize;
+
+// ログイン後の会員メニュー
+pub const MEMBER_MENU_PATH: &str = "iq/ir/SuicaDisp.aspx?returnId=SFRCMMEPC01";
+
+#[derive(Debug, PartialEq)]
+pub enum CardSummaryError {
+    RequestFailed,
+    // ログイン画面に戻された
+    SessionExpired,
+    Maintenance(Maintenance),
+}
+
+// 会員メニューに表示されるカードの情報
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct CardSummary {
+    // 先頭と末尾以外は*で伏せられている
+    pub suica_id: String,
+    // SF（電子マネー）残額
+    pub balance: Option<i64>,
+    pub card_type: Option<String>,
+    pub registered_on: Option<NaiveDate>,
+}
+
+// 見出しと値が横に並んだ行を見出し→値の表にする
+pub(crate) fn collect_fields(document: &HtmlDocument) -> Vec<(String, String)> {
+    let row_selector = Selector::parse("tr").unwrap();
+    let cell_selector = Selector::parse("th, td").unwrap();
+
+    document
+        .query_selector_all("table")
+        .unwrap_or_default()
+        .iter()
+        .flat_map(|table| table.select(&row_selector).collect::<Vec<_>>())
+        .filter_map(|row| {
+            let cells = row
+                .select(&cell_selector)
+                .map(|cell| element_text(&cell))
+                .collect::<Vec<String>>();
+
+            match cells.as_slice() {
+                [label, value] => Some((label.clone(), value.clone())),
+                _ => None,
+            }
+        })
+        .collect()
+}
+
+pub(crate) fn find_field<'a>(fields: &'a [(String, String)], keyword: &str) -> Option<&'a String> {
+    fields
+        .iter()
+        .find(|(label, _)| label.contains(keyword))
+        .map(|(_, value)| value)
+        .filter(|value| !value.is_empty())
+}
+
+// 会員メニューからカードの情報を読み取る。SuicaID番号がない場合はNone
+pub fn parse_card_summary(html: &str) -> Option<CardSummary> {
+    let document = HtmlDocument::new(html);
+    let fields = collect_fields(&document);
+
+    Some(CardSummary {
+        suica_id: find_field(&fields, "SuicaID")?.clone(),
+        balance: find_field(&fields, "残額").and_then(|value| parse_yen(value)),
+        card_type: find_field(&fields, "種別").cloned(),
+        registered_on: find_field(&fields, "登録日").and_then(|value| parse_date(value)),
+    })
+}
+
+// ログイン済みのクライアントで会員メニューを取得する
+pub async fn fetch_card_summary(client: &reqwest::Client) -> Result<CardSummary, CardSummaryError> {
+    let url = format!("{}{}", BASE_URL, MEMBER_MENU_PATH);
+
+    let html = async {
+        let response = client.get(url).send().await?;
+
+        get_text(response).await
+    }
+    .await
+    .map_err(|_| CardSummaryError::RequestFailed)?;
+
+    if let Some(maintenance) = detect_maintenance(&html) {
+        return Err(CardSummaryError::Maintenance(maintenance));
+    }
+
+    parse_card_summary(&html).ok_or(CardSummaryError::SessionExpired)
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::*;
+
+    #[test]
+    fn test_parse_card_summary() {
+        let summary = parse_card_summary(include_str!("../../test/member_menu.html")).unwrap();
+
+        assert_eq!(
+            summary,
+            CardSummary {
+                suica_id: "JE* **** **** 1234".to_string(),
+                balance: Some(1528),
+                card_type: Some("モバイルSuica(記名式)".to_string()),
+                registered_on: NaiveDate::from_ymd_opt(2020, 4, 1),
+            }
+        );
+    }
+
+    #[test]
+    fn test_parse_card_summary_login_page() {
+        assert_eq!(
+            parse_card_summary(include_str!("../../test/login.html")),
+            None
+        );
+    }
+}
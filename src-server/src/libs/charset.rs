@@ -0,0 +1,169 @@
+use encoding_rs::{Encoding, EUC_JP, SHIFT_JIS, UTF_8};
+
+// <meta charset>を探す範囲（HTML仕様のprescanと同じ1024バイト）
+const META_PRESCAN_SIZE: usize = 1024;
+
+fn find_charset_label(text: &str) -> Option<&'static Encoding> {
+    let lower = text.to_ascii_lowercase();
+    let start = lower.find("charset")? + "charset".len();
+
+    let label = lower[start..]
+        .trim_start()
+        .strip_prefix('=')?
+        .trim_start()
+        .trim_start_matches(['"', '\''])
+        .split(|c: char| !(c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | ':' | '.')))
+        .next()?;
+
+    Encoding::for_label(label.as_bytes())
+}
+
+// Content-Typeヘッダーのcharsetパラメータ
+fn charset_from_content_type(content_type: &str) -> Option<&'static Encoding> {
+    content_type
+        .split(';')
+        .skip(1)
+        .find_map(|param| find_charset_label(param.trim()))
+}
+
+// <meta charset="..."> または <meta http-equiv="Content-Type" content="...; charset=...">
+fn charset_from_meta(bytes: &[u8]) -> Option<&'static Encoding> {
+    let head = &bytes[..bytes.len().min(META_PRESCAN_SIZE)];
+    let head = String::from_utf8_lossy(head);
+    let lower = head.to_ascii_lowercase();
+
+    lower
+        .match_indices("<meta")
+        .filter_map(|(start, _)| {
+            let end = lower[start..]
+                .find('>')
+                .map_or(lower.len(), |end| start + end);
+            find_charset_label(&head[start..end])
+        })
+        .next()
+}
+
+fn decode_strict(encoding: &'static Encoding, bytes: &[u8]) -> Option<String> {
+    encoding
+        .decode_without_bom_handling_and_without_replacement(bytes)
+        .map(|text| text.into_owned())
+}
+
+// EUC-JPのバイト列はSJISとしてもデコードできてしまうことが多いため
+// 半角カナが少ない方を採用する
+fn guess_japanese(bytes: &[u8]) -> Option<String> {
+    let count_halfwidth_kana = |text: &String| {
+        text.chars()
+            .filter(|c| ('\u{FF61}'..='\u{FF9F}').contains(c))
+            .count()
+    };
+
+    [SHIFT_JIS, EUC_JP]
+        .into_iter()
+        .filter_map(|encoding| decode_strict(encoding, bytes))
+        .min_by_key(count_halfwidth_kana)
+}
+
+// レスポンスボディを文字コードを判定してデコードする
+// Content-Type → <meta charset> → UTF-8 → SJIS/EUC-JPの順で、不正なバイト列なくデコードできたものを採用する
+pub fn decode_body(bytes: &[u8], content_type: Option<&str>) -> String {
+    if let Some((encoding, bom_length)) = Encoding::for_bom(bytes) {
+        return encoding
+            .decode_without_bom_handling(&bytes[bom_length..])
+            .0
+            .into_owned();
+    }
+
+    let declared = [
+        content_type.and_then(charset_from_content_type),
+        charset_from_meta(bytes),
+    ];
+
+    declared
+        .into_iter()
+        .flatten()
+        .chain([UTF_8])
+        .find_map(|encoding| decode_strict(encoding, bytes))
+        .or_else(|| guess_japanese(bytes))
+        .unwrap_or_else(|| SHIFT_JIS.decode_without_bom_handling(bytes).0.into_owned())
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::*;
+
+    const TEXT: &str = "東京　→　新宿 ￥1,234 チャージ";
+
+    fn encode(encoding: &'static Encoding, text: &str) -> Vec<u8> {
+        encoding.encode(text).0.into_owned()
+    }
+
+    #[test]
+    fn test_charset_from_content_type() {
+        assert_eq!(
+            charset_from_content_type("text/html; charset=Shift_JIS"),
+            Some(SHIFT_JIS)
+        );
+        assert_eq!(
+            charset_from_content_type("text/html;charset=\"euc-jp\""),
+            Some(EUC_JP)
+        );
+        assert_eq!(charset_from_content_type("text/html"), None);
+    }
+
+    #[test]
+    fn test_charset_from_meta() {
+        let html = r#"<html><head><meta http-equiv="Content-Type" content="text/html;CHARSET=Shift_JIS" /></head></html>"#;
+        assert_eq!(charset_from_meta(html.as_bytes()), Some(SHIFT_JIS));
+
+        let html = r#"<html><head><meta charset='EUC-JP'></head></html>"#;
+        assert_eq!(charset_from_meta(html.as_bytes()), Some(EUC_JP));
+
+        let html = r#"<html><head><title>charset=utf-8</title></head></html>"#;
+        assert_eq!(charset_from_meta(html.as_bytes()), None);
+    }
+
+    #[test]
+    fn test_decode_body_declared() {
+        let sjis = encode(SHIFT_JIS, TEXT);
+
+        assert_eq!(
+            decode_body(&sjis, Some("text/html; charset=shift_jis")),
+            TEXT
+        );
+
+        let html = format!(
+            r#"<html><head><meta charset="Shift_JIS"></head><body>{}</body></html>"#,
+            TEXT
+        );
+        let decoded = decode_body(&encode(SHIFT_JIS, &html), Some("text/html"));
+
+        assert_eq!(decoded, html);
+    }
+
+    #[test]
+    fn test_decode_body_fallback() {
+        // 宣言なし
+        assert_eq!(decode_body(TEXT.as_bytes(), None), TEXT);
+        assert_eq!(decode_body(&encode(SHIFT_JIS, TEXT), None), TEXT);
+        assert_eq!(decode_body(&encode(EUC_JP, TEXT), None), TEXT);
+
+        // 宣言と実際の文字コードが異なる
+        assert_eq!(
+            decode_body(&encode(SHIFT_JIS, TEXT), Some("text/html; charset=utf-8")),
+            TEXT
+        );
+    }
+
+    #[test]
+    fn test_decode_body_bom() {
+        let mut bytes = vec![0xEF, 0xBB, 0xBF];
+        bytes.extend_from_slice(TEXT.as_bytes());
+
+        assert_eq!(
+            decode_body(&bytes, Some("text/html; charset=shift_jis")),
+            TEXT
+        );
+    }
+}
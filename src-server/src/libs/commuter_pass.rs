@@ -0,0 +1,241 @@
+use crate::libs::card_summary::{collect_fields, find_field};
+use crate::libs::http_client::{get_text, BASE_URL};
+use crate::libs::japanese_text::{parse_date, split_section};
+use crate::libs::maintenance::{detect_maintenance, Maintenance};
+use crate::libs::mobilesuica_login::get_title;
+use crate::libs::usage_history::UsageRecord;
+use crate::HtmlDocument;
+
+use chrono::NaiveDate;
+use serde::Serialize;
+
+// 定期券情報
+pub const COMMUTER_PASS_PATH: &str = "iq/ir/SuicaDisp.aspx?returnId=SFRCMMEPC05";
+
+// 期限切れの警告を出し始める日数
+pub const EXPIRY_WARNING_DAYS: i64 = 14;
+
+// 全角の／は正規化で半角になる
+const VIA_SEPARATORS: [char; 4] = ['・', '、', ',', '/'];
+
+#[derive(Debug, PartialEq)]
+pub enum CommuterPassError {
+    RequestFailed,
+    // ログイン画面に戻された
+    SessionExpired,
+    Maintenance(Maintenance),
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct CommuterPass {
+    pub from_station: String,
+    pub to_station: String,
+    // 経由駅。表示順のまま
+    pub via: Vec<String>,
+    pub valid_from: NaiveDate,
+    pub valid_until: NaiveDate,
+    // 通勤定期(6ヶ月) など
+    pub pass_type: String,
+}
+
+impl CommuterPass {
+    pub fn is_valid_on(&self, date: NaiveDate) -> bool {
+        self.valid_from <= date && date <= self.valid_until
+    }
+
+    // 期限までの日数。期限当日は0、切れている場合は負
+    pub fn days_until_expiry(&self, today: NaiveDate) -> i64 {
+        (self.valid_until - today).num_days()
+    }
+
+    pub fn is_expiring(&self, today: NaiveDate) -> bool {
+        (0..=EXPIRY_WARNING_DAYS).contains(&self.days_until_expiry(today))
+    }
+
+    // 区間の端と経由駅
+    fn stations(&self) -> impl Iterator<Item = &String> {
+        std::iter::once(&self.from_station)
+            .chain(self.via.iter())
+            .chain(std::iter::once(&self.to_station))
+    }
+
+    // 有効期間内に、入場駅と出場駅がどちらも定期券の区間に含まれる乗車
+    // 経路は判定できないため、区間の端と経由駅のみで判断する
+    // 区間の途中の駅（東京〜新宿の四ツ谷など）で乗り降りした場合は含まれないものとして扱う
+    // 駅名は駅の表で正規化できた場合はその駅名で比べる（「JR東 東京」は東京）
+    pub fn covers_on(&self, record: &UsageRecord, date: NaiveDate) -> bool {
+        let is_on_route =
+            |place: &str| !place.is_empty() && self.stations().any(|station| station == place);
+
+        record.entry_kind == "入"
+            && record.exit_kind == "出"
+            && self.is_valid_on(date)
+            && is_on_route(record.entry_station.as_ref().unwrap_or(&record.entry_place))
+            && is_on_route(record.exit_station.as_ref().unwrap_or(&record.exit_place))
+    }
+
+    // 利用日は履歴の月/日にtodayを基準に年を補ったもの
+    pub fn covers(&self, record: &UsageRecord, today: NaiveDate) -> bool {
+        record
+            .infer_date(today)
+            .is_some_and(|date| self.covers_on(record, date))
+    }
+}
+
+fn split_via(text: &str) -> Vec<String> {
+    text.split(VIA_SEPARATORS)
+        .map(str::trim)
+        .filter(|station| !station.is_empty() && *station != "-")
+        .map(str::to_string)
+        .collect()
+}
+
+// 定期券情報のページを読み取る。定期券が登録されていない場合はNone
+pub fn parse_commuter_pass(html: &str) -> Option<CommuterPass> {
+    let document = HtmlDocument::new(html);
+    let fields = collect_fields(&document);
+
+    let (from_station, to_station) = split_section(find_field(&fields, "区間")?)?;
+
+    let (valid_from, valid_until) = split_section(find_field(&fields, "有効期間")?)?;
+
+    Some(CommuterPass {
+        from_station,
+        to_station,
+        via: find_field(&fields, "経由")
+            .map(|via| split_via(via))
+            .unwrap_or_default(),
+        valid_from: parse_date(&valid_from)?,
+        valid_until: parse_date(&valid_until)?,
+        pass_type: find_field(&fields, "種別").cloned().unwrap_or_default(),
+    })
+}
+
+// ログイン済みのクライアントで定期券情報を取得する
+pub async fn fetch_commuter_pass(
+    client: &reqwest::Client,
+) -> Result<Option<CommuterPass>, CommuterPassError> {
+    let url = format!("{}{}", BASE_URL, COMMUTER_PASS_PATH);
+
+    let html = async {
+        let response = client.get(url).send().await?;
+
+        get_text(response).await
+    }
+    .await
+    .map_err(|_| CommuterPassError::RequestFailed)?;
+
+    if let Some(maintenance) = detect_maintenance(&html) {
+        return Err(CommuterPassError::Maintenance(maintenance));
+    }
+
+    // 定期券がない場合も同じページに案内が表示される
+    if !get_title(&html).contains("定期券") {
+        return Err(CommuterPassError::SessionExpired);
+    }
+
+    Ok(parse_commuter_pass(&html))
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::*;
+    use crate::libs::usage_history::ride;
+
+    fn date(year: i32, month: u32, day: u32) -> NaiveDate {
+        NaiveDate::from_ymd_opt(year, month, day).unwrap()
+    }
+
+    fn commuter_pass() -> CommuterPass {
+        parse_commuter_pass(include_str!("../../test/commuter_pass.html")).unwrap()
+    }
+
+    #[test]
+    fn test_parse_commuter_pass() {
+        assert_eq!(
+            commuter_pass(),
+            CommuterPass {
+                from_station: "東京".to_string(),
+                to_station: "新宿".to_string(),
+                via: vec!["神田".to_string(), "御茶ノ水".to_string()],
+                valid_from: date(2023, 9, 1),
+                valid_until: date(2024, 2, 29),
+                pass_type: "通勤定期(6ヶ月)".to_string(),
+            }
+        );
+    }
+
+    #[test]
+    fn test_parse_commuter_pass_empty() {
+        let html = "<html><head><title>JR東日本：モバイルSuica＞定期券情報</title></head>\
+                    <body><p>定期券の登録はありません。</p></body></html>";
+
+        assert_eq!(parse_commuter_pass(html), None);
+        assert_eq!(
+            parse_commuter_pass(include_str!("../../test/login.html")),
+            None
+        );
+    }
+
+    #[test]
+    fn test_expiry() {
+        let commuter_pass = commuter_pass();
+
+        assert!(commuter_pass.is_valid_on(date(2023, 9, 1)));
+        assert!(!commuter_pass.is_valid_on(date(2024, 3, 1)));
+
+        assert_eq!(commuter_pass.days_until_expiry(date(2024, 2, 15)), 14);
+        assert!(commuter_pass.is_expiring(date(2024, 2, 15)));
+        assert!(!commuter_pass.is_expiring(date(2024, 2, 14)));
+        assert!(!commuter_pass.is_expiring(date(2024, 3, 1)));
+    }
+
+    #[test]
+    fn test_covers() {
+        let commuter_pass = commuter_pass();
+        let today = date(2023, 9, 20);
+
+        assert!(commuter_pass.covers(&ride("09/16", "東京", "新宿", -208), today));
+        assert!(commuter_pass.covers(&ride("09/16", "御茶ノ水", "東京", -178), today));
+        assert!(!commuter_pass.covers(&ride("09/16", "東京", "横浜", -483), today));
+        // 区間の途中の駅は経由駅でなければ含まれない
+        assert!(!commuter_pass.covers(&ride("09/16", "東京", "四ツ谷", -178), today));
+
+        // 有効期間の前後の乗車は含まれない
+        let before = UsageRecord {
+            date: "08/31".to_string(),
+            ..ride("09/16", "東京", "新宿", -208)
+        };
+        assert!(!commuter_pass.covers(&before, today));
+        assert!(!commuter_pass.covers(&ride("09/16", "東京", "新宿", -208), date(2024, 9, 20)));
+
+        let purchase = UsageRecord {
+            entry_kind: "物販".to_string(),
+            ..Default::default()
+        };
+
+        assert!(!commuter_pass.covers(&purchase, today));
+    }
+
+    #[test]
+    fn test_covers_normalized_station() {
+        let commuter_pass = commuter_pass();
+        let today = date(2023, 9, 20);
+
+        let record = ride("09/16", "JR東 東京", "JR東 御茶ノ水", -178);
+
+        assert!(commuter_pass.covers(&record, today));
+
+        // 駅の表で正規化できなければ、表示されたままの駅名では区間に含まれない
+        let unresolved = UsageRecord {
+            entry_station: None,
+            exit_station: None,
+            ..record.clone()
+        };
+
+        assert!(!commuter_pass.covers(&unresolved, today));
+        assert!(commuter_pass.covers_on(&record, date(2023, 9, 16)));
+        assert!(!commuter_pass.covers_on(&record, date(2024, 3, 1)));
+    }
+}
@@ -0,0 +1,499 @@
+use std::collections::HashMap;
+use std::env;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+
+// env / file:<パス> / secret-service
+const CREDENTIALS_ENV: &str = "MOBILESUICA_CREDENTIALS";
+const EMAIL_ENV: &str = "MOBILESUICA_EMAIL";
+const PASSWORD_ENV: &str = "MOBILESUICA_PASSWORD";
+// サーバーでは複数の利用者がいるため、MOBILESUICA_CREDENTIALSの利用者を/auth/autoでログインさせるには明示的な許可が要る
+const SHARED_CREDENTIALS_ENV: &str = "MOBILESUICA_SHARED_CREDENTIALS";
+
+// Secret Serviceで検索する属性。secret-tool store --label=mobilesuica service mobilesuica username <メールアドレス>
+const SECRET_SERVICE_ATTRIBUTE: (&str, &str) = ("service", "mobilesuica");
+const SECRET_SERVICE_USERNAME: &str = "username";
+
+#[derive(Clone, PartialEq)]
+pub struct Credentials {
+    pub email: String,
+    pub password: String,
+}
+
+// ログにパスワードが出ないようにする
+impl fmt::Debug for Credentials {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Credentials")
+            .field("email", &self.email)
+            .field("password", &"********")
+            .finish()
+    }
+}
+
+#[derive(Debug)]
+pub enum CredentialsError {
+    NotFound,
+    ReadFailed(io::Error),
+    // グループや他人が読める。値はパーミッション
+    InsecurePermissions(u32),
+    InvalidFormat,
+    SecretService(String),
+    UnknownProvider(String),
+    SharedCredentialsNotAllowed,
+}
+
+pub fn get_credentials_error_message(error: &CredentialsError) -> String {
+    match error {
+        CredentialsError::NotFound => "ログイン情報が見つかりません。".to_string(),
+        CredentialsError::ReadFailed(e) => format!("ログイン情報を読み込めません: {}", e),
+        CredentialsError::InsecurePermissions(mode) => format!(
+            "ログイン情報のファイルは所有者だけが読めるようにしてください（現在: {:o}）。",
+            mode
+        ),
+        CredentialsError::InvalidFormat => {
+            "ログイン情報のファイルにemailとpasswordを記入してください。".to_string()
+        }
+        CredentialsError::SecretService(e) => format!("Secret Serviceを利用できません: {}", e),
+        CredentialsError::UnknownProvider(name) => {
+            format!("不明なログイン情報の取得先です: {}", name)
+        }
+        CredentialsError::SharedCredentialsNotAllowed => format!(
+            "サーバーで{}を使うには、すべての利用者が/auth/autoでそのアカウントにログインできることを確認し、{}=1を設定してください。",
+            CREDENTIALS_ENV, SHARED_CREDENTIALS_ENV
+        ),
+    }
+}
+
+// 毎回フォームに入力しなくてもログインできるようにログイン情報を取得する
+pub trait CredentialsProvider: Send + Sync {
+    fn name(&self) -> &'static str;
+
+    fn credentials(&self) -> Result<Credentials, CredentialsError>;
+}
+
+// MOBILESUICA_EMAIL, MOBILESUICA_PASSWORD から読む
+#[derive(Debug, Default)]
+pub struct EnvProvider;
+
+impl CredentialsProvider for EnvProvider {
+    fn name(&self) -> &'static str {
+        "env"
+    }
+
+    fn credentials(&self) -> Result<Credentials, CredentialsError> {
+        let get_env = |key: &str| env::var(key).ok().filter(|value| !value.is_empty());
+
+        match (get_env(EMAIL_ENV), get_env(PASSWORD_ENV)) {
+            (Some(email), Some(password)) => Ok(Credentials { email, password }),
+            _ => Err(CredentialsError::NotFound),
+        }
+    }
+}
+
+// email=... と password=... を書いたファイルから読む。#で始まる行は無視する
+#[derive(Debug)]
+pub struct FileProvider {
+    path: PathBuf,
+}
+
+impl FileProvider {
+    pub fn new(path: impl AsRef<Path>) -> Self {
+        FileProvider {
+            path: path.as_ref().to_path_buf(),
+        }
+    }
+}
+
+#[cfg(unix)]
+fn check_permissions(metadata: &fs::Metadata) -> Result<(), CredentialsError> {
+    use std::os::unix::fs::PermissionsExt;
+
+    let mode = metadata.permissions().mode() & 0o777;
+
+    match mode & 0o077 {
+        0 => Ok(()),
+        _ => Err(CredentialsError::InsecurePermissions(mode)),
+    }
+}
+
+#[cfg(not(unix))]
+fn check_permissions(_metadata: &fs::Metadata) -> Result<(), CredentialsError> {
+    Ok(())
+}
+
+fn parse_credentials_file(text: &str) -> Result<Credentials, CredentialsError> {
+    let mut email = None;
+    let mut password = None;
+
+    for line in text.lines().map(str::trim) {
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        // パスワードに=が含まれることがあるので最初の=で分ける
+        match line.split_once('=') {
+            Some((key, value)) if key.trim() == "email" => email = Some(value.trim().to_string()),
+            Some((key, value)) if key.trim() == "password" => {
+                password = Some(value.trim().to_string())
+            }
+            _ => return Err(CredentialsError::InvalidFormat),
+        }
+    }
+
+    match (email, password) {
+        (Some(email), Some(password)) if !email.is_empty() && !password.is_empty() => {
+            Ok(Credentials { email, password })
+        }
+        _ => Err(CredentialsError::InvalidFormat),
+    }
+}
+
+impl CredentialsProvider for FileProvider {
+    fn name(&self) -> &'static str {
+        "file"
+    }
+
+    fn credentials(&self) -> Result<Credentials, CredentialsError> {
+        let metadata = fs::metadata(&self.path).map_err(|e| match e.kind() {
+            io::ErrorKind::NotFound => CredentialsError::NotFound,
+            _ => CredentialsError::ReadFailed(e),
+        })?;
+
+        check_permissions(&metadata)?;
+
+        let text = fs::read_to_string(&self.path).map_err(CredentialsError::ReadFailed)?;
+
+        parse_credentials_file(&text)
+    }
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct SecretItem {
+    pub attributes: HashMap<String, String>,
+    pub secret: Vec<u8>,
+}
+
+// Secret Serviceの検索部分。テストではMemorySecretStoreに差し替える
+pub trait SecretStore: Send + Sync {
+    fn search_items(
+        &self,
+        attributes: &HashMap<String, String>,
+    ) -> Result<Vec<SecretItem>, CredentialsError>;
+}
+
+// メモリ上の項目から属性が一致するものを返す
+#[derive(Debug, Default)]
+pub struct MemorySecretStore {
+    items: Vec<SecretItem>,
+}
+
+impl MemorySecretStore {
+    pub fn new(items: Vec<SecretItem>) -> Self {
+        MemorySecretStore { items }
+    }
+}
+
+impl SecretStore for MemorySecretStore {
+    fn search_items(
+        &self,
+        attributes: &HashMap<String, String>,
+    ) -> Result<Vec<SecretItem>, CredentialsError> {
+        Ok(self
+            .items
+            .iter()
+            .filter(|item| {
+                attributes
+                    .iter()
+                    .all(|(key, value)| item.attributes.get(key) == Some(value))
+            })
+            .cloned()
+            .collect())
+    }
+}
+
+// freedesktop Secret Service（GNOME Keyring, KWalletなど）から読む
+// メールアドレスはusername属性、パスワードは秘密の値として保存しておく
+pub struct SecretServiceProvider<S: SecretStore> {
+    store: S,
+    attributes: HashMap<String, String>,
+}
+
+impl<S: SecretStore> SecretServiceProvider<S> {
+    pub fn new(store: S) -> Self {
+        let (key, value) = SECRET_SERVICE_ATTRIBUTE;
+
+        SecretServiceProvider {
+            store,
+            attributes: HashMap::from([(key.to_string(), value.to_string())]),
+        }
+    }
+
+    // 複数のアカウントを保存している場合にメールアドレスで絞り込む
+    pub fn with_username(mut self, username: &str) -> Self {
+        self.attributes
+            .insert(SECRET_SERVICE_USERNAME.to_string(), username.to_string());
+        self
+    }
+}
+
+impl<S: SecretStore> CredentialsProvider for SecretServiceProvider<S> {
+    fn name(&self) -> &'static str {
+        "secret-service"
+    }
+
+    fn credentials(&self) -> Result<Credentials, CredentialsError> {
+        let items = self.store.search_items(&self.attributes)?;
+
+        let item = items.first().ok_or(CredentialsError::NotFound)?;
+
+        let email = item
+            .attributes
+            .get(SECRET_SERVICE_USERNAME)
+            .filter(|email| !email.is_empty())
+            .ok_or(CredentialsError::InvalidFormat)?;
+
+        let password =
+            String::from_utf8(item.secret.clone()).map_err(|_| CredentialsError::InvalidFormat)?;
+
+        Ok(Credentials {
+            email: email.clone(),
+            password,
+        })
+    }
+}
+
+#[cfg(feature = "secret-service")]
+pub use dbus::DbusSecretStore;
+
+#[cfg(feature = "secret-service")]
+mod dbus {
+    use super::{CredentialsError, SecretItem, SecretStore};
+    use std::collections::HashMap;
+    use zbus::blocking::{Connection, Proxy};
+    use zbus::zvariant::{OwnedObjectPath, OwnedValue, Value};
+
+    const DESTINATION: &str = "org.freedesktop.secrets";
+    const SERVICE_PATH: &str = "/org/freedesktop/secrets";
+    const SERVICE_INTERFACE: &str = "org.freedesktop.Secret.Service";
+    const ITEM_INTERFACE: &str = "org.freedesktop.Secret.Item";
+    const SESSION_INTERFACE: &str = "org.freedesktop.Secret.Session";
+
+    // セッションバスのSecret Serviceに接続する
+    #[derive(Debug, Default)]
+    pub struct DbusSecretStore;
+
+    fn to_error(error: zbus::Error) -> CredentialsError {
+        CredentialsError::SecretService(error.to_string())
+    }
+
+    fn read_item(
+        connection: &Connection,
+        session: &OwnedObjectPath,
+        path: OwnedObjectPath,
+    ) -> zbus::Result<SecretItem> {
+        let item = Proxy::new(connection, DESTINATION, path, ITEM_INTERFACE)?;
+
+        let attributes = item.get_property::<HashMap<String, String>>("Attributes")?;
+
+        // (session, parameters, value, content_type)
+        let (_, _, secret, _): (OwnedObjectPath, Vec<u8>, Vec<u8>, String) =
+            item.call("GetSecret", &(session,))?;
+
+        Ok(SecretItem { attributes, secret })
+    }
+
+    impl SecretStore for DbusSecretStore {
+        fn search_items(
+            &self,
+            attributes: &HashMap<String, String>,
+        ) -> Result<Vec<SecretItem>, CredentialsError> {
+            let connection = Connection::session().map_err(to_error)?;
+
+            let service = Proxy::new(&connection, DESTINATION, SERVICE_PATH, SERVICE_INTERFACE)
+                .map_err(to_error)?;
+
+            // ローカルのバス越しなので暗号化はしない
+            let (_, session): (OwnedValue, OwnedObjectPath) = service
+                .call("OpenSession", &("plain", Value::from("")))
+                .map_err(to_error)?;
+
+            let (unlocked, locked): (Vec<OwnedObjectPath>, Vec<OwnedObjectPath>) = service
+                .call("SearchItems", &(attributes,))
+                .map_err(to_error)?;
+
+            let items = unlocked
+                .into_iter()
+                .map(|path| read_item(&connection, &session, path))
+                .collect::<zbus::Result<Vec<SecretItem>>>()
+                .map_err(to_error);
+
+            if let Ok(session) = Proxy::new(&connection, DESTINATION, &session, SESSION_INTERFACE) {
+                let _ = session.call_method("Close", &());
+            }
+
+            // 無人で動かすのでロック解除のプロンプトは出さない
+            match items {
+                Ok(items) if items.is_empty() && !locked.is_empty() => Err(
+                    CredentialsError::SecretService("keyring is locked".to_string()),
+                ),
+                items => items,
+            }
+        }
+    }
+}
+
+fn secret_service_provider() -> Result<Box<dyn CredentialsProvider>, CredentialsError> {
+    #[cfg(feature = "secret-service")]
+    {
+        Ok(Box::new(SecretServiceProvider::new(DbusSecretStore)))
+    }
+
+    #[cfg(not(feature = "secret-service"))]
+    {
+        Err(CredentialsError::UnknownProvider(
+            "secret-service（secret-service featureを有効にしてビルドしてください）".to_string(),
+        ))
+    }
+}
+
+// MOBILESUICA_CREDENTIALSで指定された取得先。未設定の場合はNone
+pub fn credentials_provider_from_env(
+) -> Result<Option<Box<dyn CredentialsProvider>>, CredentialsError> {
+    let Some(value) = env::var(CREDENTIALS_ENV)
+        .ok()
+        .filter(|value| !value.is_empty())
+    else {
+        return Ok(None);
+    };
+
+    let provider: Box<dyn CredentialsProvider> = match value.as_str() {
+        "env" => Box::new(EnvProvider),
+        "secret-service" => secret_service_provider()?,
+        other => match other.strip_prefix("file:") {
+            Some(path) => Box::new(FileProvider::new(path)),
+            None => return Err(CredentialsError::UnknownProvider(other.to_string())),
+        },
+    };
+
+    Ok(Some(provider))
+}
+
+// MOBILESUICA_SHARED_CREDENTIALSが1またはtrueか
+pub fn shared_credentials_allowed() -> bool {
+    matches!(
+        env::var(SHARED_CREDENTIALS_ENV).as_deref(),
+        Ok("1") | Ok("true")
+    )
+}
+
+static CREDENTIALS_PROVIDER: OnceLock<Box<dyn CredentialsProvider>> = OnceLock::new();
+
+// サーバー起動時に一度だけ設定する
+pub fn set_credentials_provider(
+    provider: Box<dyn CredentialsProvider>,
+) -> Result<(), Box<dyn CredentialsProvider>> {
+    CREDENTIALS_PROVIDER.set(provider)
+}
+
+pub fn get_credentials_provider() -> Option<&'static dyn CredentialsProvider> {
+    CREDENTIALS_PROVIDER.get().map(|provider| provider.as_ref())
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::*;
+
+    #[test]
+    fn test_credentials_debug() {
+        let credentials = Credentials {
+            email: "test@example.com".to_string(),
+            password: "secret".to_string(),
+        };
+
+        let debug = format!("{:?}", credentials);
+
+        assert!(debug.contains("test@example.com"));
+        assert!(!debug.contains("secret"));
+    }
+
+    #[test]
+    fn test_parse_credentials_file() {
+        let credentials =
+            parse_credentials_file("# mobilesuica\nemail = test@example.com\npassword=pa=ss\n")
+                .unwrap();
+
+        assert_eq!(credentials.email, "test@example.com");
+        assert_eq!(credentials.password, "pa=ss");
+
+        assert!(matches!(
+            parse_credentials_file("email=test@example.com\n"),
+            Err(CredentialsError::InvalidFormat)
+        ));
+        assert!(matches!(
+            parse_credentials_file("test@example.com\npassword\n"),
+            Err(CredentialsError::InvalidFormat)
+        ));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_file_provider_permissions() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let path = env::temp_dir().join(format!("mobilesuica_credentials_{}", std::process::id()));
+        fs::write(&path, "email=test@example.com\npassword=password\n").unwrap();
+
+        let provider = FileProvider::new(&path);
+
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o644)).unwrap();
+        assert!(matches!(
+            provider.credentials(),
+            Err(CredentialsError::InsecurePermissions(0o644))
+        ));
+
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o600)).unwrap();
+        assert_eq!(provider.credentials().unwrap().password, "password");
+
+        fs::remove_file(&path).unwrap();
+
+        assert!(matches!(
+            provider.credentials(),
+            Err(CredentialsError::NotFound)
+        ));
+    }
+
+    fn secret_item(username: &str, secret: &str) -> SecretItem {
+        SecretItem {
+            attributes: HashMap::from([
+                ("service".to_string(), "mobilesuica".to_string()),
+                ("username".to_string(), username.to_string()),
+            ]),
+            secret: secret.as_bytes().to_vec(),
+        }
+    }
+
+    #[test]
+    fn test_secret_service_provider() {
+        let store = MemorySecretStore::new(vec![
+            secret_item("first@example.com", "first"),
+            secret_item("second@example.com", "second"),
+        ]);
+
+        let provider = SecretServiceProvider::new(store).with_username("second@example.com");
+        let credentials = provider.credentials().unwrap();
+
+        assert_eq!(credentials.email, "second@example.com");
+        assert_eq!(credentials.password, "second");
+
+        let provider = SecretServiceProvider::new(MemorySecretStore::default());
+
+        assert!(matches!(
+            provider.credentials(),
+            Err(CredentialsError::NotFound)
+        ));
+    }
+}
@@ -0,0 +1,156 @@
+use crate::libs::green_ticket::GreenTicket;
+use crate::libs::usage_history::UsageRecord;
+
+use chrono::NaiveDate;
+use std::cmp::Reverse;
+use std::io;
+
+// 利用履歴をCSVで書き出す。見出し行はUsageRecordのフィールド名
+pub fn write_usage_csv<W: io::Write>(records: &[UsageRecord], writer: W) -> csv::Result<()> {
+    let mut csv_writer = csv::Writer::from_writer(writer);
+
+    for record in records {
+        csv_writer.serialize(record)?;
+    }
+
+    csv_writer.flush()?;
+
+    Ok(())
+}
+
+pub fn write_usage_json<W: io::Write>(
+    records: &[UsageRecord],
+    writer: W,
+) -> serde_json::Result<()> {
+    serde_json::to_writer_pretty(writer, records)
+}
+
+// グリーン券の購入を利用履歴に混ぜる。使用済みのものだけを含める
+// 利用履歴と同じく新しい順で、同じ日付の場合は利用履歴を先にする
+// 月/日だけでは年をまたぐと順序が崩れるので、todayを基準に年を補って並べる
+pub fn merge_green_tickets(
+    records: &[UsageRecord],
+    tickets: &[GreenTicket],
+    today: NaiveDate,
+) -> Vec<UsageRecord> {
+    let mut merged = records
+        .iter()
+        .map(|record| (record.infer_date(today), record.clone()))
+        .collect::<Vec<(Option<NaiveDate>, UsageRecord)>>();
+
+    merged.extend(
+        tickets
+            .iter()
+            .filter(|ticket| ticket.is_reimbursable())
+            .map(|ticket| (Some(ticket.purchased_on), ticket.to_usage_record())),
+    );
+
+    // 安定ソートなので同じ日付の中では元の順序を保つ
+    merged.sort_by_key(|(date, _)| Reverse(*date));
+
+    merged.into_iter().map(|(_, record)| record).collect()
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::*;
+    use crate::libs::green_ticket::parse_green_tickets;
+
+    fn records() -> Vec<UsageRecord> {
+        vec![
+            UsageRecord {
+                date: "09/16".to_string(),
+                entry_kind: "入".to_string(),
+                entry_place: "東京".to_string(),
+                exit_kind: "出".to_string(),
+                exit_place: "新宿".to_string(),
+                balance: Some(1528),
+                amount: Some(-208),
+                ..Default::default()
+            },
+            UsageRecord {
+                date: "09/16".to_string(),
+                entry_kind: "物販".to_string(),
+                entry_place: "売店, 東京駅".to_string(),
+                balance: Some(1736),
+                ..Default::default()
+            },
+        ]
+    }
+
+    #[test]
+    fn test_write_usage_csv() {
+        let mut buffer = Vec::new();
+
+        write_usage_csv(&records(), &mut buffer).unwrap();
+
+        assert_eq!(
+            String::from_utf8(buffer).unwrap(),
+            "date,entry_kind,entry_place,exit_kind,exit_place,balance,amount,\
+             entry_station,entry_station_code,exit_station,exit_station_code,category,project\n\
+             09/16,入,東京,出,新宿,1528,-208,,,,,,\n\
+             09/16,物販,\"売店, 東京駅\",,,1736,,,,,,,\n"
+        );
+    }
+
+    #[test]
+    fn test_write_usage_json() {
+        let mut buffer = Vec::new();
+
+        write_usage_json(&records(), &mut buffer).unwrap();
+
+        let json: serde_json::Value = serde_json::from_slice(&buffer).unwrap();
+
+        assert_eq!(json[0]["entry_place"], "東京");
+        assert_eq!(json[1]["amount"], serde_json::Value::Null);
+    }
+
+    #[test]
+    fn test_merge_green_tickets() {
+        let tickets = parse_green_tickets(include_str!("../../test/green_tickets.html")).unwrap();
+        let today = NaiveDate::from_ymd_opt(2023, 9, 20).unwrap();
+
+        let merged = merge_green_tickets(&records(), &tickets, today);
+
+        let rows = merged
+            .iter()
+            .map(|record| (record.date.as_str(), record.entry_kind.as_str()))
+            .collect::<Vec<_>>();
+
+        // 未使用の09/15と払い戻した09/10のグリーン券は含めない
+        assert_eq!(
+            rows,
+            vec![("09/16", "入"), ("09/16", "物販"), ("09/16", "グリーン券")]
+        );
+    }
+
+    #[test]
+    fn test_merge_green_tickets_across_years() {
+        let tickets = parse_green_tickets(include_str!("../../test/green_tickets.html")).unwrap();
+        let ticket = GreenTicket {
+            purchased_on: NaiveDate::from_ymd_opt(2023, 12, 30).unwrap(),
+            ..tickets[0].clone()
+        };
+        let records = vec![
+            UsageRecord {
+                date: "01/05".to_string(),
+                ..records()[0].clone()
+            },
+            UsageRecord {
+                date: "12/28".to_string(),
+                ..records()[1].clone()
+            },
+        ];
+        let today = NaiveDate::from_ymd_opt(2024, 1, 10).unwrap();
+
+        let merged = merge_green_tickets(&records, &[ticket], today);
+
+        let dates = merged
+            .iter()
+            .map(|record| record.date.as_str())
+            .collect::<Vec<_>>();
+
+        assert_eq!(dates, vec!["01/05", "12/30", "12/28"]);
+    }
+}
@@ -0,0 +1,205 @@
+use crate::libs::http_client::{get_text, BASE_URL};
+use crate::libs::japanese_text::{element_text, parse_date, parse_yen, split_section};
+use crate::libs::maintenance::{detect_maintenance, Maintenance};
+use crate::libs::mobilesuica_login::get_title;
+use crate::libs::usage_history::UsageRecord;
+use crate::HtmlDocument;
+
+use chrono::NaiveDate;
+use scraper::{ElementRef, Selector};
+use serde::Serialize;
+
+// Suicaグリーン券購入履歴
+pub const GREEN_TICKET_PATH: &str = "iq/ir/SuicaDisp.aspx?returnId=SFRCMMEPC07";
+
+// 利用履歴に混ぜるときの種別
+pub const GREEN_TICKET_KIND: &str = "グリーン券";
+
+#[derive(Debug, PartialEq)]
+pub enum GreenTicketError {
+    RequestFailed,
+    // ログイン画面に戻された
+    SessionExpired,
+    Maintenance(Maintenance),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum GreenTicketStatus {
+    Unused,
+    Used,
+    Refunded,
+}
+
+impl GreenTicketStatus {
+    fn parse(text: &str) -> Option<Self> {
+        match text {
+            _ if text.contains("未使用") => Some(GreenTicketStatus::Unused),
+            _ if text.contains("使用済") => Some(GreenTicketStatus::Used),
+            _ if text.contains("払戻") => Some(GreenTicketStatus::Refunded),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct GreenTicket {
+    pub purchased_on: NaiveDate,
+    pub from_station: String,
+    pub to_station: String,
+    pub amount: Option<i64>,
+    pub status: GreenTicketStatus,
+}
+
+impl GreenTicket {
+    // 精算の対象は使用済みのものだけ。未使用のものは払い戻されることがある
+    pub fn is_reimbursable(&self) -> bool {
+        self.status == GreenTicketStatus::Used
+    }
+
+    // 利用履歴と同じ形にする。金額は支払いなので負
+    pub fn to_usage_record(&self) -> UsageRecord {
+        let mut record = UsageRecord {
+            date: self.purchased_on.format("%m/%d").to_string(),
+            entry_kind: GREEN_TICKET_KIND.to_string(),
+            entry_place: self.from_station.clone(),
+            exit_kind: String::new(),
+            exit_place: self.to_station.clone(),
+            balance: None,
+            amount: self.amount.map(|amount| -amount),
+            ..Default::default()
+        };
+
+        record.resolve_stations();
+
+        record
+    }
+}
+
+fn parse_row(row: &ElementRef) -> Option<GreenTicket> {
+    let cell_selector = Selector::parse("td").unwrap();
+
+    let cells = row
+        .select(&cell_selector)
+        .map(|cell| element_text(&cell))
+        .collect::<Vec<String>>();
+
+    // 見出し行は日付として読めないので読み飛ばす
+    let [purchased_on, section, amount, status] = cells.as_slice() else {
+        return None;
+    };
+
+    let (from_station, to_station) = split_section(section)?;
+
+    Some(GreenTicket {
+        purchased_on: parse_date(purchased_on)?,
+        from_station,
+        to_station,
+        amount: parse_yen(amount),
+        status: GreenTicketStatus::parse(status)?,
+    })
+}
+
+// 購入履歴の表を読み取る。表がない場合はNone
+pub fn parse_green_tickets(html: &str) -> Option<Vec<GreenTicket>> {
+    let document = HtmlDocument::new(html);
+    let row_selector = Selector::parse("tr").unwrap();
+
+    // 購入日と状態の見出しがある一番内側の表
+    let table = document
+        .query_selector_all("table")?
+        .into_iter()
+        .filter(|table| {
+            let text = table.text().collect::<String>();
+            text.contains("購入日") && text.contains("状態")
+        })
+        .last()?;
+
+    Some(
+        table
+            .select(&row_selector)
+            .filter_map(|row| parse_row(&row))
+            .collect(),
+    )
+}
+
+// ログイン済みのクライアントでグリーン券の購入履歴を取得する
+pub async fn fetch_green_tickets(
+    client: &reqwest::Client,
+) -> Result<Vec<GreenTicket>, GreenTicketError> {
+    let url = format!("{}{}", BASE_URL, GREEN_TICKET_PATH);
+
+    let html = async {
+        let response = client.get(url).send().await?;
+
+        get_text(response).await
+    }
+    .await
+    .map_err(|_| GreenTicketError::RequestFailed)?;
+
+    if let Some(maintenance) = detect_maintenance(&html) {
+        return Err(GreenTicketError::Maintenance(maintenance));
+    }
+
+    // ログイン画面に戻されたかは本文ではなくタイトルで判定する
+    if !get_title(&html).contains("グリーン券") {
+        return Err(GreenTicketError::SessionExpired);
+    }
+
+    // 購入履歴がない場合は表がなく案内だけが表示される
+    Ok(parse_green_tickets(&html).unwrap_or_default())
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::*;
+
+    #[test]
+    fn test_parse_green_tickets() {
+        let tickets = parse_green_tickets(include_str!("../../test/green_tickets.html")).unwrap();
+
+        assert_eq!(tickets.len(), 3);
+        assert_eq!(
+            tickets[0],
+            GreenTicket {
+                purchased_on: NaiveDate::from_ymd_opt(2023, 9, 16).unwrap(),
+                from_station: "東京".to_string(),
+                to_station: "大宮".to_string(),
+                amount: Some(1000),
+                status: GreenTicketStatus::Used,
+            }
+        );
+        assert_eq!(tickets[1].status, GreenTicketStatus::Unused);
+        assert_eq!(tickets[2].status, GreenTicketStatus::Refunded);
+        assert!(tickets[0].is_reimbursable());
+        assert!(!tickets[1].is_reimbursable());
+        assert!(!tickets[2].is_reimbursable());
+
+        assert_eq!(
+            parse_green_tickets(include_str!("../../test/login.html")),
+            None
+        );
+    }
+
+    #[test]
+    fn test_to_usage_record() {
+        let tickets = parse_green_tickets(include_str!("../../test/green_tickets.html")).unwrap();
+
+        assert_eq!(
+            tickets[1].to_usage_record(),
+            UsageRecord {
+                date: "09/15".to_string(),
+                entry_kind: "グリーン券".to_string(),
+                entry_place: "新宿".to_string(),
+                exit_place: "横浜".to_string(),
+                amount: Some(-780),
+                entry_station: Some("新宿".to_string()),
+                entry_station_code: Some("JY17".to_string()),
+                exit_station: Some("横浜".to_string()),
+                exit_station_code: Some("JK12".to_string()),
+                ..Default::default()
+            }
+        );
+    }
+}
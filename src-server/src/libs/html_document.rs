@@ -34,6 +34,27 @@ impl HtmlDocument {
     pub fn get_element_by_id<'a>(&'a self, id: &'a str) -> Option<ElementRef<'a>> {
         self.query_selector(&format!("#{}", id))
     }
+
+    // 画面に表示されるテキスト（script, styleの中身を除く）
+    pub fn text_content(&self) -> String {
+        self.document
+            .root_element()
+            .descendants()
+            .filter_map(|node| {
+                let text = node.value().as_text()?;
+
+                let is_hidden = node
+                    .parent()
+                    .and_then(|parent| parent.value().as_element().map(|e| e.name()))
+                    .is_some_and(|name| matches!(name, "script" | "style" | "noscript"));
+
+                match is_hidden {
+                    true => None,
+                    false => Some(text.to_string()),
+                }
+            })
+            .collect::<String>()
+    }
 }
 
 #[cfg(test)]
@@ -123,4 +144,28 @@ mod test {
             "2"
         );
     }
+
+    #[test]
+    fn test_text_content() {
+        let html = r#"
+        <html>
+            <head>
+                <title>Test</title>
+                <style>.test { color: red; }</style>
+            </head>
+            <body>
+                <script>var msg = "hidden";</script>
+                <div>visible<span>text</span></div>
+            </body>
+        </html>
+    "#;
+
+        let document = HtmlDocument::new(html);
+        let text = document.text_content();
+
+        assert!(text.contains("Test"));
+        assert!(text.contains("visibletext"));
+        assert!(!text.contains("hidden"));
+        assert!(!text.contains("color"));
+    }
 }
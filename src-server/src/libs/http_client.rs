@@ -1,32 +1,208 @@
-use reqwest::cookie;
-use std::{collections::HashMap, sync::Arc};
-use url::Url;
+use crate::libs::charset::decode_body;
+use reqwest::{header::CONTENT_TYPE, Certificate, NoProxy, Proxy};
+use std::{
+    env,
+    path::Path,
+    sync::{Arc, OnceLock},
+};
 
-pub type MobilesuicaCookies = HashMap<String, String>;
+pub use crate::libs::mobilesuica_cookies::{MobilesuicaCookies, StoredCookie};
 pub const BASE_URL: &str = "https://www.mobilesuica.com/";
 
-pub async fn get_client(cookies: MobilesuicaCookies) -> Result<reqwest::Client, reqwest::Error> {
-    let cookie_store = Arc::new(cookie::Jar::default());
+const PROXY_ENV: &str = "MOBILESUICA_PROXY";
+const NO_PROXY_ENV: &str = "MOBILESUICA_NO_PROXY";
+const CA_CERT_ENV: &str = "MOBILESUICA_CA_CERT";
 
-    for (name, value) in cookies {
-        cookie_store.add_cookie_str(
-            &format!("{}={}", name, value),
-            &Url::parse(BASE_URL).unwrap(),
-        );
+static CLIENT_CONFIG: OnceLock<ClientConfig> = OnceLock::new();
+
+#[derive(Debug)]
+pub enum ClientConfigError {
+    CaCertReadFailed(std::io::Error),
+    CaCertInvalid(reqwest::Error),
+    ProxyInvalid(reqwest::Error),
+}
+
+// 社内ネットワーク向けのプロキシ・追加ルート証明書の設定
+#[derive(Clone, Debug, Default)]
+pub struct ClientConfig {
+    pub proxy: Option<String>,
+    pub no_proxy: Option<String>,
+    pub root_certificates: Vec<Certificate>,
+}
+
+// PEMファイルに含まれる証明書を1つずつに分割する
+fn split_pem_certificates(pem: &str) -> Vec<String> {
+    const END_MARKER: &str = "-----END CERTIFICATE-----";
+
+    pem.split_inclusive(END_MARKER)
+        .filter(|block| block.contains("-----BEGIN CERTIFICATE-----"))
+        .map(|block| block.trim().to_string())
+        .collect()
+}
+
+impl ClientConfig {
+    pub fn from_env() -> Result<Self, ClientConfigError> {
+        let get_env = |key: &str| env::var(key).ok().filter(|value| !value.is_empty());
+
+        let mut config = ClientConfig {
+            proxy: get_env(PROXY_ENV),
+            no_proxy: get_env(NO_PROXY_ENV),
+            root_certificates: vec![],
+        };
+
+        // 誤ったプロキシは最初のリクエストではなく起動時に知らせる
+        if let Some(proxy) = &config.proxy {
+            Proxy::all(proxy).map_err(ClientConfigError::ProxyInvalid)?;
+        }
+
+        if let Some(path) = get_env(CA_CERT_ENV) {
+            config.load_ca_cert(path)?;
+        }
+
+        Ok(config)
     }
 
+    pub fn load_ca_cert<P: AsRef<Path>>(
+        &mut self,
+        path: P,
+    ) -> Result<&mut Self, ClientConfigError> {
+        let pem = std::fs::read_to_string(path).map_err(ClientConfigError::CaCertReadFailed)?;
+
+        for block in split_pem_certificates(&pem) {
+            let certificate = Certificate::from_pem(block.as_bytes())
+                .map_err(ClientConfigError::CaCertInvalid)?;
+
+            self.root_certificates.push(certificate);
+        }
+
+        Ok(self)
+    }
+
+    fn apply(
+        &self,
+        builder: reqwest::ClientBuilder,
+    ) -> Result<reqwest::ClientBuilder, reqwest::Error> {
+        let builder = match &self.proxy {
+            Some(proxy_url) => {
+                let no_proxy = self.no_proxy.as_deref().and_then(NoProxy::from_string);
+                builder.proxy(Proxy::all(proxy_url)?.no_proxy(no_proxy))
+            }
+            None => builder,
+        };
+
+        Ok(self
+            .root_certificates
+            .iter()
+            .cloned()
+            .fold(builder, |builder, certificate| {
+                builder.add_root_certificate(certificate)
+            }))
+    }
+}
+
+// サーバー起動時に一度だけ設定する。未設定の場合は環境変数から読み込む
+// 環境変数の設定が誤っている場合は、既定の設定で接続せずに停止する
+pub fn set_client_config(config: ClientConfig) -> Result<(), ClientConfig> {
+    CLIENT_CONFIG.set(config)
+}
+
+fn get_client_config<'a>() -> &'a ClientConfig {
+    CLIENT_CONFIG
+        .get_or_init(|| ClientConfig::from_env().expect("failed to load http client config"))
+}
+
+// 渡したCookie Jarはレスポンス（リダイレクト含む）のSet-Cookieで更新される
+pub async fn get_client(
+    cookies: Arc<MobilesuicaCookies>,
+) -> Result<reqwest::Client, reqwest::Error> {
+    get_client_with_config(cookies, get_client_config()).await
+}
+
+pub async fn get_client_with_config(
+    cookies: Arc<MobilesuicaCookies>,
+    config: &ClientConfig,
+) -> Result<reqwest::Client, reqwest::Error> {
     let ua = "Mozilla/5.0 (Windows NT 10.0; Win64; x64) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/115.0.0.0 Safari/537.36";
-    let client = reqwest::Client::builder()
+    let builder = reqwest::Client::builder()
         .user_agent(ua)
-        .cookie_provider(cookie_store)
-        .build()?;
+        .cookie_provider(cookies);
+
+    let client = config.apply(builder)?.build()?;
 
     Ok(client)
 }
 
-pub fn get_cookies(response: &reqwest::Response) -> MobilesuicaCookies {
-    response
-        .cookies()
-        .map(|cookie| (cookie.name().to_string(), cookie.value().to_string()))
-        .collect::<HashMap<_, _>>()
+// Content-Typeや<meta charset>から文字コードを判定してボディを文字列にする
+pub async fn get_text(response: reqwest::Response) -> Result<String, reqwest::Error> {
+    let content_type = response
+        .headers()
+        .get(CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .map(|value| value.to_string());
+
+    let bytes = response.bytes().await?;
+
+    Ok(decode_body(&bytes, content_type.as_deref()))
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::*;
+
+    #[test]
+    fn test_split_pem_certificates() {
+        let pem = include_str!("../../test/ca.pem");
+
+        let certificates = split_pem_certificates(pem);
+
+        assert_eq!(certificates.len(), 2);
+
+        for certificate in certificates {
+            assert!(certificate.starts_with("-----BEGIN CERTIFICATE-----"));
+            assert!(certificate.ends_with("-----END CERTIFICATE-----"));
+        }
+
+        assert!(split_pem_certificates("").is_empty());
+    }
+
+    #[test]
+    fn test_client_config_load_ca_cert() {
+        let mut config = ClientConfig::default();
+
+        config
+            .load_ca_cert(concat!(env!("CARGO_MANIFEST_DIR"), "/test/ca.pem"))
+            .unwrap();
+
+        assert_eq!(config.root_certificates.len(), 2);
+
+        let result = ClientConfig::default().load_ca_cert("not_found.pem").err();
+
+        assert!(matches!(
+            result,
+            Some(ClientConfigError::CaCertReadFailed(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_get_client_with_config() {
+        let mut config = ClientConfig {
+            proxy: Some("http://proxy.example.com:8080".to_string()),
+            no_proxy: Some("localhost,127.0.0.1".to_string()),
+            root_certificates: vec![],
+        };
+        config
+            .load_ca_cert(concat!(env!("CARGO_MANIFEST_DIR"), "/test/ca.pem"))
+            .unwrap();
+
+        assert!(get_client_with_config(Arc::default(), &config)
+            .await
+            .is_ok());
+
+        config.proxy = Some("not a url".to_string());
+
+        assert!(get_client_with_config(Arc::default(), &config)
+            .await
+            .is_err());
+    }
 }
@@ -0,0 +1,110 @@
+use image::io::Reader;
+use serde::Serialize;
+use std::io::Cursor;
+
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct ImageInfo {
+    pub mime_type: String,
+    pub width: u32,
+    pub height: u32,
+}
+
+// 先頭のマジックバイトから画像のMIMEタイプを判定する
+pub fn sniff_mime_type(bytes: &[u8]) -> Option<&'static str> {
+    image::guess_format(bytes)
+        .ok()
+        .map(|format| format.to_mime_type())
+}
+
+// 画像ヘッダーから幅と高さを読み取る。画像全体はデコードしない
+// 有効にしているデコーダー（GIF, PNG, BMP, WebP）の形式のみ
+pub fn image_dimensions(bytes: &[u8]) -> Option<(u32, u32)> {
+    Reader::new(Cursor::new(bytes))
+        .with_guessed_format()
+        .ok()?
+        .into_dimensions()
+        .ok()
+}
+
+pub fn image_info(bytes: &[u8]) -> Option<ImageInfo> {
+    let mime_type = sniff_mime_type(bytes)?;
+    let (width, height) = image_dimensions(bytes)?;
+
+    Some(ImageInfo {
+        mime_type: mime_type.to_string(),
+        width,
+        height,
+    })
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::*;
+
+    // 論理画面サイズが175x60の透過GIF
+    const GIF: [u8; 43] = [
+        0x47, 0x49, 0x46, 0x38, 0x39, 0x61, 0xAF, 0x00, 0x3C, 0x00, 0x80, 0x00, 0x00, 0x00, 0x00,
+        0x00, 0xFF, 0xFF, 0xFF, 0x21, 0xF9, 0x04, 0x01, 0x00, 0x00, 0x00, 0x00, 0x2C, 0x00, 0x00,
+        0x00, 0x00, 0x01, 0x00, 0x01, 0x00, 0x00, 0x02, 0x02, 0x44, 0x01, 0x00, 0x3B,
+    ];
+
+    #[test]
+    fn test_sniff_mime_type() {
+        assert_eq!(sniff_mime_type(&GIF), Some("image/gif"));
+        assert_eq!(
+            sniff_mime_type(&[0x89, b'P', b'N', b'G', 0x0D, 0x0A, 0x1A, 0x0A]),
+            Some("image/png")
+        );
+        assert_eq!(
+            sniff_mime_type(&[0xFF, 0xD8, 0xFF, 0xE0]),
+            Some("image/jpeg")
+        );
+        assert_eq!(sniff_mime_type(b"RIFF\0\0\0\0WEBPVP8 "), Some("image/webp"));
+        assert_eq!(sniff_mime_type(b"<html>"), None);
+        assert_eq!(sniff_mime_type(&[]), None);
+    }
+
+    #[test]
+    fn test_image_dimensions_gif() {
+        assert_eq!(image_dimensions(&GIF), Some((175, 60)));
+        assert_eq!(image_dimensions(&GIF[..8]), None);
+    }
+
+    #[test]
+    fn test_image_dimensions_png() {
+        let image = image::GrayImage::new(350, 120);
+        let mut png = Cursor::new(vec![]);
+        image
+            .write_to(&mut png, image::ImageOutputFormat::Png)
+            .unwrap();
+
+        assert_eq!(image_dimensions(png.get_ref()), Some((350, 120)));
+        // IHDRの途中で切れている
+        assert_eq!(image_dimensions(&png.get_ref()[..20]), None);
+    }
+
+    #[test]
+    fn test_image_dimensions_bmp() {
+        let image = image::RgbImage::new(175, 60);
+        let mut bmp = Cursor::new(vec![]);
+        image
+            .write_to(&mut bmp, image::ImageOutputFormat::Bmp)
+            .unwrap();
+
+        assert_eq!(image_dimensions(bmp.get_ref()), Some((175, 60)));
+    }
+
+    #[test]
+    fn test_image_info() {
+        assert_eq!(
+            image_info(&GIF),
+            Some(ImageInfo {
+                mime_type: "image/gif".to_string(),
+                width: 175,
+                height: 60,
+            })
+        );
+        assert_eq!(image_info(b"not an image"), None);
+    }
+}
@@ -0,0 +1,215 @@
+use chrono::NaiveDate;
+use scraper::ElementRef;
+use unicode_normalization::UnicodeNormalization;
+
+// 区間や期間の区切り。全角の～は正規化で半角になる
+const SECTION_SEPARATORS: [char; 2] = ['〜', '~'];
+
+// 元号と元年の前年（西暦 = 前年 + 元号の年）
+const ERAS: [(&str, i32); 3] = [("令和", 2018), ("平成", 1988), ("昭和", 1925)];
+
+// NFKCで全角英数字・半角カナ・全角空白をそろえ、連続する空白を1つにして前後を取り除く
+pub fn normalize_text(text: &str) -> String {
+    text.nfkc()
+        .collect::<String>()
+        .split_whitespace()
+        .collect::<Vec<&str>>()
+        .join(" ")
+}
+
+// HtmlDocumentで取り出した要素のテキストを正規化する
+pub fn element_text(element: &ElementRef) -> String {
+    normalize_text(&element.text().collect::<String>())
+}
+
+// ￥1,234 や \1,234、+1,000、-208、1,234円 を数値にする
+// 最初に現れる数字のまとまりを金額とみなす
+pub fn parse_yen(text: &str) -> Option<i64> {
+    let text = normalize_text(text);
+
+    let start = text.find(|c: char| c.is_ascii_digit())?;
+    let negative = text[..start]
+        .trim_end_matches(['¥', '\\', ' '])
+        .ends_with(['-', '−', '▲']);
+
+    let digits = text[start..]
+        .chars()
+        .take_while(|c| c.is_ascii_digit() || *c == ',')
+        .filter(|c| c.is_ascii_digit())
+        .collect::<String>();
+
+    let value = digits.parse::<i64>().ok()?;
+
+    match negative {
+        true => Some(-value),
+        false => Some(value),
+    }
+}
+
+// ￥1,234 のように3桁ごとに区切る
+pub fn format_yen(amount: i64) -> String {
+    let digits = amount.abs().to_string();
+    let mut formatted = String::new();
+
+    for (index, digit) in digits.chars().enumerate() {
+        if index > 0 && (digits.len() - index).is_multiple_of(3) {
+            formatted.push(',');
+        }
+        formatted.push(digit);
+    }
+
+    match amount < 0 {
+        true => format!("-￥{}", formatted),
+        false => format!("￥{}", formatted),
+    }
+}
+
+fn parse_numbers(text: &str) -> Option<Vec<u32>> {
+    text.split(|c: char| !c.is_ascii_digit())
+        .filter(|part| !part.is_empty())
+        .map(|part| part.parse::<u32>().ok())
+        .collect()
+}
+
+// 令和5年9月16日 のような和暦を西暦にする。元年は1年
+fn replace_era(text: &str) -> String {
+    ERAS.iter()
+        .find_map(|(era, offset)| {
+            let rest = text.strip_prefix(era)?;
+            let rest = rest
+                .strip_prefix("元")
+                .map_or(rest.to_string(), |rest| format!("1{}", rest));
+            let end = rest.find(|c: char| !c.is_ascii_digit())?;
+            let year = rest[..end].parse::<i32>().ok()?;
+
+            Some(format!("{}{}", offset + year, &rest[end..]))
+        })
+        .unwrap_or(text.to_string())
+}
+
+// 2020年4月1日、2020/04/01、令和2年4月1日 を日付にする
+pub fn parse_date(text: &str) -> Option<NaiveDate> {
+    let text = replace_era(&normalize_text(text));
+
+    match parse_numbers(&text)?.as_slice() {
+        [year, month, day] => NaiveDate::from_ymd_opt(*year as i32, *month, *day),
+        _ => None,
+    }
+}
+
+// 利用履歴の 09/15 や 9月15日 を月と日にする
+pub fn parse_month_day(text: &str) -> Option<(u32, u32)> {
+    let text = normalize_text(text);
+
+    match parse_numbers(&text)?.as_slice() {
+        [month, day] if (1..=12).contains(month) && (1..=31).contains(day) => Some((*month, *day)),
+        _ => None,
+    }
+}
+
+// 東京〜新宿 や 2023/09/01〜2024/02/29 を前後に分ける。どちらかが空の場合はNone
+pub fn split_section(text: &str) -> Option<(String, String)> {
+    let (from, to) = text.split_once(SECTION_SEPARATORS)?;
+    let (from, to) = (from.trim(), to.trim());
+
+    match from.is_empty() || to.is_empty() {
+        true => None,
+        false => Some((from.to_string(), to.to_string())),
+    }
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::*;
+    use proptest::prelude::*;
+
+    #[test]
+    fn test_normalize_text() {
+        assert_eq!(normalize_text("　ｶｰﾄﾞ　"), "カード");
+        assert_eq!(normalize_text("ＪＲ東\u{00A0}\u{00A0}東京"), "JR東 東京");
+        assert_eq!(
+            normalize_text("モバイルSuica（記名式）"),
+            "モバイルSuica(記名式)"
+        );
+        assert_eq!(normalize_text("\n  \t"), "");
+    }
+
+    #[test]
+    fn test_parse_yen() {
+        assert_eq!(parse_yen("\\1,528"), Some(1528));
+        assert_eq!(parse_yen("￥１，２３４"), Some(1234));
+        assert_eq!(parse_yen("+1,000"), Some(1000));
+        assert_eq!(parse_yen("-208"), Some(-208));
+        assert_eq!(parse_yen("-￥1,000"), Some(-1000));
+        assert_eq!(parse_yen("－２０８円"), Some(-208));
+        assert_eq!(parse_yen("3,000円（税込）"), Some(3000));
+        assert_eq!(parse_yen(""), None);
+        assert_eq!(parse_yen("-"), None);
+    }
+
+    #[test]
+    fn test_format_yen() {
+        assert_eq!(format_yen(0), "￥0");
+        assert_eq!(format_yen(208), "￥208");
+        assert_eq!(format_yen(1234567), "￥1,234,567");
+        assert_eq!(format_yen(-1000), "-￥1,000");
+    }
+
+    #[test]
+    fn test_parse_date() {
+        assert_eq!(
+            parse_date("2020年4月1日"),
+            NaiveDate::from_ymd_opt(2020, 4, 1)
+        );
+        assert_eq!(
+            parse_date("２０２０/０４/０１"),
+            NaiveDate::from_ymd_opt(2020, 4, 1)
+        );
+        assert_eq!(
+            parse_date("令和5年9月16日"),
+            NaiveDate::from_ymd_opt(2023, 9, 16)
+        );
+        assert_eq!(
+            parse_date("令和元年5月1日"),
+            NaiveDate::from_ymd_opt(2019, 5, 1)
+        );
+        assert_eq!(parse_date("4月1日"), None);
+        assert_eq!(parse_date("2020年13月1日"), None);
+    }
+
+    #[test]
+    fn test_parse_month_day() {
+        assert_eq!(parse_month_day("09/15"), Some((9, 15)));
+        assert_eq!(parse_month_day("０９/１５"), Some((9, 15)));
+        assert_eq!(parse_month_day("9月15日"), Some((9, 15)));
+        assert_eq!(parse_month_day("13/01"), None);
+        assert_eq!(parse_month_day("繰"), None);
+    }
+
+    #[test]
+    fn test_split_section() {
+        assert_eq!(
+            split_section("東京 〜 新宿"),
+            Some(("東京".to_string(), "新宿".to_string()))
+        );
+        assert_eq!(
+            split_section(&normalize_text("品川～小田原")),
+            Some(("品川".to_string(), "小田原".to_string()))
+        );
+        assert_eq!(split_section("東京〜"), None);
+        assert_eq!(split_section("東京"), None);
+    }
+
+    proptest! {
+        #[test]
+        fn prop_parse_yen_roundtrip(value in -10_000_000i64..10_000_000) {
+            let formatted = format_yen(value.abs()).replace('￥', "");
+            let sign = if value < 0 { "-" } else { "" };
+
+            prop_assert_eq!(parse_yen(&format_yen(value)), Some(value));
+            prop_assert_eq!(parse_yen(&format!("{}\\{}", sign, formatted)), Some(value));
+            prop_assert_eq!(parse_yen(&format!("{}{}円", sign, formatted)), Some(value));
+        }
+    }
+}
@@ -0,0 +1,224 @@
+use crate::libs::japanese_text::format_yen;
+use crate::libs::stations::find_station_by_code;
+use crate::libs::usage_history::UsageRecord;
+
+use serde::Serialize;
+
+// 出場した駅で別の事業者に入場し直した乗り継ぎ
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct Transfer {
+    pub station: String,
+    pub from_company: String,
+    pub to_company: String,
+}
+
+// 1回の移動
+// 利用履歴には時刻がないため、乗り継ぎの間隔はわからない。朝と夜の乗車を1回の移動にしないよう、
+// 既定では乗車ごとに分け、乗り継ぎらしい場合はpossible_transferに印を付けるだけにする
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct Journey {
+    pub date: String,
+    pub from_station: String,
+    pub to_station: String,
+    // 各乗車の運賃の合計（正）
+    pub fare: i64,
+    // 乗り継ぎをまとめた場合のみ
+    pub transfers: Vec<Transfer>,
+    // 直前の移動から乗り継いだかもしれない場合、その乗り継ぎ
+    pub possible_transfer: Option<Transfer>,
+    // 乗った順
+    pub rides: Vec<UsageRecord>,
+}
+
+impl Journey {
+    // 新宿 → 横浜 ￥XXX の形の1行
+    pub fn summary(&self) -> String {
+        format!(
+            "{} → {} {}",
+            self.from_station,
+            self.to_station,
+            format_yen(self.fare)
+        )
+    }
+}
+
+fn is_ride(record: &UsageRecord) -> bool {
+    record.entry_kind == "入" || record.exit_kind == "出"
+}
+
+// 駅の表で正規化できた場合はその駅名
+fn entry_station(record: &UsageRecord) -> String {
+    record
+        .entry_station
+        .clone()
+        .unwrap_or(record.entry_place.clone())
+}
+
+fn exit_station(record: &UsageRecord) -> String {
+    record
+        .exit_station
+        .clone()
+        .unwrap_or(record.exit_place.clone())
+}
+
+fn company(code: &Option<String>) -> Option<String> {
+    code.as_deref()
+        .and_then(find_station_by_code)
+        .map(|station| station.company.clone())
+}
+
+// 前の乗車の出場から次の乗車の入場までが乗り継ぎかもしれないか
+// 同じ日に続けて乗り、出場した駅と同じ駅で別の事業者に入場した場合。時刻がないので間隔は見ていない
+fn find_transfer(previous: &UsageRecord, next: &UsageRecord) -> Option<Transfer> {
+    let station = exit_station(previous);
+
+    if previous.date != next.date || station.is_empty() || station != entry_station(next) {
+        return None;
+    }
+
+    let from_company = company(&previous.exit_station_code)?;
+    let to_company = company(&next.entry_station_code)?;
+
+    match from_company == to_company {
+        true => None,
+        false => Some(Transfer {
+            station,
+            from_company,
+            to_company,
+        }),
+    }
+}
+
+fn create_journey(
+    rides: Vec<UsageRecord>,
+    transfers: Vec<Transfer>,
+    possible_transfer: Option<Transfer>,
+) -> Journey {
+    let first = rides.first().unwrap();
+    let last = rides.last().unwrap();
+
+    Journey {
+        date: first.date.clone(),
+        from_station: entry_station(first),
+        to_station: exit_station(last),
+        fare: rides
+            .iter()
+            .filter_map(|ride| ride.amount)
+            .map(|amount| -amount)
+            .sum(),
+        transfers,
+        possible_transfer,
+        rides,
+    }
+}
+
+// 利用履歴（新しい順）の乗車を移動にする。物販やチャージの行は含めない
+// merge_transfersがfalseなら乗車ごとに1つの移動にし、乗り継ぎらしい移動にはpossible_transferを付ける
+// trueなら乗り継ぎらしい乗車を1つの移動にまとめる。利用者が同じ移動だと確かめた場合に使う
+// 結果も新しい順
+pub fn group_journeys(records: &[UsageRecord], merge_transfers: bool) -> Vec<Journey> {
+    let mut journeys = vec![];
+    let mut rides: Vec<UsageRecord> = vec![];
+    let mut transfers = vec![];
+    let mut possible_transfer = None;
+
+    for record in records.iter().rev().filter(|record| is_ride(record)) {
+        if let Some(previous) = rides.last() {
+            let transfer = find_transfer(previous, record);
+
+            match (transfer, merge_transfers) {
+                (Some(transfer), true) => transfers.push(transfer),
+                (transfer, _) => {
+                    journeys.push(create_journey(
+                        std::mem::take(&mut rides),
+                        std::mem::take(&mut transfers),
+                        possible_transfer.take(),
+                    ));
+                    possible_transfer = transfer;
+                }
+            }
+        }
+
+        rides.push(record.clone());
+    }
+
+    if !rides.is_empty() {
+        journeys.push(create_journey(rides, transfers, possible_transfer));
+    }
+
+    journeys.reverse();
+
+    journeys
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::*;
+    use crate::libs::usage_history::parse_usage_history;
+    use crate::libs::usage_history::ride;
+
+    #[test]
+    fn test_group_journeys_transfer() {
+        // 新しい順。JR新宿→渋谷から東急渋谷→横浜に乗り継いだ
+        let records = vec![
+            ride("09/16", "東急渋谷", "東急横浜", -280),
+            ride("09/16", "新宿", "渋谷", -170),
+        ];
+
+        let transfer = Transfer {
+            station: "渋谷".to_string(),
+            from_company: "JR東日本".to_string(),
+            to_company: "東急電鉄".to_string(),
+        };
+
+        // 時刻がないので既定ではまとめず、乗り継ぎかもしれないことだけを示す
+        let journeys = group_journeys(&records, false);
+
+        assert_eq!(journeys.len(), 2);
+        assert_eq!(journeys[0].summary(), "渋谷 → 横浜 ￥280");
+        assert_eq!(journeys[0].possible_transfer, Some(transfer.clone()));
+        assert_eq!(journeys[1].summary(), "新宿 → 渋谷 ￥170");
+        assert_eq!(journeys[1].possible_transfer, None);
+
+        let journeys = group_journeys(&records, true);
+
+        assert_eq!(journeys.len(), 1);
+        assert_eq!(journeys[0].summary(), "新宿 → 横浜 ￥450");
+        assert_eq!(journeys[0].transfers, vec![transfer]);
+        assert_eq!(journeys[0].possible_transfer, None);
+        assert_eq!(journeys[0].rides[0].entry_place, "新宿");
+    }
+
+    #[test]
+    fn test_group_journeys_separate() {
+        let records = vec![
+            // 同じ事業者で入場し直したのは別の移動
+            ride("09/16", "渋谷", "新宿", -170),
+            ride("09/16", "新宿", "渋谷", -170),
+            // 日付が違う
+            ride("09/15", "東急渋谷", "東急横浜", -280),
+            ride("09/14", "新宿", "渋谷", -170),
+        ];
+
+        let journeys = group_journeys(&records, true);
+
+        assert_eq!(journeys.len(), 4);
+        assert!(journeys.iter().all(|journey| journey.transfers.is_empty()));
+        assert!(journeys
+            .iter()
+            .all(|journey| journey.possible_transfer.is_none()));
+        assert_eq!(journeys[0].summary(), "渋谷 → 新宿 ￥170");
+    }
+
+    #[test]
+    fn test_group_journeys_history() {
+        let records = parse_usage_history(include_str!("../../test/history.html")).unwrap();
+
+        let journeys = group_journeys(&records, true);
+
+        assert_eq!(journeys.len(), 2);
+        assert_eq!(journeys[0].summary(), "東京 → 新宿 ￥208");
+        assert_eq!(journeys[1].summary(), "渋谷 → 横浜 ￥318");
+    }
+}
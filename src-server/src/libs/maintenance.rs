@@ -0,0 +1,250 @@
+use crate::libs::japanese_text::normalize_text;
+use crate::HtmlDocument;
+
+use chrono::{DateTime, Datelike, Duration, FixedOffset, NaiveDate, NaiveDateTime, Utc};
+use regex::Regex;
+use serde::Serialize;
+use std::sync::OnceLock;
+
+const MAINTENANCE_KEYWORDS: [&str; 3] =
+    ["メンテナンス中", "システムメンテナンス", "サービスを停止"];
+
+// モバイルSuicaの告知は日本時間
+fn jst() -> FixedOffset {
+    FixedOffset::east_opt(9 * 60 * 60).unwrap()
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct Maintenance {
+    pub message: String,
+    // 終了予定日時。告知から読み取れない場合はNone
+    pub end_at: Option<DateTime<FixedOffset>>,
+}
+
+fn datetime_regex<'a>() -> &'a Regex {
+    static DATETIME_REGEX: OnceLock<Regex> = OnceLock::new();
+
+    // 例: 2023年9月16日（土）23:00, 9/17 5時, 5時00分
+    DATETIME_REGEX.get_or_init(|| {
+        Regex::new(
+            r"(?x)
+            (?:
+                (?:(?P<year>\d{4})\s*[年/\-]\s*)?
+                (?P<month>\d{1,2})\s*[月/\-]\s*
+                (?P<day>\d{1,2})\s*日?\s*
+                (?:[（(][^）)]{1,3}[）)])?\s*
+            )?
+            (?P<hour>\d{1,2})\s*
+            (?::\s*(?P<minute>\d{2})|時\s*(?:(?P<minute_ja>\d{1,2})\s*分)?)
+            ",
+        )
+        .unwrap()
+    })
+}
+
+// 告知文中の最後の日時を終了予定日時とする
+fn parse_end_at(text: &str, now: DateTime<FixedOffset>) -> Option<DateTime<FixedOffset>> {
+    // 全角の数字・記号を半角にする
+    let text = normalize_text(text);
+    let today = now.with_timezone(&jst()).date_naive();
+
+    let mut date: Option<NaiveDate> = None;
+    let mut end_at: Option<NaiveDateTime> = None;
+
+    for captures in datetime_regex().captures_iter(&text) {
+        let number = |name: &str| {
+            captures
+                .name(name)
+                .and_then(|m| m.as_str().parse::<u32>().ok())
+        };
+
+        if let (Some(month), Some(day)) = (number("month"), number("day")) {
+            let year = number("year").map_or(today.year(), |year| year as i32);
+            // 存在しない日付（9/31など）の日時は読み飛ばし、他の日時から終了予定を探す
+            let Some(mut parsed) = NaiveDate::from_ymd_opt(year, month, day) else {
+                continue;
+            };
+
+            // 年がない場合、半年以上前の日付は翌年のこととする
+            if number("year").is_none() && parsed < today - Duration::days(183) {
+                let Some(next_year) = NaiveDate::from_ymd_opt(year + 1, month, day) else {
+                    continue;
+                };
+                parsed = next_year;
+            }
+            date = Some(parsed);
+        }
+
+        let hour = number("hour")?;
+        let minute = number("minute").or(number("minute_ja")).unwrap_or(0);
+
+        if hour > 24 || minute > 59 {
+            continue;
+        }
+
+        let base_date = date.unwrap_or(today);
+        // 24:00は翌日の0:00
+        let datetime = match hour {
+            24 => base_date.succ_opt()?.and_hms_opt(0, minute, 0)?,
+            _ => base_date.and_hms_opt(hour, minute, 0)?,
+        };
+
+        end_at = Some(datetime);
+    }
+
+    let mut end_at = end_at?;
+
+    // 日付のない時刻だけの告知で既に過ぎている時刻は翌日のこととする
+    if date.is_none() && end_at < now.with_timezone(&jst()).naive_local() {
+        end_at += Duration::days(1);
+    }
+
+    end_at.and_local_timezone(jst()).single()
+}
+
+// メンテナンス告知の文言を含む行
+fn find_maintenance_message(text: &str) -> Option<String> {
+    let lines = text
+        .lines()
+        .map(|line| line.trim())
+        .filter(|line| !line.is_empty())
+        .collect::<Vec<&str>>();
+
+    let index = lines
+        .iter()
+        .position(|line| MAINTENANCE_KEYWORDS.iter().any(|k| line.contains(k)))?;
+
+    // 日時は次の行に書かれていることが多いので続く数行も含める
+    Some(lines[index..lines.len().min(index + 4)].join("\n"))
+}
+
+pub fn detect_maintenance_at(html: &str, now: DateTime<FixedOffset>) -> Option<Maintenance> {
+    let document = HtmlDocument::new(html);
+
+    let title = match document.query_selector("title") {
+        Some(element) => element.text().collect::<String>(),
+        None => "".to_string(),
+    };
+
+    let message = match find_maintenance_message(&document.text_content()) {
+        Some(message) => message,
+        None if title.contains("メンテナンス") => title,
+        None => return None,
+    };
+
+    Some(Maintenance {
+        end_at: parse_end_at(&message, now),
+        message,
+    })
+}
+
+// ページがメンテナンス告知かどうか
+pub fn detect_maintenance(html: &str) -> Option<Maintenance> {
+    detect_maintenance_at(html, Utc::now().with_timezone(&jst()))
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::*;
+
+    fn at(text: &str) -> DateTime<FixedOffset> {
+        DateTime::parse_from_rfc3339(text).unwrap()
+    }
+
+    #[test]
+    fn test_parse_end_at() {
+        let now = at("2023-09-16T23:30:00+09:00");
+
+        assert_eq!(
+            parse_end_at("2023年9月16日（土）23:00～2023年9月17日（日）5:00", now),
+            Some(at("2023-09-17T05:00:00+09:00"))
+        );
+        assert_eq!(
+            parse_end_at(
+                "９月１６日（土）２３時００分　～　９月１７日（日）５時００分",
+                now
+            ),
+            Some(at("2023-09-17T05:00:00+09:00"))
+        );
+        assert_eq!(
+            parse_end_at("9/16 23:00～24:00", now),
+            Some(at("2023-09-17T00:00:00+09:00"))
+        );
+        // 時刻のみで既に過ぎている場合は翌日
+        assert_eq!(
+            parse_end_at("5:00までご利用いただけません", now),
+            Some(at("2023-09-17T05:00:00+09:00"))
+        );
+        assert_eq!(parse_end_at("終了時刻は未定です", now), None);
+    }
+
+    #[test]
+    fn test_parse_end_at_year_boundary() {
+        let now = at("2023-12-31T23:30:00+09:00");
+
+        assert_eq!(
+            parse_end_at("12月31日 23:00～1月1日 6:00", now),
+            Some(at("2024-01-01T06:00:00+09:00"))
+        );
+    }
+
+    #[test]
+    fn test_parse_end_at_impossible_date() {
+        let now = at("2023-09-16T23:30:00+09:00");
+
+        assert_eq!(
+            parse_end_at("9月16日 23:00～9月31日 5:00（9月17日 5:00に訂正）", now),
+            Some(at("2023-09-17T05:00:00+09:00"))
+        );
+        assert_eq!(parse_end_at("2月30日 5:00", now), None);
+    }
+
+    #[test]
+    fn test_detect_maintenance() {
+        let html = r#"
+        <html>
+            <head>
+                <title>JR東日本：モバイルSuica＞メンテナンス</title>
+            </head>
+            <body>
+                <p>ただいまシステムメンテナンス中のため、ご利用いただけません。</p>
+                <p>【終了予定】2023年9月17日（日）5:00</p>
+            </body>
+        </html>
+        "#;
+
+        let maintenance = detect_maintenance_at(html, at("2023-09-16T23:30:00+09:00")).unwrap();
+
+        assert!(maintenance
+            .message
+            .starts_with("ただいまシステムメンテナンス中"));
+        assert_eq!(maintenance.end_at, Some(at("2023-09-17T05:00:00+09:00")));
+    }
+
+    #[test]
+    fn test_detect_maintenance_title_only() {
+        let html = r#"
+        <html>
+            <head>
+                <title>メンテナンスのお知らせ</title>
+            </head>
+            <body></body>
+        </html>
+        "#;
+
+        let maintenance = detect_maintenance(html).unwrap();
+
+        assert_eq!(maintenance.message, "メンテナンスのお知らせ");
+        assert_eq!(maintenance.end_at, None);
+    }
+
+    #[test]
+    fn test_detect_maintenance_login_page() {
+        // ログイン画面のscript内にもメンテナンスの文言がある
+        assert_eq!(
+            detect_maintenance(include_str!("../../test/login.html")),
+            None
+        );
+    }
+}
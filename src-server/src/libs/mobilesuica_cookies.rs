@@ -0,0 +1,337 @@
+use reqwest::header::HeaderValue;
+use serde::{Deserialize, Serialize};
+use std::sync::RwLock;
+use url::Url;
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct StoredCookie {
+    pub name: String,
+    pub value: String,
+    pub domain: String,
+    // Domain属性がない場合は発行元ホストにのみ送信する
+    pub host_only: bool,
+    pub path: String,
+    // UNIXタイムスタンプ。Noneの場合はセッションCookie
+    pub expires: Option<i64>,
+    pub secure: bool,
+    pub http_only: bool,
+}
+
+// 属性を保持したままセッションに保存できるCookie Jar
+// reqwestのCookieStoreとして使うことでリダイレクト中のSet-Cookieも反映される
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct MobilesuicaCookies {
+    cookies: RwLock<Vec<StoredCookie>>,
+}
+
+fn now() -> i64 {
+    chrono::Local::now().timestamp()
+}
+
+// RFC 6265 5.1.4 default-path
+fn default_path(url: &Url) -> String {
+    let path = url.path();
+
+    match path.rfind('/') {
+        Some(0) | None => "/".to_string(),
+        Some(index) => path[..index].to_string(),
+    }
+}
+
+fn domain_match(host: &str, domain: &str) -> bool {
+    host == domain
+        || (host.ends_with(domain)
+            && host[..host.len() - domain.len()].ends_with('.')
+            && host.parse::<std::net::IpAddr>().is_err())
+}
+
+fn path_match(request_path: &str, cookie_path: &str) -> bool {
+    request_path == cookie_path
+        || (request_path.starts_with(cookie_path)
+            && (cookie_path.ends_with('/') || request_path[cookie_path.len()..].starts_with('/')))
+}
+
+impl StoredCookie {
+    fn parse(set_cookie: &str, url: &Url) -> Option<Self> {
+        let cookie = cookie::Cookie::parse(set_cookie).ok()?;
+        let host = url.host_str()?.to_lowercase();
+
+        let (domain, host_only) = match cookie.domain().filter(|domain| !domain.is_empty()) {
+            Some(domain) => {
+                let domain = domain.to_lowercase();
+
+                // 発行元と関係のないドメインのCookieは受け付けない
+                if !domain_match(&host, &domain) {
+                    return None;
+                }
+                (domain, false)
+            }
+            None => (host, true),
+        };
+
+        let path = match cookie.path() {
+            Some(path) if path.starts_with('/') => path.to_string(),
+            _ => default_path(url),
+        };
+
+        // Max-AgeはExpiresより優先される
+        let expires = match cookie.max_age() {
+            Some(max_age) => Some(now() + max_age.whole_seconds()),
+            None => cookie
+                .expires_datetime()
+                .map(|datetime| datetime.unix_timestamp()),
+        };
+
+        Some(StoredCookie {
+            name: cookie.name().to_string(),
+            value: cookie.value().to_string(),
+            domain,
+            host_only,
+            path,
+            expires,
+            secure: cookie.secure().unwrap_or(false),
+            http_only: cookie.http_only().unwrap_or(false),
+        })
+    }
+
+    pub fn is_expired(&self) -> bool {
+        match self.expires {
+            Some(expires) => expires <= now(),
+            None => false,
+        }
+    }
+
+    fn is_same(&self, other: &StoredCookie) -> bool {
+        self.name == other.name && self.domain == other.domain && self.path == other.path
+    }
+
+    fn matches(&self, url: &Url) -> bool {
+        let host = match url.host_str() {
+            Some(host) => host.to_lowercase(),
+            None => return false,
+        };
+
+        let domain_matched = match self.host_only {
+            true => host == self.domain,
+            false => domain_match(&host, &self.domain),
+        };
+
+        domain_matched
+            && path_match(url.path(), &self.path)
+            && (!self.secure || url.scheme() == "https")
+            && !self.is_expired()
+    }
+}
+
+impl MobilesuicaCookies {
+    pub fn add_cookie_str(&self, set_cookie: &str, url: &Url) {
+        if let Some(cookie) = StoredCookie::parse(set_cookie, url) {
+            self.insert(cookie);
+        }
+    }
+
+    // 同じ名前・ドメイン・パスのCookieは上書きし、期限切れのものは削除する
+    pub fn insert(&self, cookie: StoredCookie) {
+        let mut cookies = self.cookies.write().unwrap();
+
+        cookies.retain(|stored| !stored.is_same(&cookie) && !stored.is_expired());
+
+        if !cookie.is_expired() {
+            cookies.push(cookie);
+        }
+    }
+
+    pub fn get(&self, name: &str) -> Option<StoredCookie> {
+        self.cookies
+            .read()
+            .unwrap()
+            .iter()
+            .find(|cookie| cookie.name == name && !cookie.is_expired())
+            .cloned()
+    }
+
+    pub fn to_vec(&self) -> Vec<StoredCookie> {
+        self.cookies.read().unwrap().clone()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.cookies.read().unwrap().is_empty()
+    }
+
+    // 送信先URLに対応するCookieヘッダーの値を作る
+    pub fn cookie_header(&self, url: &Url) -> Option<String> {
+        let cookies = self.cookies.read().unwrap();
+
+        let mut matched = cookies
+            .iter()
+            .filter(|cookie| cookie.matches(url))
+            .collect::<Vec<&StoredCookie>>();
+
+        if matched.is_empty() {
+            return None;
+        }
+
+        // パスが長いものを先に送る
+        matched.sort_by_key(|cookie| std::cmp::Reverse(cookie.path.len()));
+
+        Some(
+            matched
+                .iter()
+                .map(|cookie| format!("{}={}", cookie.name, cookie.value))
+                .collect::<Vec<String>>()
+                .join("; "),
+        )
+    }
+}
+
+impl Clone for MobilesuicaCookies {
+    fn clone(&self) -> Self {
+        MobilesuicaCookies {
+            cookies: RwLock::new(self.to_vec()),
+        }
+    }
+}
+
+impl PartialEq for MobilesuicaCookies {
+    fn eq(&self, other: &Self) -> bool {
+        self.to_vec() == other.to_vec()
+    }
+}
+
+impl reqwest::cookie::CookieStore for MobilesuicaCookies {
+    fn set_cookies(&self, cookie_headers: &mut dyn Iterator<Item = &HeaderValue>, url: &Url) {
+        cookie_headers
+            .filter_map(|header| header.to_str().ok())
+            .for_each(|set_cookie| self.add_cookie_str(set_cookie, url));
+    }
+
+    fn cookies(&self, url: &Url) -> Option<HeaderValue> {
+        HeaderValue::from_str(&self.cookie_header(url)?).ok()
+    }
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::*;
+
+    fn url(url: &str) -> Url {
+        Url::parse(url).unwrap()
+    }
+
+    #[test]
+    fn test_add_cookie_str_keeps_attributes() {
+        let cookies = MobilesuicaCookies::default();
+
+        cookies.add_cookie_str(
+            "ASP.NET_SessionId=abc; path=/; secure; HttpOnly",
+            &url("https://www.mobilesuica.com/ka/lg/LoginForm.aspx"),
+        );
+        cookies.add_cookie_str(
+            "lb=server1; Domain=.mobilesuica.com; Path=/ka; Max-Age=3600",
+            &url("https://www.mobilesuica.com/ka/lg/LoginForm.aspx"),
+        );
+
+        let session_id = cookies.get("ASP.NET_SessionId").unwrap();
+
+        assert_eq!(session_id.domain, "www.mobilesuica.com");
+        assert!(session_id.host_only);
+        assert_eq!(session_id.path, "/");
+        assert_eq!(session_id.expires, None);
+        assert!(session_id.secure);
+        assert!(session_id.http_only);
+
+        let lb = cookies.get("lb").unwrap();
+
+        assert_eq!(lb.domain, "mobilesuica.com");
+        assert!(!lb.host_only);
+        assert_eq!(lb.path, "/ka");
+        assert!(lb.expires.unwrap() > now());
+    }
+
+    #[test]
+    fn test_add_cookie_str_default_path_and_foreign_domain() {
+        let cookies = MobilesuicaCookies::default();
+
+        cookies.add_cookie_str(
+            "page=1",
+            &url("https://www.mobilesuica.com/ka/lg/LoginForm.aspx"),
+        );
+        cookies.add_cookie_str(
+            "evil=1; Domain=example.com",
+            &url("https://www.mobilesuica.com/"),
+        );
+
+        assert_eq!(cookies.get("page").unwrap().path, "/ka/lg");
+        assert_eq!(cookies.get("evil"), None);
+    }
+
+    #[test]
+    fn test_insert_merges_updates() {
+        let cookies = MobilesuicaCookies::default();
+        let base_url = url("https://www.mobilesuica.com/");
+
+        cookies.add_cookie_str("id=first; Path=/", &base_url);
+        cookies.add_cookie_str("id=second; Path=/", &base_url);
+
+        assert_eq!(cookies.to_vec().len(), 1);
+        assert_eq!(cookies.get("id").unwrap().value, "second");
+
+        // 期限切れのSet-Cookieで削除される
+        cookies.add_cookie_str("id=; Path=/; Max-Age=0", &base_url);
+
+        assert_eq!(cookies.get("id"), None);
+        assert!(cookies.is_empty());
+    }
+
+    #[test]
+    fn test_cookie_header() {
+        let cookies = MobilesuicaCookies::default();
+
+        cookies.add_cookie_str("root=1; Path=/", &url("https://www.mobilesuica.com/"));
+        cookies.add_cookie_str("ka=2; Path=/ka", &url("https://www.mobilesuica.com/"));
+        cookies.add_cookie_str(
+            "secure=3; Path=/; Secure",
+            &url("https://www.mobilesuica.com/"),
+        );
+        cookies.add_cookie_str(
+            "shared=4; Domain=mobilesuica.com; Path=/",
+            &url("https://www.mobilesuica.com/"),
+        );
+
+        assert_eq!(
+            cookies
+                .cookie_header(&url("https://www.mobilesuica.com/ka/lg/"))
+                .unwrap(),
+            "ka=2; root=1; secure=3; shared=4"
+        );
+        assert_eq!(
+            cookies
+                .cookie_header(&url("http://www.mobilesuica.com/kaa"))
+                .unwrap(),
+            "root=1; shared=4"
+        );
+        assert_eq!(
+            cookies
+                .cookie_header(&url("https://sub.mobilesuica.com/"))
+                .unwrap(),
+            "shared=4"
+        );
+        assert_eq!(cookies.cookie_header(&url("https://example.com/")), None);
+    }
+
+    #[test]
+    fn test_serialize_roundtrip() {
+        let cookies = MobilesuicaCookies::default();
+
+        cookies.add_cookie_str(
+            "ASP.NET_SessionId=abc; Path=/; Secure; HttpOnly",
+            &url("https://www.mobilesuica.com/"),
+        );
+
+        let serialized = serde_json::to_string(&cookies).unwrap();
+        let deserialized: MobilesuicaCookies = serde_json::from_str(&serialized).unwrap();
+
+        assert_eq!(deserialized, cookies);
+    }
+}
@@ -1,24 +1,9 @@
 use crate::HtmlDocument;
 
-use encoding_rs::SHIFT_JIS;
+use crate::libs::sjis_urlencoded::serialize_into_sjis;
 
 use serde::{Deserialize, Serialize};
 
-// SJISにしてURIエンコードする
-fn encode_uri_from_sjis(text: &str) -> String {
-    SHIFT_JIS
-        .encode(text)
-        .0
-        .to_vec()
-        .iter()
-        .fold(String::new(), |encoded_str, byte| match byte {
-            32 => format!("{}+", encoded_str),
-            42 | 45 | 46 | 95 => format!("{}{}", encoded_str, *byte as char),
-            48..=57 | 65..=90 | 97..=122 => format!("{}{}", encoded_str, *byte as char),
-            _ => format!("{}%{:2X}", encoded_str, byte),
-        })
-}
-
 #[allow(non_snake_case)]
 #[derive(Debug, Default, Serialize, Deserialize, PartialEq)]
 pub struct MobilesuicaFormParams {
@@ -90,7 +75,7 @@ impl MobilesuicaFormParams {
 
     // 各パラメータをSJISにしてURIエンコードする
     pub fn serialize_into_sjis(&self) -> String {
-        [
+        serialize_into_sjis([
             ("__EVENTARGUMENT", &self.__EVENTARGUMENT),
             ("__EVENTTARGET", &self.__EVENTTARGET),
             ("__VIEWSTATE", &self.__VIEWSTATE),
@@ -107,17 +92,7 @@ impl MobilesuicaFormParams {
                 &self.WebCaptcha1__editor_clientState,
             ),
             ("WebCaptcha1_clientState", &self.WebCaptcha1_clientState),
-        ]
-        .iter()
-        .map(|(key, value)| {
-            format!(
-                "{}={}",
-                encode_uri_from_sjis(key),
-                encode_uri_from_sjis(value)
-            )
-        })
-        .collect::<Vec<String>>()
-        .join("&")
+        ])
     }
 }
 
@@ -126,21 +101,6 @@ mod test {
 
     use super::*;
 
-    #[test]
-    fn test_encode_uri_from_sjis() {
-        let text = "あいうえお";
-
-        let encoded = encode_uri_from_sjis(text);
-
-        assert_eq!(encoded, "%82%A0%82%A2%82%A4%82%A6%82%A8");
-
-        let text2 = "ABCDEFGHIJKLMNOPQRSTUVWXYZ";
-
-        let encoded2 = encode_uri_from_sjis(text2);
-
-        assert_eq!(encoded2, "ABCDEFGHIJKLMNOPQRSTUVWXYZ");
-    }
-
     #[test]
     fn test_mobilesuica_form_params_serialize() {
         let form_params = MobilesuicaFormParams {
@@ -0,0 +1,306 @@
+use crate::libs::http_client::{get_text, BASE_URL};
+use crate::libs::image_info::sniff_mime_type;
+use crate::libs::maintenance::{detect_maintenance, Maintenance};
+use crate::{HtmlDocument, MobilesuicaFormParams, WebForm};
+
+use reqwest::header::{HeaderValue, CONTENT_TYPE};
+
+const MEMBER_MENU_TITLE: &str = "JR東日本：モバイルSuica＞会員メニュー";
+
+#[derive(Debug, PartialEq)]
+pub enum LoginError {
+    RequestFailed,
+    InvalidCaptcha,
+    InvalidCredentials,
+    AccountLocked,
+    Maintenance(Maintenance),
+    Unknown,
+}
+
+pub fn get_login_error_message(error: &LoginError) -> String {
+    match error {
+        LoginError::RequestFailed => "ログインリクエストに失敗しました。",
+        LoginError::InvalidCaptcha => "画像の文字が正しくありません。",
+        LoginError::InvalidCredentials => "メールアドレスまたはパスワードが正しくありません。",
+        LoginError::AccountLocked => "アカウントがロックされています。",
+        LoginError::Maintenance(_) => "モバイルSuicaはメンテナンス中です。",
+        LoginError::Unknown => "ログインに失敗しました。",
+    }
+    .to_string()
+}
+
+fn get_captcha_imageurl(html: &str) -> String {
+    let document = HtmlDocument::new(html);
+
+    match document.query_selector(".igc_TrendyCaptchaImage") {
+        Some(element) => element.value().attr("src").unwrap_or(""),
+        None => "",
+    }
+    .to_string()
+}
+
+fn get_action_url(html: &str) -> String {
+    let document = HtmlDocument::new(html);
+
+    match document.get_element_by_id("form1") {
+        Some(element) => element.value().attr("action").unwrap_or(""),
+        None => "",
+    }
+    .to_string()
+}
+
+// ログイン画面から読み取る情報
+#[derive(Debug)]
+pub struct LoginPage {
+    pub mobilesuica_form_params: MobilesuicaFormParams,
+    pub login_form: Option<WebForm>,
+    pub captcha_url: String,
+    pub action_url: String,
+}
+
+impl LoginPage {
+    pub fn new(html: &str) -> Self {
+        LoginPage {
+            mobilesuica_form_params: MobilesuicaFormParams::new(html),
+            login_form: WebForm::new(html, "#form1"),
+            captcha_url: get_captcha_imageurl(html),
+            action_url: get_action_url(html),
+        }
+    }
+}
+
+pub fn get_title(html: &str) -> String {
+    let document = HtmlDocument::new(html);
+
+    match document.query_selector("title") {
+        Some(element) => element.text().collect::<String>(),
+        None => "".to_string(),
+    }
+}
+
+// ログイン画面のエラーメッセージ表示領域の文言
+fn get_error_message(html: &str) -> String {
+    let document = HtmlDocument::new(html);
+
+    let selector = ".errorArea, .errorMsg, .errorTxt, .error, #errorMsg, font[color='red'], font[color='#FF0000'], font[color='#ff0000']";
+
+    document
+        .query_selector_all(selector)
+        .unwrap_or_default()
+        .iter()
+        .map(|element| element.text().collect::<String>())
+        .collect::<Vec<String>>()
+        .join("")
+        .split_whitespace()
+        .collect::<String>()
+}
+
+// ログイン失敗時のページからエラーの種類を判定する
+pub fn get_login_error(html: &str) -> LoginError {
+    if let Some(maintenance) = detect_maintenance(html) {
+        return LoginError::Maintenance(maintenance);
+    }
+
+    let message = get_error_message(html);
+
+    let contains_any = |keywords: &[&str]| keywords.iter().any(|k| message.contains(k));
+
+    if contains_any(&["メンテナンス"]) {
+        LoginError::Maintenance(Maintenance {
+            message,
+            end_at: None,
+        })
+    } else if contains_any(&["ロック", "利用停止", "停止されて"]) {
+        LoginError::AccountLocked
+    } else if contains_any(&["画像", "表示されている文字"]) {
+        LoginError::InvalidCaptcha
+    } else if contains_any(&["メールアドレス", "パスワード"]) {
+        LoginError::InvalidCredentials
+    } else {
+        LoginError::Unknown
+    }
+}
+
+// ログイン後のページが会員メニューかどうか
+pub fn check_login_result(html: &str) -> Result<(), LoginError> {
+    match get_title(html).as_str() {
+        MEMBER_MENU_TITLE => Ok(()),
+        _ => Err(get_login_error(html)),
+    }
+}
+
+pub async fn fetch_login_page(client: &reqwest::Client) -> Result<LoginPage, LoginError> {
+    let html = async {
+        let response = client.get(BASE_URL).send().await?;
+
+        get_text(response).await
+    }
+    .await
+    .map_err(|_| LoginError::RequestFailed)?;
+
+    if let Some(maintenance) = detect_maintenance(&html) {
+        return Err(LoginError::Maintenance(maintenance));
+    }
+
+    Ok(LoginPage::new(&html))
+}
+
+pub async fn download_captcha(
+    client: &reqwest::Client,
+    captcha_url: &str,
+) -> Result<Vec<u8>, LoginError> {
+    let url = format!("{}{}", BASE_URL, captcha_url);
+
+    let captcha_image = async { client.get(url).send().await?.bytes().await }
+        .await
+        .map_err(|_| LoginError::RequestFailed)?;
+
+    // エラーページなど画像以外が返ってきた場合
+    match sniff_mime_type(&captcha_image) {
+        Some(_) => Ok(captcha_image.to_vec()),
+        None => Err(LoginError::RequestFailed),
+    }
+}
+
+pub async fn login(
+    client: &reqwest::Client,
+    action_url: &str,
+    form_params: &MobilesuicaFormParams,
+) -> Result<(), LoginError> {
+    let url = format!("{}{}", BASE_URL, action_url);
+
+    let form_body = form_params.serialize_into_sjis();
+
+    let html = async {
+        let response = client
+            .post(url)
+            .header(
+                CONTENT_TYPE,
+                HeaderValue::from_static("application/x-www-form-urlencoded"),
+            )
+            .body(form_body)
+            .send()
+            .await?;
+
+        get_text(response).await
+    }
+    .await
+    .map_err(|_| LoginError::RequestFailed)?;
+
+    check_login_result(&html)
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::*;
+
+    #[test]
+    fn test_login_page_new() {
+        let login_page = LoginPage::new(include_str!("../../test/login.html"));
+
+        assert_eq!(
+            login_page.captcha_url,
+            "WebCaptchaImage.axd?guid=36974673-b1f8-4244-ac5f-e3145f6150da"
+        );
+        assert_eq!(
+            login_page.action_url,
+            "ka/lg/LoginForm.aspx?pguid=af60fc73-558f-460c-a2b1-d830c426f307"
+        );
+        assert_eq!(
+            login_page.login_form.unwrap().get("__VIEWSTATEGENERATOR"),
+            Some("BB3126B1")
+        );
+    }
+
+    #[test]
+    fn test_get_title() {
+        let html = r#"
+        <html>
+            <head>
+                <title>test</title>
+            </head>
+            <body>
+            </body>
+        </html>
+        "#;
+
+        let title = get_title(html);
+
+        assert_eq!(title, "test".to_string());
+    }
+
+    fn error_page(message: &str) -> String {
+        format!(
+            r#"
+            <html>
+                <head>
+                    <title>JR東日本：モバイルSuica＞ログイン</title>
+                </head>
+                <body>
+                    <script>var msg = "ただいまシステムメンテナンス中のため";</script>
+                    <p class="sentence2">下の画像に表示されている文字を半角で入力してください。</p>
+                    <div class="errorArea"><font color="red">{}</font></div>
+                </body>
+            </html>
+            "#,
+            message
+        )
+    }
+
+    #[test]
+    fn test_get_login_error() {
+        let cases = [
+            (
+                "画像に表示されている文字が正しくありません。",
+                LoginError::InvalidCaptcha,
+            ),
+            (
+                "メールアドレスまたはパスワードが正しくありません。",
+                LoginError::InvalidCredentials,
+            ),
+            (
+                "パスワードを規定回数以上間違えたため、アカウントをロックしました。",
+                LoginError::AccountLocked,
+            ),
+            ("", LoginError::Unknown),
+        ];
+
+        for (message, expected) in cases {
+            assert_eq!(get_login_error(&error_page(message)), expected);
+        }
+
+        assert!(matches!(
+            get_login_error(&error_page("ただいまシステムメンテナンス中です。")),
+            LoginError::Maintenance(_)
+        ));
+
+        let maintenance_page = r#"
+        <html>
+            <head>
+                <title>JR東日本：モバイルSuica＞システムメンテナンス</title>
+            </head>
+            <body></body>
+        </html>
+        "#;
+
+        assert!(matches!(
+            get_login_error(maintenance_page),
+            LoginError::Maintenance(_)
+        ));
+    }
+
+    #[test]
+    fn test_check_login_result() {
+        let member_menu = format!(
+            "<html><head><title>{}</title></head></html>",
+            MEMBER_MENU_TITLE
+        );
+
+        assert_eq!(check_login_result(&member_menu), Ok(()));
+        assert_eq!(
+            check_login_result(&error_page("画像に表示されている文字が正しくありません。")),
+            Err(LoginError::InvalidCaptcha)
+        );
+    }
+}
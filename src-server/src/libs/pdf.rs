@@ -0,0 +1,182 @@
+// 帳票用の最小限のPDF。A4縦で、日本語は平成角ゴシック1書体のみ
+
+// A4縦（pt）
+pub(crate) const PAGE_WIDTH: f32 = 595.0;
+pub(crate) const PAGE_HEIGHT: f32 = 842.0;
+pub(crate) const MARGIN: f32 = 50.0;
+
+// 表示できない文字は〓にする
+const REPLACEMENT_CHAR: u16 = 0x3013;
+
+// 1ページ分のコンテンツストリーム
+#[derive(Default)]
+pub(crate) struct Page {
+    content: String,
+}
+
+impl Page {
+    pub(crate) fn text(&mut self, x: f32, y: f32, size: f32, text: &str) {
+        self.content.push_str(&format!(
+            "BT /F1 {} Tf {:.2} {:.2} Td <{}> Tj ET\n",
+            size,
+            x,
+            y,
+            encode_text(text)
+        ));
+    }
+
+    pub(crate) fn text_right(&mut self, right: f32, y: f32, size: f32, text: &str) {
+        self.text(right - text_width(text, size), y, size, text);
+    }
+
+    pub(crate) fn text_center(&mut self, y: f32, size: f32, text: &str) {
+        self.text((PAGE_WIDTH - text_width(text, size)) / 2.0, y, size, text);
+    }
+
+    // 列の幅に収まらない場合は末尾を…にする
+    pub(crate) fn text_fit(&mut self, x: f32, y: f32, size: f32, max_width: f32, text: &str) {
+        self.text(x, y, size, &fit_text(text, size, max_width));
+    }
+
+    pub(crate) fn line(&mut self, x1: f32, y1: f32, x2: f32, y2: f32) {
+        self.content.push_str(&format!(
+            "0.5 w {:.2} {:.2} m {:.2} {:.2} l S\n",
+            x1, y1, x2, y2
+        ));
+    }
+}
+
+// 半角の文字は全角の半分の幅
+fn is_half_width(c: char) -> bool {
+    c.is_ascii() || ('\u{FF61}'..='\u{FF9F}').contains(&c)
+}
+
+fn text_width(text: &str, size: f32) -> f32 {
+    text.chars()
+        .map(|c| if is_half_width(c) { 0.5 } else { 1.0 })
+        .sum::<f32>()
+        * size
+}
+
+fn fit_text(text: &str, size: f32, max_width: f32) -> String {
+    if text_width(text, size) <= max_width {
+        return text.to_string();
+    }
+
+    let mut fitted = String::new();
+
+    for c in text.chars() {
+        if text_width(&format!("{}{}…", fitted, c), size) > max_width {
+            break;
+        }
+        fitted.push(c);
+    }
+
+    format!("{}…", fitted)
+}
+
+// UniJIS-UCS2-HW-Hは2バイトのUCS-2をそのまま使う
+fn encode_text(text: &str) -> String {
+    text.chars()
+        .map(|c| u16::try_from(c as u32).unwrap_or(REPLACEMENT_CHAR))
+        .map(|code| format!("{:04X}", code))
+        .collect()
+}
+
+// フォントは埋め込まず、ビューアが持つ平成角ゴシックを使う。各ページの下にページ番号を入れる
+// 1: Catalog, 2: Pages, 3-5: フォント, 6以降: ページとコンテンツを交互に置く
+pub(crate) fn write_pdf(pages: &mut [Page]) -> Vec<u8> {
+    let count = pages.len();
+    for (index, page) in pages.iter_mut().enumerate() {
+        page.text_center(40.0, 8.0, &format!("{} / {}", index + 1, count));
+    }
+
+    let page_ids = (0..pages.len()).map(|index| 6 + index * 2);
+
+    let mut objects = vec![
+        "<< /Type /Catalog /Pages 2 0 R >>".to_string(),
+        format!(
+            "<< /Type /Pages /Kids [{}] /Count {} >>",
+            page_ids
+                .map(|id| format!("{} 0 R", id))
+                .collect::<Vec<String>>()
+                .join(" "),
+            pages.len()
+        ),
+        "<< /Type /Font /Subtype /Type0 /BaseFont /HeiseiKakuGo-W5 \
+         /Encoding /UniJIS-UCS2-HW-H /DescendantFonts [4 0 R] >>"
+            .to_string(),
+        "<< /Type /Font /Subtype /CIDFontType0 /BaseFont /HeiseiKakuGo-W5 \
+         /CIDSystemInfo << /Registry (Adobe) /Ordering (Japan1) /Supplement 2 >> \
+         /FontDescriptor 5 0 R /DW 1000 /W [231 632 500] >>"
+            .to_string(),
+        "<< /Type /FontDescriptor /FontName /HeiseiKakuGo-W5 /Flags 4 \
+         /FontBBox [-92 -250 1010 922] /ItalicAngle 0 /Ascent 880 /Descent -120 \
+         /CapHeight 700 /StemV 93 >>"
+            .to_string(),
+    ];
+
+    for (index, page) in pages.iter().enumerate() {
+        objects.push(format!(
+            "<< /Type /Page /Parent 2 0 R /MediaBox [0 0 {} {}] \
+             /Resources << /Font << /F1 3 0 R >> >> /Contents {} 0 R >>",
+            PAGE_WIDTH,
+            PAGE_HEIGHT,
+            7 + index * 2
+        ));
+        objects.push(format!(
+            "<< /Length {} >>\nstream\n{}endstream",
+            page.content.len(),
+            page.content
+        ));
+    }
+
+    let mut pdf = "%PDF-1.4\n".to_string();
+    let mut offsets = vec![];
+
+    for (index, object) in objects.iter().enumerate() {
+        offsets.push(pdf.len());
+        pdf.push_str(&format!("{} 0 obj\n{}\nendobj\n", index + 1, object));
+    }
+
+    let xref_offset = pdf.len();
+    pdf.push_str(&format!(
+        "xref\n0 {}\n0000000000 65535 f \n",
+        objects.len() + 1
+    ));
+    for offset in offsets {
+        pdf.push_str(&format!("{:010} 00000 n \n", offset));
+    }
+    pdf.push_str(&format!(
+        "trailer\n<< /Size {} /Root 1 0 R >>\nstartxref\n{}\n%%EOF\n",
+        objects.len() + 1,
+        xref_offset
+    ));
+
+    pdf.into_bytes()
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::*;
+
+    #[test]
+    fn test_text_width() {
+        assert_eq!(text_width("ｶｰﾄﾞ 100", 10.0), 40.0);
+        assert_eq!(text_width("東京", 10.0), 20.0);
+    }
+
+    #[test]
+    fn test_fit_text() {
+        assert_eq!(fit_text("東京", 10.0, 20.0), "東京");
+        assert_eq!(fit_text("高輪ゲートウェイ", 10.0, 40.0), "高輪ゲ…");
+    }
+
+    #[test]
+    fn test_encode_text() {
+        assert_eq!(encode_text("A領"), "00419818");
+        // 基本多言語面の外の文字
+        assert_eq!(encode_text("𠮷"), "3013");
+    }
+}
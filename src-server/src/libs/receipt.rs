@@ -0,0 +1,384 @@
+use crate::libs::japanese_text::format_yen;
+use crate::libs::pdf::{write_pdf, Page, MARGIN, PAGE_WIDTH};
+use crate::libs::usage_history::UsageRecord;
+
+use chrono::NaiveDate;
+use serde::Serialize;
+
+// アプリが利用履歴から作る明細で、交通事業者の領収書ではない
+pub const RECEIPT_TITLE: &str = "利用明細";
+const RECEIPT_NOTICE: [&str; 2] = [
+    "本書はモバイルSuicaの利用履歴をもとに本アプリで作成した明細です。",
+    "交通事業者が発行する領収書ではありません。",
+];
+
+// 用途の既定値
+pub const DEFAULT_RECEIPT_NOTE: &str = "交通費";
+
+const ROW_HEIGHT: f32 = 16.0;
+// 1ページ目は宛名や金額の下から表を始める
+const FIRST_TABLE_TOP: f32 = 570.0;
+const TABLE_TOP: f32 = 780.0;
+const TABLE_BOTTOM: f32 = 70.0;
+
+const DATE_COLUMN: f32 = MARGIN;
+const DESCRIPTION_COLUMN: f32 = MARGIN + 70.0;
+
+#[derive(Debug, PartialEq)]
+pub enum ReceiptError {
+    // 選択した行に支払いが含まれない
+    NoPayments,
+    // 選択した行がサーバーで取得した利用履歴にない
+    RecordNotFound,
+}
+
+pub fn get_receipt_error_message(error: &ReceiptError) -> String {
+    match error {
+        ReceiptError::NoPayments => "利用明細に記載できる利用がありません。",
+        ReceiptError::RecordNotFound => "選択した利用がモバイルSuicaの利用履歴に見つかりません。",
+    }
+    .to_string()
+}
+
+pub fn get_receipt_error_code(error: &ReceiptError) -> String {
+    match error {
+        ReceiptError::NoPayments => "no_payments",
+        ReceiptError::RecordNotFound => "record_not_found",
+    }
+    .to_string()
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct ReceiptLine {
+    pub date: NaiveDate,
+    pub description: String,
+    // 支払った金額（正）
+    pub amount: i64,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct Receipt {
+    pub addressee: String,
+    pub note: String,
+    pub issued_on: NaiveDate,
+    pub lines: Vec<ReceiptLine>,
+}
+
+fn describe(record: &UsageRecord) -> String {
+    match (record.entry_kind.as_str(), record.exit_kind.as_str()) {
+        ("入", "出") => format!("{} 〜 {}", record.entry_place, record.exit_place),
+        _ => format!("{} {}", record.entry_kind, record.entry_place)
+            .trim()
+            .to_string(),
+    }
+}
+
+// 選択された行を、サーバーで取得した利用履歴の行に置き換える
+// 利用履歴にない行が含まれる場合はエラーにする。同じ行を重ねて選ぶことはできない
+// 結果は利用履歴の順（新しい順）
+pub fn select_records(
+    history: &[UsageRecord],
+    selected: &[UsageRecord],
+) -> Result<Vec<UsageRecord>, ReceiptError> {
+    let mut used = vec![false; history.len()];
+
+    for record in selected {
+        let index = (0..history.len())
+            .find(|index| !used[*index] && history[*index].is_same_row(record))
+            .ok_or(ReceiptError::RecordNotFound)?;
+
+        used[index] = true;
+    }
+
+    Ok(history
+        .iter()
+        .zip(used)
+        .filter(|(_, used)| *used)
+        .map(|(record, _)| record.clone())
+        .collect())
+}
+
+impl Receipt {
+    // entriesは年を補った日付と利用履歴の組で、利用履歴と同じく新しい順
+    // 利用（差額が負）の行だけを古い順に記載する。チャージなどは含めない
+    pub fn new(
+        addressee: &str,
+        note: &str,
+        issued_on: NaiveDate,
+        entries: &[(NaiveDate, UsageRecord)],
+    ) -> Result<Self, ReceiptError> {
+        let mut lines = entries
+            .iter()
+            .rev()
+            .filter_map(|(date, record)| {
+                let amount = record.amount.filter(|amount| *amount < 0)?;
+
+                Some(ReceiptLine {
+                    date: *date,
+                    description: describe(record),
+                    amount: -amount,
+                })
+            })
+            .collect::<Vec<ReceiptLine>>();
+
+        if lines.is_empty() {
+            return Err(ReceiptError::NoPayments);
+        }
+
+        // 安定ソートなので、同じ日の中では乗った順が保たれる
+        lines.sort_by_key(|line| line.date);
+
+        Ok(Receipt {
+            addressee: addressee.to_string(),
+            note: note.to_string(),
+            issued_on,
+            lines,
+        })
+    }
+
+    pub fn total(&self) -> i64 {
+        self.lines.iter().map(|line| line.amount).sum()
+    }
+
+    // 利用期間。行は古い順に並んでいる
+    pub fn period(&self) -> (NaiveDate, NaiveDate) {
+        (
+            self.lines.first().unwrap().date,
+            self.lines.last().unwrap().date,
+        )
+    }
+
+    pub fn to_pdf(&self) -> Vec<u8> {
+        let mut pages = vec![Page::default()];
+        let mut y = self.write_heading(&mut pages[0]);
+
+        for line in &self.lines {
+            if y - ROW_HEIGHT < TABLE_BOTTOM {
+                pages.push(Page::default());
+                y = write_table_header(pages.last_mut().unwrap(), TABLE_TOP);
+            }
+
+            let page = pages.last_mut().unwrap();
+            y -= ROW_HEIGHT;
+
+            page.text(
+                DATE_COLUMN,
+                y,
+                10.0,
+                &line.date.format("%Y/%m/%d").to_string(),
+            );
+            page.text(DESCRIPTION_COLUMN, y, 10.0, &line.description);
+            page.text_right(PAGE_WIDTH - MARGIN, y, 10.0, &format_yen(line.amount));
+        }
+
+        // 合計の行は罫線と合わせて2行分
+        if y - ROW_HEIGHT * 2.0 < TABLE_BOTTOM {
+            pages.push(Page::default());
+            y = write_table_header(pages.last_mut().unwrap(), TABLE_TOP);
+        }
+
+        let page = pages.last_mut().unwrap();
+        page.line(MARGIN, y - 6.0, PAGE_WIDTH - MARGIN, y - 6.0);
+        y -= ROW_HEIGHT * 1.5;
+        page.text(DESCRIPTION_COLUMN, y, 10.0, "合計");
+        page.text_right(PAGE_WIDTH - MARGIN, y, 10.0, &format_yen(self.total()));
+
+        write_pdf(&mut pages)
+    }
+
+    // 宛名、金額、作成元の注記を書いて表の開始位置を返す
+    fn write_heading(&self, page: &mut Page) -> f32 {
+        let right = PAGE_WIDTH - MARGIN;
+        let format_date = |date: NaiveDate| date.format("%Y年%-m月%-d日").to_string();
+
+        page.text_center(770.0, 24.0, RECEIPT_TITLE);
+
+        page.text(MARGIN, 720.0, 14.0, &format!("{} 様", self.addressee));
+        page.line(MARGIN, 715.0, 300.0, 715.0);
+
+        page.text_right(
+            right,
+            720.0,
+            10.0,
+            &format!("作成日 {}", format_date(self.issued_on)),
+        );
+
+        page.text(MARGIN, 680.0, 12.0, "合計金額");
+        page.text(130.0, 678.0, 20.0, &format_yen(self.total()));
+        page.line(MARGIN, 672.0, 300.0, 672.0);
+
+        page.text(MARGIN, 652.0, 10.0, &format!("用途 {}", self.note));
+
+        let (from, to) = self.period();
+        page.text(
+            MARGIN,
+            636.0,
+            10.0,
+            &format!("利用期間 {} 〜 {}", format_date(from), format_date(to)),
+        );
+
+        for (index, notice) in RECEIPT_NOTICE.iter().enumerate() {
+            page.text(MARGIN, 610.0 - index as f32 * 14.0, 9.0, notice);
+        }
+
+        write_table_header(page, FIRST_TABLE_TOP)
+    }
+}
+
+fn write_table_header(page: &mut Page, top: f32) -> f32 {
+    page.text(DATE_COLUMN, top, 10.0, "日付");
+    page.text(DESCRIPTION_COLUMN, top, 10.0, "内容");
+    page.text_right(PAGE_WIDTH - MARGIN, top, 10.0, "金額");
+    page.line(MARGIN, top - 6.0, PAGE_WIDTH - MARGIN, top - 6.0);
+
+    top - 6.0
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::*;
+    use crate::libs::usage_history::{parse_usage_history, ride};
+
+    fn issued_on() -> NaiveDate {
+        NaiveDate::from_ymd_opt(2023, 9, 20).unwrap()
+    }
+
+    fn date(year: i32, month: u32, day: u32) -> NaiveDate {
+        NaiveDate::from_ymd_opt(year, month, day).unwrap()
+    }
+
+    fn history() -> Vec<UsageRecord> {
+        parse_usage_history(include_str!("../../test/history.html")).unwrap()
+    }
+
+    fn entries(records: &[UsageRecord], today: NaiveDate) -> Vec<(NaiveDate, UsageRecord)> {
+        records
+            .iter()
+            .filter_map(|record| Some((record.infer_date(today)?, record.clone())))
+            .collect()
+    }
+
+    fn receipt() -> Receipt {
+        let entries = entries(&history(), issued_on());
+
+        Receipt::new("山田太郎", DEFAULT_RECEIPT_NOTE, issued_on(), &entries).unwrap()
+    }
+
+    #[test]
+    fn test_receipt_new() {
+        let receipt = receipt();
+
+        // チャージと繰越の行は含めず、古い順に並べる
+        assert_eq!(receipt.lines.len(), 4);
+        assert_eq!(receipt.lines[0].date, date(2023, 9, 14));
+        assert_eq!(receipt.lines[1].description, "渋谷 〜 横浜");
+        // 同じ日の中では乗った順
+        assert_eq!(receipt.lines[2].description, "物販");
+        assert_eq!(receipt.lines[3].description, "東京 〜 新宿");
+        assert_eq!(receipt.total(), 1000);
+        assert_eq!(receipt.period(), (date(2023, 9, 14), date(2023, 9, 16)));
+
+        let charge = UsageRecord {
+            entry_kind: "ｶｰﾄﾞ".to_string(),
+            amount: Some(1000),
+            ..Default::default()
+        };
+
+        assert_eq!(
+            Receipt::new(
+                "",
+                DEFAULT_RECEIPT_NOTE,
+                issued_on(),
+                &[(issued_on(), charge)]
+            ),
+            Err(ReceiptError::NoPayments)
+        );
+    }
+
+    #[test]
+    fn test_receipt_period_across_years() {
+        let today = date(2024, 1, 10);
+        let records = vec![
+            ride("01/02", "東京", "新宿", -208),
+            ride("12/28", "東京", "新宿", -208),
+        ];
+
+        let receipt =
+            Receipt::new("", DEFAULT_RECEIPT_NOTE, today, &entries(&records, today)).unwrap();
+
+        assert_eq!(receipt.period(), (date(2023, 12, 28), date(2024, 1, 2)));
+    }
+
+    #[test]
+    fn test_select_records() {
+        let history = history();
+
+        // 利用履歴の行に置き換えるので、金額や駅を書き換えた行は選べない
+        let selected = vec![
+            UsageRecord {
+                project: Some("PRJ-001".to_string()),
+                ..history[3].clone()
+            },
+            history[0].clone(),
+        ];
+
+        assert_eq!(
+            select_records(&history, &selected).unwrap(),
+            vec![history[0].clone(), history[3].clone()]
+        );
+
+        let forged = UsageRecord {
+            amount: Some(-20800),
+            ..history[0].clone()
+        };
+
+        assert_eq!(
+            select_records(&history, &[forged]),
+            Err(ReceiptError::RecordNotFound)
+        );
+        // 同じ行を重ねて選ぶことはできない
+        assert_eq!(
+            select_records(&history, &[history[0].clone(), history[0].clone()]),
+            Err(ReceiptError::RecordNotFound)
+        );
+    }
+
+    #[test]
+    fn test_to_pdf() {
+        let pdf = String::from_utf8(receipt().to_pdf()).unwrap();
+
+        assert!(pdf.starts_with("%PDF-1.4\n"));
+        assert!(pdf.ends_with("%%EOF\n"));
+        // 利用明細
+        assert!(pdf.contains("<52297528660E7D30>"));
+        // 発行者として東日本旅客鉄道を載せない
+        assert!(!pdf.contains("677165E5672C65C55BA292449053"));
+
+        // startxrefがxref表を指している
+        let offset = pdf
+            .lines()
+            .rev()
+            .nth(1)
+            .and_then(|line| line.parse::<usize>().ok())
+            .unwrap();
+        assert!(pdf[offset..].starts_with("xref\n"));
+    }
+
+    #[test]
+    fn test_to_pdf_pages() {
+        let records = vec![ride("09/16", "東京", "新宿", -208); 100];
+
+        let receipt = Receipt::new(
+            "",
+            DEFAULT_RECEIPT_NOTE,
+            issued_on(),
+            &entries(&records, issued_on()),
+        )
+        .unwrap();
+        let pdf = String::from_utf8(receipt.to_pdf()).unwrap();
+
+        assert_eq!(receipt.total(), 20800);
+        assert!(pdf.contains("/Count 3"));
+    }
+}
@@ -0,0 +1,479 @@
+use crate::libs::commuter_pass::CommuterPass;
+use crate::libs::green_ticket::GREEN_TICKET_KIND;
+use crate::libs::japanese_text::format_yen;
+use crate::libs::pdf::{write_pdf, Page, MARGIN, PAGE_WIDTH};
+use crate::libs::tag_rules::{apply_tag_rules, TagCategory, TagRule};
+use crate::libs::usage_history::UsageRecord;
+
+use chrono::{Datelike, NaiveDate};
+use serde::Serialize;
+
+// ルールに名前がない場合などの用務
+pub const DEFAULT_REPORT_PURPOSE: &str = "業務";
+
+const WEEKDAYS: [&str; 7] = ["月", "火", "水", "木", "金", "土", "日"];
+
+const ROW_HEIGHT: f32 = 16.0;
+// 1ページ目は期間や氏名の下から表を始める
+const FIRST_TABLE_TOP: f32 = 640.0;
+const TABLE_TOP: f32 = 780.0;
+const TABLE_BOTTOM: f32 = 70.0;
+
+const DATE_COLUMN: f32 = MARGIN;
+const PURPOSE_COLUMN: f32 = MARGIN + 60.0;
+const FROM_COLUMN: f32 = MARGIN + 180.0;
+const TO_COLUMN: f32 = MARGIN + 270.0;
+const PROJECT_COLUMN: f32 = MARGIN + 360.0;
+const AMOUNT_COLUMN: f32 = PAGE_WIDTH - MARGIN;
+// 列の間の余白
+const COLUMN_GAP: f32 = 6.0;
+
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct ReportLine {
+    pub date: NaiveDate,
+    pub purpose: String,
+    pub from_station: String,
+    pub to_station: String,
+    // 運賃（正）
+    pub fare: i64,
+    pub project: Option<String>,
+}
+
+// プロジェクトごとの小計。プロジェクトのない乗車はNone
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct ProjectSubtotal {
+    pub project: Option<String>,
+    pub rides: usize,
+    pub fare: i64,
+}
+
+// 交通費精算書
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct MonthlyReport {
+    pub employee: String,
+    pub year: i32,
+    pub month: u32,
+    pub period_from: NaiveDate,
+    pub period_to: NaiveDate,
+    pub created_on: NaiveDate,
+    // 日付の古い順
+    pub lines: Vec<ReportLine>,
+    pub subtotals: Vec<ProjectSubtotal>,
+    pub total: i64,
+}
+
+fn is_ride(record: &UsageRecord) -> bool {
+    record.entry_kind == "入" || record.exit_kind == "出" || record.entry_kind == GREEN_TICKET_KIND
+}
+
+// 駅の表で正規化できた場合はその駅名
+fn station(name: &Option<String>, place: &str) -> String {
+    name.clone().unwrap_or(place.to_string())
+}
+
+// 業務に区分したルールの名前を用務にする
+fn purpose(record: &UsageRecord, date: NaiveDate, rules: &[TagRule]) -> String {
+    rules
+        .iter()
+        .find(|rule| {
+            rule.category == Some(TagCategory::Business) && rule.matcher.matches(record, Some(date))
+        })
+        .map(|rule| rule.name.trim().to_string())
+        .filter(|name| !name.is_empty())
+        .unwrap_or(DEFAULT_REPORT_PURPOSE.to_string())
+}
+
+// 定期券の区間の乗車は精算しないので含めない
+fn create_line(
+    record: &UsageRecord,
+    date: NaiveDate,
+    rules: &[TagRule],
+    commuter_pass: Option<&CommuterPass>,
+) -> Option<ReportLine> {
+    let mut record = record.clone();
+
+    // 年を補った日付を基準にすれば、ルールの曜日はその日の曜日で判定される
+    apply_tag_rules(std::slice::from_mut(&mut record), rules, date);
+
+    if !is_ride(&record) || record.category != Some(TagCategory::Business) {
+        return None;
+    }
+
+    if commuter_pass.is_some_and(|commuter_pass| commuter_pass.covers_on(&record, date)) {
+        return None;
+    }
+
+    let fare = record.amount.filter(|amount| *amount < 0)?;
+
+    Some(ReportLine {
+        date,
+        purpose: purpose(&record, date, rules),
+        from_station: station(&record.entry_station, &record.entry_place),
+        to_station: station(&record.exit_station, &record.exit_place),
+        fare: -fare,
+        project: record.project,
+    })
+}
+
+fn create_subtotals(lines: &[ReportLine]) -> Vec<ProjectSubtotal> {
+    let mut subtotals: Vec<ProjectSubtotal> = vec![];
+
+    for line in lines {
+        match subtotals
+            .iter_mut()
+            .find(|subtotal| subtotal.project == line.project)
+        {
+            Some(subtotal) => {
+                subtotal.rides += 1;
+                subtotal.fare += line.fare;
+            }
+            None => subtotals.push(ProjectSubtotal {
+                project: line.project.clone(),
+                rides: 1,
+                fare: line.fare,
+            }),
+        }
+    }
+
+    // プロジェクト名の順。プロジェクトのないものは最後
+    subtotals.sort_by_key(|subtotal| (subtotal.project.is_none(), subtotal.project.clone()));
+
+    subtotals
+}
+
+fn format_date(date: NaiveDate) -> String {
+    format!(
+        "{}/{}({})",
+        date.month(),
+        date.day(),
+        WEEKDAYS[date.weekday().num_days_from_monday() as usize]
+    )
+}
+
+impl MonthlyReport {
+    // entriesは年を補った日付と利用履歴の組で、利用履歴と同じく新しい順
+    // ルールで業務に区分された乗車のうち、その月のものを古い順に載せる
+    pub fn new(
+        employee: &str,
+        year: i32,
+        month: u32,
+        created_on: NaiveDate,
+        entries: &[(NaiveDate, UsageRecord)],
+        rules: &[TagRule],
+        commuter_pass: Option<&CommuterPass>,
+    ) -> Option<Self> {
+        let period_from = NaiveDate::from_ymd_opt(year, month, 1)?;
+        let period_to = period_from
+            .checked_add_months(chrono::Months::new(1))?
+            .pred_opt()?;
+
+        let mut lines = entries
+            .iter()
+            .rev()
+            .filter(|(date, _)| (period_from..=period_to).contains(date))
+            .filter_map(|(date, record)| create_line(record, *date, rules, commuter_pass))
+            .collect::<Vec<ReportLine>>();
+
+        // 安定ソートなので、同じ日の中では逆順にした履歴の順（乗った順）が保たれる
+        lines.sort_by_key(|line| line.date);
+
+        let subtotals = create_subtotals(&lines);
+
+        Some(MonthlyReport {
+            employee: employee.to_string(),
+            year,
+            month,
+            period_from,
+            period_to,
+            created_on,
+            total: lines.iter().map(|line| line.fare).sum(),
+            lines,
+            subtotals,
+        })
+    }
+
+    pub fn to_pdf(&self) -> Vec<u8> {
+        let mut pages = vec![Page::default()];
+        let mut y = self.write_heading(&mut pages[0]);
+
+        for line in &self.lines {
+            if y - ROW_HEIGHT < TABLE_BOTTOM {
+                pages.push(Page::default());
+                y = write_table_header(pages.last_mut().unwrap(), TABLE_TOP);
+            }
+
+            let page = pages.last_mut().unwrap();
+            y -= ROW_HEIGHT;
+
+            let project = line.project.as_deref().unwrap_or_default();
+            let columns = [
+                (PURPOSE_COLUMN, FROM_COLUMN, line.purpose.as_str()),
+                (FROM_COLUMN, TO_COLUMN, &line.from_station),
+                (TO_COLUMN, PROJECT_COLUMN, &line.to_station),
+                (PROJECT_COLUMN, AMOUNT_COLUMN - 50.0, project),
+            ];
+
+            page.text(DATE_COLUMN, y, 10.0, &format_date(line.date));
+            for (left, right, text) in columns {
+                page.text_fit(left, y, 10.0, column_width(left, right), text);
+            }
+            page.text_right(AMOUNT_COLUMN, y, 10.0, &format_yen(line.fare));
+        }
+
+        // 小計の見出しと合計の分。小計が多い場合は途中で改ページする
+        if y - ROW_HEIGHT * 4.0 < TABLE_BOTTOM {
+            pages.push(Page::default());
+            y = TABLE_TOP;
+        }
+
+        let page = pages.last_mut().unwrap();
+        page.line(MARGIN, y - 6.0, AMOUNT_COLUMN, y - 6.0);
+        y -= ROW_HEIGHT * 1.5;
+        page.text(PURPOSE_COLUMN, y, 10.0, "プロジェクト別小計");
+
+        for subtotal in &self.subtotals {
+            if y - ROW_HEIGHT * 3.0 < TABLE_BOTTOM {
+                pages.push(Page::default());
+                y = TABLE_TOP;
+            }
+
+            let page = pages.last_mut().unwrap();
+            y -= ROW_HEIGHT;
+
+            let project = subtotal.project.as_deref().unwrap_or("(なし)");
+            let width = column_width(FROM_COLUMN, PROJECT_COLUMN);
+
+            page.text_fit(FROM_COLUMN, y, 10.0, width, project);
+            page.text(PROJECT_COLUMN, y, 10.0, &format!("{}件", subtotal.rides));
+            page.text_right(AMOUNT_COLUMN, y, 10.0, &format_yen(subtotal.fare));
+        }
+
+        let page = pages.last_mut().unwrap();
+        page.line(FROM_COLUMN, y - 6.0, AMOUNT_COLUMN, y - 6.0);
+        y -= ROW_HEIGHT * 1.5;
+        page.text(PURPOSE_COLUMN, y, 12.0, "合計");
+        page.text_right(AMOUNT_COLUMN, y, 12.0, &format_yen(self.total));
+
+        write_pdf(&mut pages)
+    }
+
+    // 表題、期間、氏名、承認欄を書いて表の開始位置を返す
+    fn write_heading(&self, page: &mut Page) -> f32 {
+        page.text_center(770.0, 20.0, "交通費精算書");
+
+        page.text(
+            MARGIN,
+            720.0,
+            12.0,
+            &format!("{}年{}月分", self.year, self.month),
+        );
+        page.text(
+            MARGIN,
+            700.0,
+            10.0,
+            &format!(
+                "対象期間 {} 〜 {}",
+                self.period_from.format("%Y年%-m月%-d日"),
+                self.period_to.format("%Y年%-m月%-d日")
+            ),
+        );
+        page.text(MARGIN, 680.0, 12.0, &format!("氏名 {}", self.employee));
+        page.line(MARGIN, 675.0, 260.0, 675.0);
+        page.text(
+            MARGIN,
+            660.0,
+            10.0,
+            &format!("作成日 {}", self.created_on.format("%Y年%-m月%-d日")),
+        );
+
+        write_stamp_boxes(page);
+
+        write_table_header(page, FIRST_TABLE_TOP)
+    }
+}
+
+fn column_width(left: f32, right: f32) -> f32 {
+    right - left - COLUMN_GAP
+}
+
+// 右上の承認・確認・申請の押印欄
+fn write_stamp_boxes(page: &mut Page) {
+    const SIZE: f32 = 50.0;
+    const TOP: f32 = 730.0;
+    const LABEL_HEIGHT: f32 = 14.0;
+
+    for (index, label) in ["承認", "確認", "申請"].iter().enumerate() {
+        let left = AMOUNT_COLUMN - SIZE * (3 - index) as f32;
+        let right = left + SIZE;
+        let bottom = TOP - LABEL_HEIGHT - SIZE;
+
+        page.line(left, TOP, right, TOP);
+        page.line(left, TOP - LABEL_HEIGHT, right, TOP - LABEL_HEIGHT);
+        page.line(left, bottom, right, bottom);
+        page.line(left, TOP, left, bottom);
+        page.line(right, TOP, right, bottom);
+        page.text(left + 15.0, TOP - 11.0, 10.0, label);
+    }
+}
+
+fn write_table_header(page: &mut Page, top: f32) -> f32 {
+    page.text(DATE_COLUMN, top, 10.0, "日付");
+    page.text(PURPOSE_COLUMN, top, 10.0, "用務");
+    page.text(FROM_COLUMN, top, 10.0, "出発");
+    page.text(TO_COLUMN, top, 10.0, "到着");
+    page.text(PROJECT_COLUMN, top, 10.0, "プロジェクト");
+    page.text_right(AMOUNT_COLUMN, top, 10.0, "金額");
+    page.line(MARGIN, top - 6.0, AMOUNT_COLUMN, top - 6.0);
+
+    top - 6.0
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::*;
+    use crate::libs::commuter_pass::parse_commuter_pass;
+    use crate::libs::usage_history::ride;
+
+    fn date(month: u32, day: u32) -> NaiveDate {
+        NaiveDate::from_ymd_opt(2023, month, day).unwrap()
+    }
+
+    fn rules() -> Vec<TagRule> {
+        serde_json::from_str(
+            r#"[
+                {
+                    "name": "A社打合せ",
+                    "match": { "stations": ["渋谷", "横浜"] },
+                    "category": "business",
+                    "project": "PRJ-001"
+                },
+                {
+                    "name": "通勤",
+                    "match": { "stations": ["新宿", "東京"], "weekdays": ["Mon", "Tue", "Wed", "Thu", "Fri"] },
+                    "category": "business"
+                },
+                {
+                    "name": "B社の案件",
+                    "match": { "stations": ["東京", "品川"] },
+                    "project": "PRJ-002"
+                },
+                {
+                    "name": "出張",
+                    "match": { "stations": ["東京", "品川"] },
+                    "category": "business"
+                }
+            ]"#,
+        )
+        .unwrap()
+    }
+
+    // 2023/09/15は金曜日、09/16は土曜日
+    fn entries() -> Vec<(NaiveDate, UsageRecord)> {
+        vec![
+            (date(10, 2), ride("10/02", "東京", "新宿", -208)),
+            (date(9, 16), ride("09/16", "東京", "新宿", -208)),
+            (date(9, 16), ride("09/16", "東京", "品川", -178)),
+            (date(9, 15), ride("09/15", "渋谷", "横浜", -318)),
+            (date(9, 15), ride("09/15", "東京", "新宿", -208)),
+            (
+                date(9, 15),
+                UsageRecord {
+                    date: "09/15".to_string(),
+                    entry_kind: "物販".to_string(),
+                    amount: Some(-264),
+                    ..Default::default()
+                },
+            ),
+            (date(8, 31), ride("08/31", "渋谷", "横浜", -318)),
+        ]
+    }
+
+    fn report() -> MonthlyReport {
+        MonthlyReport::new("山田太郎", 2023, 9, date(10, 2), &entries(), &rules(), None).unwrap()
+    }
+
+    #[test]
+    fn test_monthly_report_new() {
+        let report = report();
+
+        assert_eq!(report.period_from, date(9, 1));
+        assert_eq!(report.period_to, date(9, 30));
+
+        // 土曜日の通勤と物販、対象月以外は載せない
+        assert_eq!(report.lines.len(), 3);
+        // 同じ日の中では乗った順
+        assert_eq!(report.lines[0].date, date(9, 15));
+        assert_eq!(report.lines[0].purpose, "通勤");
+        assert_eq!(report.lines[0].project, None);
+        assert_eq!(report.lines[1].date, date(9, 15));
+        assert_eq!(report.lines[1].purpose, "A社打合せ");
+        assert_eq!(report.lines[1].from_station, "渋谷");
+        assert_eq!(report.lines[2].purpose, "出張");
+        assert_eq!(report.lines[2].project, Some("PRJ-002".to_string()));
+
+        assert_eq!(
+            report.subtotals,
+            vec![
+                ProjectSubtotal {
+                    project: Some("PRJ-001".to_string()),
+                    rides: 1,
+                    fare: 318,
+                },
+                ProjectSubtotal {
+                    project: Some("PRJ-002".to_string()),
+                    rides: 1,
+                    fare: 178,
+                },
+                ProjectSubtotal {
+                    project: None,
+                    rides: 1,
+                    fare: 208,
+                },
+            ]
+        );
+        assert_eq!(report.total, 704);
+
+        assert_eq!(
+            MonthlyReport::new("", 2023, 13, date(10, 2), &[], &rules(), None),
+            None
+        );
+    }
+
+    #[test]
+    fn test_monthly_report_commuter_pass() {
+        let commuter_pass =
+            parse_commuter_pass(include_str!("../../test/commuter_pass.html")).unwrap();
+
+        let mut entries = entries();
+        entries.push((date(9, 20), ride("09/20", "JR東 新宿", "JR東 東京", -208)));
+
+        let report = MonthlyReport::new(
+            "山田太郎",
+            2023,
+            9,
+            date(10, 2),
+            &entries,
+            &rules(),
+            Some(&commuter_pass),
+        )
+        .unwrap();
+
+        // 東京〜新宿の通勤は定期券で乗れるので載せない
+        assert_eq!(report.lines.len(), 2);
+        assert_eq!(report.lines[0].purpose, "A社打合せ");
+        assert_eq!(report.lines[1].purpose, "出張");
+        assert_eq!(report.total, 496);
+    }
+
+    #[test]
+    fn test_to_pdf() {
+        let pdf = String::from_utf8(report().to_pdf()).unwrap();
+
+        assert!(pdf.starts_with("%PDF-1.4\n"));
+        assert!(pdf.ends_with("%%EOF\n"));
+        // 交通費精算書
+        assert!(pdf.contains("<4EA4901A8CBB7CBE7B9766F8>"));
+        assert!(pdf.contains("/Count 1"));
+    }
+}
@@ -153,6 +153,29 @@ impl Session {
         false
     }
 
+    pub fn id(&self) -> &str {
+        &self.id
+    }
+
+    // データを破棄し、新しいIDのセッションに切り替える
+    // アプリのユーザーが替わるときに、前のユーザーのモバイルSuicaのCookieやセッションIDを引き継がないようにする
+    pub fn renew(&mut self) {
+        let mut session_store = get_session_store().lock().unwrap();
+
+        clear_session(&mut session_store, &self.id);
+
+        self.id = genereate_session_id();
+        self.data.clear();
+        self.update_expired_at();
+
+        session_store.insert(self.id.clone(), self.clone());
+    }
+
+    // 有効期限（UNIX時間の秒）
+    pub fn expired_at(&self) -> u64 {
+        self.expired_at
+    }
+
     pub fn update_expired_at(&mut self) -> () {
         self.expired_at = (chrono::Local::now().timestamp() as u64) + SESSION_EXPIRED_TIME;
     }
@@ -289,4 +312,21 @@ mod test {
 
         assert_eq!(session.is_some(), true);
     }
+
+    #[test]
+    fn test_renew() {
+        let session_id = Session::new();
+
+        let mut session = Session::get_session(&session_id).unwrap();
+        session.set("auth_cookies", "cookies");
+
+        session.renew();
+
+        assert_ne!(session.id(), session_id);
+        assert!(!Session::has_session(&session_id));
+        assert_eq!(session.get::<String>("auth_cookies"), None);
+
+        let renewed = Session::get_session(session.id()).unwrap();
+        assert_eq!(renewed.get::<String>("auth_cookies"), None);
+    }
 }
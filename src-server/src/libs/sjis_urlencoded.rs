@@ -0,0 +1,238 @@
+use encoding_rs::SHIFT_JIS;
+
+const HEX: &[u8; 16] = b"0123456789ABCDEF";
+
+// application/x-www-form-urlencodedでそのまま出力できるバイトか
+fn is_unreserved(byte: u8) -> bool {
+    matches!(byte, b'*' | b'-' | b'.' | b'_') || byte.is_ascii_alphanumeric()
+}
+
+fn hex_value(byte: u8) -> Option<u8> {
+    match byte {
+        b'0'..=b'9' => Some(byte - b'0'),
+        b'A'..=b'F' => Some(byte - b'A' + 10),
+        b'a'..=b'f' => Some(byte - b'a' + 10),
+        _ => None,
+    }
+}
+
+// SJISにしてURIエンコードした結果をbufferに追記する
+pub fn encode_uri_from_sjis_into(text: &str, buffer: &mut String) {
+    let (bytes, _, _) = SHIFT_JIS.encode(text);
+
+    buffer.reserve(bytes.len() * 3);
+
+    for &byte in bytes.iter() {
+        match byte {
+            b' ' => buffer.push('+'),
+            _ if is_unreserved(byte) => buffer.push(byte as char),
+            _ => {
+                buffer.push('%');
+                buffer.push(HEX[(byte >> 4) as usize] as char);
+                buffer.push(HEX[(byte & 0x0F) as usize] as char);
+            }
+        }
+    }
+}
+
+// SJISにしてURIエンコードする
+pub fn encode_uri_from_sjis(text: &str) -> String {
+    let mut buffer = String::new();
+
+    encode_uri_from_sjis_into(text, &mut buffer);
+
+    buffer
+}
+
+// URIデコードしてSJISとして読む。不正なエンコードの場合はNone
+pub fn decode_uri_from_sjis(encoded: &str) -> Option<String> {
+    let mut bytes = Vec::with_capacity(encoded.len());
+    let mut iter = encoded.bytes();
+
+    while let Some(byte) = iter.next() {
+        match byte {
+            b'+' => bytes.push(b' '),
+            b'%' => {
+                let high = hex_value(iter.next()?)?;
+                let low = hex_value(iter.next()?)?;
+                bytes.push((high << 4) | low);
+            }
+            _ => bytes.push(byte),
+        }
+    }
+
+    SHIFT_JIS
+        .decode_without_bom_handling_and_without_replacement(&bytes)
+        .map(|text| text.into_owned())
+}
+
+// 任意のキーと値の組をSJISのapplication/x-www-form-urlencodedにする
+pub fn serialize_into_sjis<I, K, V>(pairs: I) -> String
+where
+    I: IntoIterator<Item = (K, V)>,
+    K: AsRef<str>,
+    V: AsRef<str>,
+{
+    let mut buffer = String::new();
+
+    for (index, (key, value)) in pairs.into_iter().enumerate() {
+        if index > 0 {
+            buffer.push('&');
+        }
+        encode_uri_from_sjis_into(key.as_ref(), &mut buffer);
+        buffer.push('=');
+        encode_uri_from_sjis_into(value.as_ref(), &mut buffer);
+    }
+
+    buffer
+}
+
+// serialize_into_sjisの逆変換
+pub fn deserialize_from_sjis(body: &str) -> Option<Vec<(String, String)>> {
+    body.split('&')
+        .filter(|pair| !pair.is_empty())
+        .map(|pair| {
+            let (key, value) = pair.split_once('=').unwrap_or((pair, ""));
+
+            Some((decode_uri_from_sjis(key)?, decode_uri_from_sjis(value)?))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::*;
+    use proptest::prelude::*;
+
+    #[test]
+    fn test_encode_uri_from_sjis() {
+        let text = "あいうえお";
+
+        let encoded = encode_uri_from_sjis(text);
+
+        assert_eq!(encoded, "%82%A0%82%A2%82%A4%82%A6%82%A8");
+
+        let text2 = "ABCDEFGHIJKLMNOPQRSTUVWXYZ";
+
+        let encoded2 = encode_uri_from_sjis(text2);
+
+        assert_eq!(encoded2, "ABCDEFGHIJKLMNOPQRSTUVWXYZ");
+    }
+
+    #[test]
+    fn test_encode_uri_from_sjis_control_chars() {
+        // 0x10未満のバイトも2桁でエンコードする
+        assert_eq!(encode_uri_from_sjis("a\nb\tc d"), "a%0Ab%09c+d");
+    }
+
+    #[test]
+    fn test_decode_uri_from_sjis() {
+        assert_eq!(
+            decode_uri_from_sjis("%82%A0%82%A2%82%A4%82%A6%82%A8").unwrap(),
+            "あいうえお"
+        );
+        assert_eq!(
+            decode_uri_from_sjis("a+b%40example.com").unwrap(),
+            "a b@example.com"
+        );
+        assert_eq!(decode_uri_from_sjis("%8"), None);
+        assert_eq!(decode_uri_from_sjis("%ZZ"), None);
+        // SJISとして不正なバイト列
+        assert_eq!(decode_uri_from_sjis("%82"), None);
+    }
+
+    #[test]
+    fn test_serialize_into_sjis() {
+        let serialized = serialize_into_sjis([("LOGIN", "ログイン"), ("mail", "a b@example.com")]);
+
+        assert_eq!(
+            serialized,
+            "LOGIN=%83%8D%83O%83C%83%93&mail=a+b%40example.com"
+        );
+
+        let pairs = vec![("key".to_string(), String::new())];
+        assert_eq!(serialize_into_sjis(pairs), "key=");
+
+        assert_eq!(serialize_into_sjis(Vec::<(&str, &str)>::new()), "");
+    }
+
+    #[test]
+    fn test_deserialize_from_sjis() {
+        assert_eq!(
+            deserialize_from_sjis("LOGIN=%83%8D%83O%83C%83%93&empty=&flag").unwrap(),
+            vec![
+                ("LOGIN".to_string(), "ログイン".to_string()),
+                ("empty".to_string(), "".to_string()),
+                ("flag".to_string(), "".to_string()),
+            ]
+        );
+    }
+
+    // SJISで往復できる文字だけにする
+    fn retain_sjis(text: String) -> String {
+        text.chars()
+            .filter(|c| {
+                let text = c.to_string();
+                let (bytes, _, had_errors) = SHIFT_JIS.encode(&text);
+                !had_errors && SHIFT_JIS.decode_without_bom_handling(&bytes).0 == text
+            })
+            .collect()
+    }
+
+    fn sjis_text() -> impl Strategy<Value = String> {
+        any::<String>().prop_map(retain_sjis)
+    }
+
+    fn japanese_text() -> impl Strategy<Value = String> {
+        "[ -~ぁ-んァ-ヶ東京新宿横浜駅線入出　、。ー！-～｡-ﾟ\n]{0,64}".prop_map(retain_sjis)
+    }
+
+    proptest! {
+        #[test]
+        fn prop_roundtrip(text in sjis_text()) {
+            let encoded = encode_uri_from_sjis(&text);
+
+            prop_assert_eq!(decode_uri_from_sjis(&encoded), Some(text));
+        }
+
+        #[test]
+        fn prop_roundtrip_japanese(text in japanese_text()) {
+            let encoded = encode_uri_from_sjis(&text);
+
+            prop_assert_eq!(decode_uri_from_sjis(&encoded), Some(text));
+        }
+
+        #[test]
+        fn prop_encoded_is_ascii(text in sjis_text()) {
+            let encoded = encode_uri_from_sjis(&text);
+
+            prop_assert!(encoded
+                .bytes()
+                .all(|byte| is_unreserved(byte) || byte == b'%' || byte == b'+'));
+        }
+
+        #[test]
+        fn prop_matches_sjis_bytes(bytes in proptest::collection::vec(any::<u8>(), 0..64)) {
+            let (expected, _) = SHIFT_JIS.decode_without_bom_handling(&bytes);
+            let encoded = bytes
+                .iter()
+                .map(|byte| format!("%{:02X}", byte))
+                .collect::<String>();
+
+            match decode_uri_from_sjis(&encoded) {
+                Some(decoded) => prop_assert_eq!(decoded, expected.into_owned()),
+                None => prop_assert!(expected.contains(char::REPLACEMENT_CHARACTER)),
+            }
+        }
+
+        #[test]
+        fn prop_serialize_roundtrip(
+            pairs in proptest::collection::vec((sjis_text(), sjis_text()), 0..8)
+        ) {
+            let serialized = serialize_into_sjis(pairs.iter().map(|(k, v)| (k, v)));
+
+            prop_assert_eq!(deserialize_from_sjis(&serialized), Some(pairs));
+        }
+    }
+}
@@ -0,0 +1,191 @@
+use crate::libs::japanese_text::normalize_text;
+
+use serde::{Deserialize, Serialize};
+use std::sync::OnceLock;
+
+// 関東のJR東日本・東京メトロ・都営地下鉄と私鉄各線の駅。路線ごとに1行で、緯度経度は駅付近のおおよその位置
+// JR東日本を先に並べ、同名の駅はJRを優先する
+const STATIONS_CSV: &str = include_str!("../../data/stations.csv");
+
+// 利用履歴で駅名の前に付く事業者の略称と、駅の表の事業者名
+// 長いものから順に試す
+const COMPANY_PREFIXES: [(&str, &str); 7] = [
+    ("JR東", "JR東日本"),
+    ("メトロ", "東京メトロ"),
+    ("都営", "都営地下鉄"),
+    ("東急", "東急電鉄"),
+    ("JR", "JR東日本"),
+    ("地", "東京メトロ"),
+    ("都", "都営地下鉄"),
+];
+
+// 省略された駅名として前方一致を許す最短の文字数
+const MIN_TRUNCATED_CHARS: usize = 2;
+
+static STATIONS: OnceLock<Vec<Station>> = OnceLock::new();
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Station {
+    pub company: String,
+    pub line: String,
+    // 駅ナンバリング（JY01 など）
+    pub code: String,
+    pub name: String,
+    pub romaji: String,
+    pub latitude: f64,
+    pub longitude: f64,
+    // 履歴での別表記。;区切り
+    #[serde(skip_serializing)]
+    pub aliases: String,
+}
+
+impl Station {
+    fn matches(&self, name: &str) -> bool {
+        self.name == name || self.aliases.split(';').any(|alias| alias == name)
+    }
+}
+
+pub fn get_stations() -> &'static [Station] {
+    STATIONS.get_or_init(|| {
+        csv::Reader::from_reader(STATIONS_CSV.as_bytes())
+            .deserialize()
+            .collect::<Result<Vec<Station>, csv::Error>>()
+            .expect("data/stations.csv is invalid")
+    })
+}
+
+pub fn find_station_by_code(code: &str) -> Option<&'static Station> {
+    get_stations().iter().find(|station| station.code == code)
+}
+
+// 全角英数字と半角カナをそろえ、空白と末尾の駅を取り除く
+fn normalize_name(name: &str) -> String {
+    let name = normalize_text(name).replace(' ', "");
+
+    match name.strip_suffix('駅') {
+        Some(stripped) if !stripped.is_empty() => stripped.to_string(),
+        _ => name,
+    }
+}
+
+fn find_station(name: &str, company: Option<&str>) -> Option<&'static Station> {
+    let in_company = |station: &&Station| company.is_none_or(|company| station.company == company);
+
+    if let Some(station) = get_stations()
+        .iter()
+        .filter(in_company)
+        .find(|station| station.matches(name))
+    {
+        return Some(station);
+    }
+
+    if name.chars().count() < MIN_TRUNCATED_CHARS {
+        return None;
+    }
+
+    // 途中で切れた駅名は、候補が1つの駅名に絞れる場合のみ採用する
+    let candidates = get_stations()
+        .iter()
+        .filter(in_company)
+        .filter(|station| station.name.starts_with(name))
+        .collect::<Vec<&Station>>();
+
+    let first = candidates.first()?;
+
+    match candidates.iter().all(|station| station.name == first.name) {
+        true => Some(first),
+        false => None,
+    }
+}
+
+// 利用履歴の駅名を駅の表と照らし合わせる。見つからない場合はNone
+pub fn normalize_station(name: &str) -> Option<&'static Station> {
+    let name = normalize_name(name);
+
+    if name.is_empty() {
+        return None;
+    }
+
+    // 事業者の略称が付いている場合は、その事業者の駅から探す
+    let prefixed = COMPANY_PREFIXES.iter().find_map(|(prefix, company)| {
+        let rest = name.strip_prefix(prefix).filter(|rest| !rest.is_empty())?;
+
+        find_station(rest, Some(company))
+    });
+
+    prefixed.or_else(|| find_station(&name, None))
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::*;
+
+    fn code(name: &str) -> Option<&str> {
+        normalize_station(name).map(|station| station.code.as_str())
+    }
+
+    #[test]
+    fn test_get_stations() {
+        let stations = get_stations();
+
+        assert!(!stations.is_empty());
+        assert_eq!(find_station_by_code("JY01").unwrap().name, "東京");
+        assert_eq!(find_station_by_code("JY01").unwrap().romaji, "Tokyo");
+        assert_eq!(find_station_by_code("KK37").unwrap().name, "横浜");
+        assert_eq!(find_station_by_code("E28").unwrap().name, "都庁前");
+
+        // 駅ナンバリングは重複しない
+        let mut codes = stations
+            .iter()
+            .map(|station| station.code.as_str())
+            .collect::<Vec<&str>>();
+        codes.sort();
+        codes.dedup();
+        assert_eq!(codes.len(), stations.len());
+
+        for company in [
+            "JR東日本",
+            "東京メトロ",
+            "都営地下鉄",
+            "東急電鉄",
+            "小田急電鉄",
+            "京王電鉄",
+            "西武鉄道",
+            "東武鉄道",
+            "京成電鉄",
+            "京浜急行電鉄",
+            "相模鉄道",
+        ] {
+            assert!(stations.iter().any(|station| station.company == company));
+        }
+    }
+
+    #[test]
+    fn test_normalize_station() {
+        assert_eq!(code("東京"), Some("JY01"));
+        assert_eq!(code("ＪＲ東　東京"), Some("JY01"));
+        assert_eq!(code("渋谷駅"), Some("JY20"));
+        assert_eq!(code("東急渋谷"), Some("TY01"));
+        assert_eq!(code("地　銀座"), Some("G09"));
+        // 別表記と途中で切れた駅名
+        assert_eq!(code("お茶の水"), Some("JC03"));
+        assert_eq!(code("高輪ｹﾞｰﾄ"), Some("JY26"));
+        assert_eq!(code("梅ケ丘"), Some("OH09"));
+        // 霞ケ関（メトロ）と霞ヶ関（東武）は別の駅
+        assert_eq!(code("霞ケ関"), Some("M15"));
+        assert_eq!(code("霞ヶ関"), Some("TJ23"));
+        assert_eq!(code("都　大門"), Some("A09"));
+        assert_eq!(code("ﾓﾊﾞｲﾙ"), None);
+        assert_eq!(code(""), None);
+    }
+
+    #[test]
+    fn test_normalize_station_truncated() {
+        // 1文字では新宿と新大久保のどちらか決められない
+        assert_eq!(code("新"), None);
+        // 新大久保と新大塚のどちらか決められない
+        assert_eq!(code("新大"), None);
+        assert_eq!(code("新大久"), Some("JY16"));
+    }
+}
@@ -0,0 +1,256 @@
+use crate::libs::japanese_text::normalize_text;
+use crate::libs::stations::normalize_station;
+use crate::libs::usage_history::UsageRecord;
+
+use chrono::{Datelike, NaiveDate, Weekday};
+use serde::{Deserialize, Serialize};
+
+pub const MAX_TAG_RULES: usize = 100;
+const MAX_PROJECT_LENGTH: usize = 32;
+
+#[derive(Debug, PartialEq)]
+pub enum TagRuleError {
+    TooManyRules,
+    EmptyName,
+    // 区分もプロジェクトも付けないルール
+    NoTag,
+    InvalidProject,
+    InvalidAmountRange,
+}
+
+pub fn get_tag_rule_error_message(error: &TagRuleError) -> String {
+    match error {
+        TagRuleError::TooManyRules => format!("ルールは{}件までです。", MAX_TAG_RULES),
+        TagRuleError::EmptyName => "ルールの名前を入力してください。".to_string(),
+        TagRuleError::NoTag => "区分かプロジェクトのどちらかを指定してください。".to_string(),
+        TagRuleError::InvalidProject => format!(
+            "プロジェクトは{}文字以内で入力してください。",
+            MAX_PROJECT_LENGTH
+        ),
+        TagRuleError::InvalidAmountRange => {
+            "金額の範囲は下限が上限以下になるように指定してください。".to_string()
+        }
+    }
+}
+
+pub fn get_tag_rule_error_code(error: &TagRuleError) -> String {
+    match error {
+        TagRuleError::TooManyRules => "too_many_rules",
+        TagRuleError::EmptyName => "empty_name",
+        TagRuleError::NoTag => "no_tag",
+        TagRuleError::InvalidProject => "invalid_project",
+        TagRuleError::InvalidAmountRange => "invalid_amount_range",
+    }
+    .to_string()
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TagCategory {
+    Business,
+    Personal,
+}
+
+// 指定した条件をすべて満たす行に一致する。何も指定しない場合はすべての行
+// 利用履歴には時刻がないため、時間帯は条件にできない
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RuleMatcher {
+    // 2駅の組。向きは問わない
+    #[serde(default)]
+    pub stations: Option<[String; 2]>,
+    // Mon, Tue などの曜日
+    #[serde(default)]
+    pub weekdays: Option<Vec<Weekday>>,
+    // 金額の範囲（差額の絶対値）
+    #[serde(default)]
+    pub min_amount: Option<i64>,
+    #[serde(default)]
+    pub max_amount: Option<i64>,
+    // 入, 物販, カード などの種別
+    #[serde(default)]
+    pub kind: Option<String>,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct TagRule {
+    pub name: String,
+    #[serde(default, rename = "match")]
+    pub matcher: RuleMatcher,
+    #[serde(default)]
+    pub category: Option<TagCategory>,
+    // 案件コードなど
+    #[serde(default)]
+    pub project: Option<String>,
+}
+
+// 駅の表にある駅は正規化した駅名で比べる
+fn station_name(name: &str) -> String {
+    normalize_station(name)
+        .map(|station| station.name.clone())
+        .unwrap_or(normalize_text(name))
+}
+
+impl RuleMatcher {
+    // 曜日の判定には年を補った日付を使う。日付がわからない行は曜日の条件に一致しない
+    pub fn matches(&self, record: &UsageRecord, date: Option<NaiveDate>) -> bool {
+        let matches_stations = self.stations.as_ref().is_none_or(|[a, b]| {
+            let entry = record
+                .entry_station
+                .clone()
+                .unwrap_or(normalize_text(&record.entry_place));
+            let exit = record
+                .exit_station
+                .clone()
+                .unwrap_or(normalize_text(&record.exit_place));
+            let (a, b) = (station_name(a), station_name(b));
+
+            (entry == a && exit == b) || (entry == b && exit == a)
+        });
+
+        let matches_weekdays = self
+            .weekdays
+            .as_ref()
+            .is_none_or(|weekdays| date.is_some_and(|date| weekdays.contains(&date.weekday())));
+
+        let amount = record.amount.map(i64::abs);
+        let matches_amount = match (self.min_amount, self.max_amount) {
+            (None, None) => true,
+            (min, max) => amount.is_some_and(|amount| {
+                min.is_none_or(|min| min <= amount) && max.is_none_or(|max| amount <= max)
+            }),
+        };
+
+        let matches_kind = self
+            .kind
+            .as_ref()
+            .is_none_or(|kind| normalize_text(kind) == normalize_text(&record.entry_kind));
+
+        matches_stations && matches_weekdays && matches_amount && matches_kind
+    }
+}
+
+pub fn validate_tag_rules(rules: &[TagRule]) -> Result<(), TagRuleError> {
+    if rules.len() > MAX_TAG_RULES {
+        return Err(TagRuleError::TooManyRules);
+    }
+
+    for rule in rules {
+        if rule.name.trim().is_empty() {
+            return Err(TagRuleError::EmptyName);
+        }
+
+        if rule.category.is_none() && rule.project.is_none() {
+            return Err(TagRuleError::NoTag);
+        }
+
+        if rule.project.as_ref().is_some_and(|project| {
+            project.trim().is_empty() || project.chars().count() > MAX_PROJECT_LENGTH
+        }) {
+            return Err(TagRuleError::InvalidProject);
+        }
+
+        if let (Some(min), Some(max)) = (rule.matcher.min_amount, rule.matcher.max_amount) {
+            if min > max {
+                return Err(TagRuleError::InvalidAmountRange);
+            }
+        }
+    }
+
+    Ok(())
+}
+
+// ルールを上から順に試し、区分とプロジェクトはそれぞれ最初に一致したルールのものを付ける
+pub fn apply_tag_rules(records: &mut [UsageRecord], rules: &[TagRule], today: NaiveDate) {
+    for record in records.iter_mut() {
+        let date = record.infer_date(today);
+        let matched = rules
+            .iter()
+            .filter(|rule| rule.matcher.matches(record, date))
+            .collect::<Vec<&TagRule>>();
+
+        record.category = matched.iter().find_map(|rule| rule.category);
+        record.project = matched.iter().find_map(|rule| rule.project.clone());
+    }
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::*;
+    use crate::libs::usage_history::ride;
+
+    fn rules() -> Vec<TagRule> {
+        serde_json::from_str(
+            r#"[
+                {
+                    "name": "通勤",
+                    "match": { "stations": ["新宿", "東京"], "weekdays": ["Mon", "Tue", "Wed", "Thu", "Fri"] },
+                    "category": "business"
+                },
+                {
+                    "name": "A社の案件",
+                    "match": { "stations": ["渋谷", "横浜"] },
+                    "category": "business",
+                    "project": "PRJ-001"
+                },
+                {
+                    "name": "買い物",
+                    "match": { "kind": "物販", "max_amount": 1000 },
+                    "category": "personal"
+                }
+            ]"#,
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn test_apply_tag_rules() {
+        // 2023/09/15は金曜日、09/16は土曜日
+        let today = NaiveDate::from_ymd_opt(2023, 9, 20).unwrap();
+        let purchase = UsageRecord {
+            date: "09/16".to_string(),
+            entry_kind: "物販".to_string(),
+            amount: Some(-264),
+            ..Default::default()
+        };
+        let mut records = vec![
+            ride("09/16", "東京", "新宿", -208),
+            ride("09/15", "東京", "新宿", -208),
+            ride("09/15", "渋谷", "横浜", -318),
+            purchase,
+        ];
+
+        apply_tag_rules(&mut records, &rules(), today);
+
+        assert_eq!(records[0].category, None);
+        assert_eq!(records[1].category, Some(TagCategory::Business));
+        assert_eq!(records[1].project, None);
+        assert_eq!(records[2].project, Some("PRJ-001".to_string()));
+        assert_eq!(records[3].category, Some(TagCategory::Personal));
+    }
+
+    #[test]
+    fn test_validate_tag_rules() {
+        assert_eq!(validate_tag_rules(&rules()), Ok(()));
+
+        let mut rule = rules().remove(0);
+        rule.category = None;
+        assert_eq!(validate_tag_rules(&[rule]), Err(TagRuleError::NoTag));
+
+        let mut rule = rules().remove(0);
+        rule.matcher.min_amount = Some(500);
+        rule.matcher.max_amount = Some(100);
+        assert_eq!(
+            validate_tag_rules(&[rule]),
+            Err(TagRuleError::InvalidAmountRange)
+        );
+
+        // 時間帯の条件は受け付けない
+        let result = serde_json::from_str::<TagRule>(
+            r#"{ "name": "朝", "match": { "time_of_day": "07:00-09:00" }, "category": "business" }"#,
+        );
+        assert!(result.is_err());
+    }
+}
@@ -0,0 +1,287 @@
+use crate::libs::http_client::{get_text, BASE_URL};
+use crate::libs::japanese_text::{element_text, normalize_text, parse_month_day, parse_yen};
+use crate::libs::maintenance::{detect_maintenance, Maintenance};
+use crate::libs::stations::normalize_station;
+use crate::libs::tag_rules::TagCategory;
+use crate::HtmlDocument;
+
+use chrono::{Datelike, NaiveDate};
+
+use scraper::{ElementRef, Selector};
+use serde::{Deserialize, Serialize};
+
+// SF（電子マネー）利用履歴
+pub const USAGE_HISTORY_PATH: &str = "iq/ir/SuicaDisp.aspx?returnId=SFRCMMEPC03";
+
+#[derive(Debug, PartialEq)]
+pub enum UsageHistoryError {
+    RequestFailed,
+    // ログイン画面に戻された
+    SessionExpired,
+    Maintenance(Maintenance),
+}
+
+// 利用履歴の1行
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct UsageRecord {
+    // 月/日（履歴に年は表示されない）
+    pub date: String,
+    // 入, 物販, カード, バス等 など（半角カナは全角にそろえる）
+    pub entry_kind: String,
+    pub entry_place: String,
+    // 出
+    pub exit_kind: String,
+    pub exit_place: String,
+    // 残額
+    pub balance: Option<i64>,
+    // 差額。チャージは正、利用は負
+    pub amount: Option<i64>,
+    // 駅の表で正規化した駅名と駅ナンバリング。駅でない場合はNone
+    #[serde(default)]
+    pub entry_station: Option<String>,
+    #[serde(default)]
+    pub entry_station_code: Option<String>,
+    #[serde(default)]
+    pub exit_station: Option<String>,
+    #[serde(default)]
+    pub exit_station_code: Option<String>,
+    // ユーザーのルールで付けた区分とプロジェクト
+    #[serde(default)]
+    pub category: Option<TagCategory>,
+    #[serde(default)]
+    pub project: Option<String>,
+}
+
+impl UsageRecord {
+    // 利用場所を駅の表と照らし合わせて駅名と駅ナンバリングを埋める
+    pub fn resolve_stations(&mut self) {
+        let entry = normalize_station(&self.entry_place);
+        let exit = normalize_station(&self.exit_place);
+
+        self.entry_station = entry.map(|station| station.name.clone());
+        self.entry_station_code = entry.map(|station| station.code.clone());
+        self.exit_station = exit.map(|station| station.name.clone());
+        self.exit_station_code = exit.map(|station| station.code.clone());
+    }
+
+    // 履歴に年がないため、todayより後にならない直近の年を補う
+    pub fn infer_date(&self, today: NaiveDate) -> Option<NaiveDate> {
+        let (month, day) = parse_month_day(&self.date)?;
+
+        // 2/29は閏年まで遡る
+        (0..8)
+            .filter_map(|years_ago| NaiveDate::from_ymd_opt(today.year() - years_ago, month, day))
+            .find(|date| *date <= today)
+    }
+
+    // 利用履歴の表の列が同じ行か。駅の表で補った列や区分は比べない
+    pub fn is_same_row(&self, other: &UsageRecord) -> bool {
+        let text = |record: &UsageRecord| {
+            [
+                &record.entry_kind,
+                &record.entry_place,
+                &record.exit_kind,
+                &record.exit_place,
+            ]
+            .map(|text| normalize_text(text))
+        };
+
+        parse_month_day(&self.date) == parse_month_day(&other.date)
+            && text(self) == text(other)
+            && self.balance == other.balance
+            && self.amount == other.amount
+    }
+}
+
+fn find_history_table(document: &HtmlDocument) -> Option<ElementRef<'_>> {
+    if let Some(table) = document.query_selector("table.grybg01") {
+        return Some(table);
+    }
+
+    // クラス名が変わった場合に備えて、見出しに残額と差額がある一番内側の表を探す
+    document
+        .query_selector_all("table")?
+        .into_iter()
+        .filter(|table| {
+            let text = table.text().collect::<String>();
+            text.contains("残額") && text.contains("差額")
+        })
+        .last()
+}
+
+fn parse_row(row: &ElementRef) -> Option<UsageRecord> {
+    let cell_selector = Selector::parse("td").unwrap();
+
+    let cells = row
+        .select(&cell_selector)
+        .map(|cell| element_text(&cell))
+        .collect::<Vec<String>>();
+
+    // 見出し行や列数の違う行は読み飛ばす
+    if cells.len() != 7 {
+        return None;
+    }
+
+    let (month, day) = parse_month_day(&cells[0])?;
+
+    let mut record = UsageRecord {
+        date: format!("{:02}/{:02}", month, day),
+        entry_kind: cells[1].clone(),
+        entry_place: cells[2].clone(),
+        exit_kind: cells[3].clone(),
+        exit_place: cells[4].clone(),
+        balance: parse_yen(&cells[5]),
+        amount: parse_yen(&cells[6]),
+        ..Default::default()
+    };
+
+    record.resolve_stations();
+
+    Some(record)
+}
+
+// 利用履歴の表を読み取る。表がない場合はNone
+pub fn parse_usage_history(html: &str) -> Option<Vec<UsageRecord>> {
+    let document = HtmlDocument::new(html);
+    let row_selector = Selector::parse("tr").unwrap();
+
+    let table = find_history_table(&document)?;
+
+    Some(
+        table
+            .select(&row_selector)
+            .filter_map(|row| parse_row(&row))
+            .collect(),
+    )
+}
+
+// ログイン済みのクライアントで利用履歴を取得する
+pub async fn fetch_usage_history(
+    client: &reqwest::Client,
+) -> Result<Vec<UsageRecord>, UsageHistoryError> {
+    let url = format!("{}{}", BASE_URL, USAGE_HISTORY_PATH);
+
+    let html = async {
+        let response = client.get(url).send().await?;
+
+        get_text(response).await
+    }
+    .await
+    .map_err(|_| UsageHistoryError::RequestFailed)?;
+
+    if let Some(maintenance) = detect_maintenance(&html) {
+        return Err(UsageHistoryError::Maintenance(maintenance));
+    }
+
+    parse_usage_history(&html).ok_or(UsageHistoryError::SessionExpired)
+}
+
+// テスト用の乗車の行。駅の表で駅名を補う
+#[cfg(test)]
+pub(crate) fn ride(date: &str, entry_place: &str, exit_place: &str, amount: i64) -> UsageRecord {
+    let mut record = UsageRecord {
+        date: date.to_string(),
+        entry_kind: "入".to_string(),
+        entry_place: entry_place.to_string(),
+        exit_kind: "出".to_string(),
+        exit_place: exit_place.to_string(),
+        amount: Some(amount),
+        ..Default::default()
+    };
+
+    record.resolve_stations();
+
+    record
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::*;
+
+    #[test]
+    fn test_parse_usage_history() {
+        let records = parse_usage_history(include_str!("../../test/history.html")).unwrap();
+
+        assert_eq!(records.len(), 6);
+        assert_eq!(
+            records[0],
+            UsageRecord {
+                date: "09/16".to_string(),
+                entry_kind: "入".to_string(),
+                entry_place: "東京".to_string(),
+                exit_kind: "出".to_string(),
+                exit_place: "新宿".to_string(),
+                balance: Some(1528),
+                amount: Some(-208),
+                entry_station: Some("東京".to_string()),
+                entry_station_code: Some("JY01".to_string()),
+                exit_station: Some("新宿".to_string()),
+                exit_station_code: Some("JY17".to_string()),
+                ..Default::default()
+            }
+        );
+        assert_eq!(records[1].entry_kind, "物販");
+        assert_eq!(records[1].entry_station, None);
+        assert_eq!(records[1].entry_place, "");
+        assert_eq!(records[2].entry_kind, "カード");
+        assert_eq!(records[2].amount, Some(1000));
+        assert_eq!(records[5].amount, None);
+    }
+
+    #[test]
+    fn test_infer_date() {
+        let today = NaiveDate::from_ymd_opt(2024, 1, 10).unwrap();
+        let record = |date: &str| UsageRecord {
+            date: date.to_string(),
+            ..Default::default()
+        };
+
+        assert_eq!(
+            record("01/10").infer_date(today),
+            NaiveDate::from_ymd_opt(2024, 1, 10)
+        );
+        assert_eq!(
+            record("12/31").infer_date(today),
+            NaiveDate::from_ymd_opt(2023, 12, 31)
+        );
+        assert_eq!(
+            record("02/29").infer_date(today),
+            NaiveDate::from_ymd_opt(2020, 2, 29)
+        );
+        assert_eq!(record("繰").infer_date(today), None);
+    }
+
+    #[test]
+    fn test_is_same_row() {
+        let record = UsageRecord {
+            date: "9/16".to_string(),
+            entry_kind: "ｶｰﾄﾞ".to_string(),
+            entry_place: "ﾓﾊﾞｲﾙ".to_string(),
+            balance: Some(2528),
+            amount: Some(1000),
+            ..Default::default()
+        };
+        let normalized = UsageRecord {
+            date: "09/16".to_string(),
+            entry_kind: "カード".to_string(),
+            entry_place: "モバイル".to_string(),
+            project: Some("PRJ-001".to_string()),
+            ..record.clone()
+        };
+
+        assert!(record.is_same_row(&normalized));
+        assert!(!record.is_same_row(&UsageRecord {
+            amount: Some(2000),
+            ..normalized
+        }));
+    }
+
+    #[test]
+    fn test_parse_usage_history_login_page() {
+        assert_eq!(
+            parse_usage_history(include_str!("../../test/login.html")),
+            None
+        );
+    }
+}
@@ -0,0 +1,705 @@
+use crate::libs::japanese_text::normalize_text;
+use crate::libs::tag_rules::TagRule;
+use crate::libs::usage_history::UsageRecord;
+
+use argon2::password_hash::{
+    rand_core::OsRng, PasswordHash, PasswordHasher, PasswordVerifier, SaltString,
+};
+use argon2::Argon2;
+use chrono::{DateTime, FixedOffset, NaiveDate};
+use rusqlite::{params, Connection, OptionalExtension, Statement};
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::env;
+use std::path::Path;
+use std::sync::{Mutex, OnceLock};
+
+const DATABASE_ENV: &str = "MOBILESUICA_DATABASE";
+const DEFAULT_DATABASE_PATH: &str = "mobilesuica.sqlite3";
+
+const MIN_USERNAME_LENGTH: usize = 3;
+const MAX_USERNAME_LENGTH: usize = 32;
+const MIN_PASSWORD_LENGTH: usize = 8;
+
+const SCHEMA: &str = "
+PRAGMA foreign_keys = ON;
+
+CREATE TABLE IF NOT EXISTS users (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    username TEXT NOT NULL UNIQUE,
+    password_hash TEXT NOT NULL,
+    created_at INTEGER NOT NULL
+);
+
+CREATE TABLE IF NOT EXISTS linked_accounts (
+    user_id INTEGER NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    label TEXT NOT NULL,
+    email TEXT NOT NULL,
+    linked_at INTEGER NOT NULL,
+    PRIMARY KEY (user_id, label)
+);
+
+CREATE TABLE IF NOT EXISTS user_settings (
+    user_id INTEGER NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    key TEXT NOT NULL,
+    value TEXT NOT NULL,
+    PRIMARY KEY (user_id, key)
+);
+
+CREATE TABLE IF NOT EXISTS archived_history (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    user_id INTEGER NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    account TEXT NOT NULL,
+    -- 保存した日を基準に年を補った利用日（YYYY-MM-DD）。月/日が読めない行はNULL
+    ride_date TEXT,
+    -- 重複判定用のキー（record_keyを参照）
+    record_key TEXT NOT NULL,
+    record TEXT NOT NULL,
+    archived_at INTEGER NOT NULL,
+    UNIQUE (user_id, account, record_key)
+);
+
+CREATE TABLE IF NOT EXISTS tag_rules (
+    user_id INTEGER NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    position INTEGER NOT NULL,
+    rule TEXT NOT NULL,
+    PRIMARY KEY (user_id, position)
+);
+";
+
+#[derive(Debug)]
+pub enum UserStoreError {
+    Database(rusqlite::Error),
+    InvalidUsername,
+    WeakPassword,
+    UsernameTaken,
+    // ユーザー名とパスワードのどちらが違うかは区別しない
+    InvalidCredentials,
+    HashFailed,
+}
+
+impl From<rusqlite::Error> for UserStoreError {
+    fn from(error: rusqlite::Error) -> Self {
+        UserStoreError::Database(error)
+    }
+}
+
+pub fn get_user_store_error_message(error: &UserStoreError) -> String {
+    match error {
+        UserStoreError::Database(_) => "データベースの操作に失敗しました。".to_string(),
+        UserStoreError::InvalidUsername => format!(
+            "ユーザー名は{}〜{}文字の英数字と . _ - @ で入力してください。",
+            MIN_USERNAME_LENGTH, MAX_USERNAME_LENGTH
+        ),
+        UserStoreError::WeakPassword => format!(
+            "パスワードは{}文字以上で入力してください。",
+            MIN_PASSWORD_LENGTH
+        ),
+        UserStoreError::UsernameTaken => "このユーザー名は既に使われています。".to_string(),
+        UserStoreError::InvalidCredentials => {
+            "ユーザー名またはパスワードが正しくありません。".to_string()
+        }
+        UserStoreError::HashFailed => "パスワードの処理に失敗しました。".to_string(),
+    }
+}
+
+pub fn get_user_store_error_code(error: &UserStoreError) -> String {
+    match error {
+        UserStoreError::Database(_) => "database_error",
+        UserStoreError::InvalidUsername => "invalid_username",
+        UserStoreError::WeakPassword => "weak_password",
+        UserStoreError::UsernameTaken => "username_taken",
+        UserStoreError::InvalidCredentials => "invalid_credentials",
+        UserStoreError::HashFailed => "hash_failed",
+    }
+    .to_string()
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct User {
+    pub id: i64,
+    pub username: String,
+    // UNIX時間の秒
+    pub created_at: i64,
+}
+
+// ユーザーに紐付けたモバイルSuicaのアカウント。Cookieはセッションにのみ保持する
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct LinkedAccount {
+    pub label: String,
+    pub email: String,
+    pub linked_at: i64,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct ArchivedRecord {
+    pub account: String,
+    #[serde(flatten)]
+    pub record: UsageRecord,
+    // 年を補った利用日
+    pub ride_date: Option<NaiveDate>,
+    pub archived_at: i64,
+}
+
+fn now() -> i64 {
+    chrono::Local::now().timestamp()
+}
+
+// UNIX時間を日本時間の日付にする。履歴の月/日に年を補う基準にする
+fn jst_date(timestamp: i64) -> Option<NaiveDate> {
+    let jst = FixedOffset::east_opt(9 * 60 * 60).unwrap();
+
+    Some(
+        DateTime::from_timestamp(timestamp, 0)?
+            .with_timezone(&jst)
+            .date_naive(),
+    )
+}
+
+// 蓄積した履歴の重複判定のキー
+// UsageRecordに列が増えたり表記の正規化が変わったりしても同じ行が同じキーになるよう、
+// 履歴の表にある列だけを正規化して並べる
+fn record_key(record: &UsageRecord, ride_date: Option<NaiveDate>) -> String {
+    let amount = |amount: Option<i64>| amount.map(|amount| amount.to_string()).unwrap_or_default();
+
+    [
+        ride_date
+            .map(|date| date.to_string())
+            .unwrap_or_else(|| normalize_text(&record.date)),
+        normalize_text(&record.entry_kind),
+        normalize_text(&record.entry_place),
+        normalize_text(&record.exit_kind),
+        normalize_text(&record.exit_place),
+        amount(record.balance),
+        amount(record.amount),
+    ]
+    .join("\t")
+}
+
+fn insert_archived_record(
+    statement: &mut Statement,
+    user_id: i64,
+    account: &str,
+    record: &UsageRecord,
+    archived_at: i64,
+) -> Result<usize, rusqlite::Error> {
+    let ride_date = jst_date(archived_at).and_then(|today| record.infer_date(today));
+
+    statement.execute(params![
+        user_id,
+        account,
+        ride_date.map(|date| date.to_string()),
+        record_key(record, ride_date),
+        serde_json::to_string(record).unwrap(),
+        archived_at
+    ])
+}
+
+const INSERT_ARCHIVED_RECORD: &str = "INSERT OR IGNORE INTO archived_history
+    (user_id, account, ride_date, record_key, record, archived_at)
+    VALUES (?1, ?2, ?3, ?4, ?5, ?6)";
+
+fn is_valid_username(username: &str) -> bool {
+    (MIN_USERNAME_LENGTH..=MAX_USERNAME_LENGTH).contains(&username.chars().count())
+        && username
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '.' | '_' | '-' | '@'))
+}
+
+fn hash_password(password: &str) -> Result<String, UserStoreError> {
+    let salt = SaltString::generate(&mut OsRng);
+
+    Argon2::default()
+        .hash_password(password.as_bytes(), &salt)
+        .map(|hash| hash.to_string())
+        .map_err(|_| UserStoreError::HashFailed)
+}
+
+fn verify_password(password: &str, password_hash: &str) -> bool {
+    PasswordHash::new(password_hash).is_ok_and(|hash| {
+        Argon2::default()
+            .verify_password(password.as_bytes(), &hash)
+            .is_ok()
+    })
+}
+
+// アプリのユーザーと、ユーザーに紐付くアカウント・設定・履歴の保存先
+#[derive(Debug)]
+pub struct UserStore {
+    connection: Mutex<Connection>,
+}
+
+impl UserStore {
+    pub fn open(path: impl AsRef<Path>) -> Result<Self, UserStoreError> {
+        Self::with_connection(Connection::open(path)?)
+    }
+
+    pub fn open_in_memory() -> Result<Self, UserStoreError> {
+        Self::with_connection(Connection::open_in_memory()?)
+    }
+
+    fn with_connection(connection: Connection) -> Result<Self, UserStoreError> {
+        connection.execute_batch(SCHEMA)?;
+
+        Ok(UserStore {
+            connection: Mutex::new(connection),
+        })
+    }
+
+    pub fn register(&self, username: &str, password: &str) -> Result<User, UserStoreError> {
+        if !is_valid_username(username) {
+            return Err(UserStoreError::InvalidUsername);
+        }
+
+        if password.chars().count() < MIN_PASSWORD_LENGTH {
+            return Err(UserStoreError::WeakPassword);
+        }
+
+        let password_hash = hash_password(password)?;
+        let created_at = now();
+
+        let connection = self.connection.lock().unwrap();
+
+        connection
+            .execute(
+                "INSERT INTO users (username, password_hash, created_at) VALUES (?1, ?2, ?3)",
+                params![username, password_hash, created_at],
+            )
+            .map_err(|e| match e.sqlite_error_code() {
+                Some(rusqlite::ErrorCode::ConstraintViolation) => UserStoreError::UsernameTaken,
+                _ => UserStoreError::Database(e),
+            })?;
+
+        Ok(User {
+            id: connection.last_insert_rowid(),
+            username: username.to_string(),
+            created_at,
+        })
+    }
+
+    pub fn authenticate(&self, username: &str, password: &str) -> Result<User, UserStoreError> {
+        let row = self
+            .connection
+            .lock()
+            .unwrap()
+            .query_row(
+                "SELECT id, username, created_at, password_hash FROM users WHERE username = ?1",
+                params![username],
+                |row| {
+                    Ok((
+                        User {
+                            id: row.get(0)?,
+                            username: row.get(1)?,
+                            created_at: row.get(2)?,
+                        },
+                        row.get::<_, String>(3)?,
+                    ))
+                },
+            )
+            .optional()?;
+
+        match row {
+            Some((user, password_hash)) if verify_password(password, &password_hash) => Ok(user),
+            _ => Err(UserStoreError::InvalidCredentials),
+        }
+    }
+
+    pub fn get_user(&self, user_id: i64) -> Result<Option<User>, UserStoreError> {
+        Ok(self
+            .connection
+            .lock()
+            .unwrap()
+            .query_row(
+                "SELECT id, username, created_at FROM users WHERE id = ?1",
+                params![user_id],
+                |row| {
+                    Ok(User {
+                        id: row.get(0)?,
+                        username: row.get(1)?,
+                        created_at: row.get(2)?,
+                    })
+                },
+            )
+            .optional()?)
+    }
+
+    // 同じラベルの場合はメールアドレスを更新する
+    pub fn link_account(
+        &self,
+        user_id: i64,
+        label: &str,
+        email: &str,
+    ) -> Result<(), UserStoreError> {
+        self.connection.lock().unwrap().execute(
+            "INSERT INTO linked_accounts (user_id, label, email, linked_at) VALUES (?1, ?2, ?3, ?4)
+             ON CONFLICT (user_id, label) DO UPDATE SET email = excluded.email, linked_at = excluded.linked_at",
+            params![user_id, label, email, now()],
+        )?;
+
+        Ok(())
+    }
+
+    pub fn unlink_account(&self, user_id: i64, label: &str) -> Result<bool, UserStoreError> {
+        let deleted = self.connection.lock().unwrap().execute(
+            "DELETE FROM linked_accounts WHERE user_id = ?1 AND label = ?2",
+            params![user_id, label],
+        )?;
+
+        Ok(deleted > 0)
+    }
+
+    pub fn linked_accounts(&self, user_id: i64) -> Result<Vec<LinkedAccount>, UserStoreError> {
+        let connection = self.connection.lock().unwrap();
+
+        let mut statement = connection.prepare(
+            "SELECT label, email, linked_at FROM linked_accounts WHERE user_id = ?1 ORDER BY label",
+        )?;
+
+        let accounts = statement
+            .query_map(params![user_id], |row| {
+                Ok(LinkedAccount {
+                    label: row.get(0)?,
+                    email: row.get(1)?,
+                    linked_at: row.get(2)?,
+                })
+            })?
+            .collect::<Result<Vec<LinkedAccount>, rusqlite::Error>>()?;
+
+        Ok(accounts)
+    }
+
+    pub fn set_setting(&self, user_id: i64, key: &str, value: &str) -> Result<(), UserStoreError> {
+        self.connection.lock().unwrap().execute(
+            "INSERT INTO user_settings (user_id, key, value) VALUES (?1, ?2, ?3)
+             ON CONFLICT (user_id, key) DO UPDATE SET value = excluded.value",
+            params![user_id, key, value],
+        )?;
+
+        Ok(())
+    }
+
+    pub fn settings(&self, user_id: i64) -> Result<BTreeMap<String, String>, UserStoreError> {
+        let connection = self.connection.lock().unwrap();
+
+        let mut statement =
+            connection.prepare("SELECT key, value FROM user_settings WHERE user_id = ?1")?;
+
+        let settings = statement
+            .query_map(params![user_id], |row| Ok((row.get(0)?, row.get(1)?)))?
+            .collect::<Result<BTreeMap<String, String>, rusqlite::Error>>()?;
+
+        Ok(settings)
+    }
+
+    // モバイルSuicaでは直近の履歴しか見られないので取得した履歴を蓄積する
+    // 既に保存済みの行は無視し、追加した件数を返す
+    pub fn archive_history(
+        &self,
+        user_id: i64,
+        account: &str,
+        records: &[UsageRecord],
+    ) -> Result<usize, UserStoreError> {
+        let mut connection = self.connection.lock().unwrap();
+        let transaction = connection.transaction()?;
+        let archived_at = now();
+
+        let mut inserted = 0;
+
+        {
+            let mut statement = transaction.prepare(INSERT_ARCHIVED_RECORD)?;
+
+            for record in records {
+                inserted +=
+                    insert_archived_record(&mut statement, user_id, account, record, archived_at)?;
+            }
+        }
+
+        transaction.commit()?;
+
+        Ok(inserted)
+    }
+
+    // ルールは順序に意味があるので、まとめて置き換える
+    pub fn set_tag_rules(&self, user_id: i64, rules: &[TagRule]) -> Result<(), UserStoreError> {
+        let mut connection = self.connection.lock().unwrap();
+        let transaction = connection.transaction()?;
+
+        transaction.execute("DELETE FROM tag_rules WHERE user_id = ?1", params![user_id])?;
+
+        {
+            let mut statement = transaction
+                .prepare("INSERT INTO tag_rules (user_id, position, rule) VALUES (?1, ?2, ?3)")?;
+
+            for (position, rule) in rules.iter().enumerate() {
+                let rule = serde_json::to_string(rule).unwrap();
+
+                statement.execute(params![user_id, position as i64, rule])?;
+            }
+        }
+
+        transaction.commit()?;
+
+        Ok(())
+    }
+
+    pub fn tag_rules(&self, user_id: i64) -> Result<Vec<TagRule>, UserStoreError> {
+        let connection = self.connection.lock().unwrap();
+
+        let mut statement = connection
+            .prepare("SELECT rule FROM tag_rules WHERE user_id = ?1 ORDER BY position")?;
+
+        let rules = statement
+            .query_map(params![user_id], |row| row.get::<_, String>(0))?
+            .collect::<Result<Vec<String>, rusqlite::Error>>()?;
+
+        Ok(rules
+            .iter()
+            .filter_map(|rule| serde_json::from_str(rule).ok())
+            .collect())
+    }
+
+    // 保存した順（各取得の中では新しい順）に返す
+    pub fn archived_history(
+        &self,
+        user_id: i64,
+        account: Option<&str>,
+    ) -> Result<Vec<ArchivedRecord>, UserStoreError> {
+        let connection = self.connection.lock().unwrap();
+
+        let mut statement = connection.prepare(
+            "SELECT account, record, ride_date, archived_at FROM archived_history
+             WHERE user_id = ?1 AND (?2 IS NULL OR account = ?2) ORDER BY id",
+        )?;
+
+        let rows = statement
+            .query_map(params![user_id, account], |row| {
+                Ok((
+                    row.get::<_, String>(0)?,
+                    row.get::<_, String>(1)?,
+                    row.get::<_, Option<String>>(2)?,
+                    row.get::<_, i64>(3)?,
+                ))
+            })?
+            .collect::<Result<Vec<_>, rusqlite::Error>>()?;
+
+        Ok(rows
+            .into_iter()
+            .filter_map(|(account, record, ride_date, archived_at)| {
+                Some(ArchivedRecord {
+                    account,
+                    record: serde_json::from_str(&record).ok()?,
+                    ride_date: ride_date.and_then(|date| date.parse().ok()),
+                    archived_at,
+                })
+            })
+            .collect())
+    }
+}
+
+// MOBILESUICA_DATABASEで指定されたSQLiteファイルを開く
+pub fn user_store_from_env() -> Result<UserStore, UserStoreError> {
+    let path = env::var(DATABASE_ENV)
+        .ok()
+        .filter(|path| !path.is_empty())
+        .unwrap_or(DEFAULT_DATABASE_PATH.to_string());
+
+    UserStore::open(path)
+}
+
+static USER_STORE: OnceLock<UserStore> = OnceLock::new();
+
+// サーバー起動時に一度だけ設定する
+pub fn set_user_store(store: UserStore) -> Result<(), UserStore> {
+    USER_STORE.set(store)
+}
+
+pub fn get_user_store() -> Option<&'static UserStore> {
+    USER_STORE.get()
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::*;
+
+    fn record(date: &str, balance: i64, amount: i64) -> UsageRecord {
+        UsageRecord {
+            date: date.to_string(),
+            entry_kind: "入".to_string(),
+            balance: Some(balance),
+            amount: Some(amount),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_register_and_authenticate() {
+        let store = UserStore::open_in_memory().unwrap();
+
+        let user = store.register("suica-user", "password1").unwrap();
+
+        assert_eq!(user.username, "suica-user");
+        assert_eq!(store.authenticate("suica-user", "password1").unwrap(), user);
+        assert_eq!(store.get_user(user.id).unwrap(), Some(user));
+
+        assert!(matches!(
+            store.authenticate("suica-user", "password2"),
+            Err(UserStoreError::InvalidCredentials)
+        ));
+        assert!(matches!(
+            store.authenticate("unknown", "password1"),
+            Err(UserStoreError::InvalidCredentials)
+        ));
+    }
+
+    #[test]
+    fn test_register_error() {
+        let store = UserStore::open_in_memory().unwrap();
+
+        store.register("suica-user", "password1").unwrap();
+
+        assert!(matches!(
+            store.register("suica-user", "password1"),
+            Err(UserStoreError::UsernameTaken)
+        ));
+        assert!(matches!(
+            store.register("ab", "password1"),
+            Err(UserStoreError::InvalidUsername)
+        ));
+        assert!(matches!(
+            store.register("user name", "password1"),
+            Err(UserStoreError::InvalidUsername)
+        ));
+        assert!(matches!(
+            store.register("another", "short"),
+            Err(UserStoreError::WeakPassword)
+        ));
+    }
+
+    #[test]
+    fn test_password_is_hashed() {
+        let hash = hash_password("password1").unwrap();
+
+        assert!(hash.starts_with("$argon2"));
+        assert!(verify_password("password1", &hash));
+        assert!(!verify_password("password2", &hash));
+        assert!(!verify_password("password1", "not a hash"));
+    }
+
+    #[test]
+    fn test_linked_accounts_and_settings() {
+        let store = UserStore::open_in_memory().unwrap();
+        let user = store.register("suica-user", "password1").unwrap();
+
+        store
+            .link_account(user.id, "personal", "old@example.com")
+            .unwrap();
+        store
+            .link_account(user.id, "personal", "personal@example.com")
+            .unwrap();
+        store
+            .link_account(user.id, "corporate", "corporate@example.com")
+            .unwrap();
+
+        let accounts = store.linked_accounts(user.id).unwrap();
+
+        assert_eq!(accounts.len(), 2);
+        assert_eq!(accounts[0].label, "corporate");
+        assert_eq!(accounts[1].email, "personal@example.com");
+
+        assert!(store.unlink_account(user.id, "corporate").unwrap());
+        assert!(!store.unlink_account(user.id, "corporate").unwrap());
+
+        store.set_setting(user.id, "export_format", "csv").unwrap();
+        store.set_setting(user.id, "export_format", "json").unwrap();
+
+        assert_eq!(
+            store.settings(user.id).unwrap(),
+            BTreeMap::from([("export_format".to_string(), "json".to_string())])
+        );
+    }
+
+    #[test]
+    fn test_archive_history() {
+        let store = UserStore::open_in_memory().unwrap();
+        let user = store.register("suica-user", "password1").unwrap();
+
+        let first = vec![record("09/16", 1528, -208), record("09/15", 1736, -264)];
+        let second = vec![record("09/17", 1320, -208), record("09/16", 1528, -208)];
+
+        assert_eq!(
+            store.archive_history(user.id, "personal", &first).unwrap(),
+            2
+        );
+        // 前回と重なる行は保存しない
+        assert_eq!(
+            store.archive_history(user.id, "personal", &second).unwrap(),
+            1
+        );
+        assert_eq!(
+            store.archive_history(user.id, "corporate", &first).unwrap(),
+            2
+        );
+
+        let personal = store.archived_history(user.id, Some("personal")).unwrap();
+
+        assert_eq!(personal.len(), 3);
+        assert_eq!(personal[2].record, second[0]);
+
+        assert_eq!(store.archived_history(user.id, None).unwrap().len(), 5);
+    }
+
+    #[test]
+    fn test_archive_history_ignores_derived_fields() {
+        let store = UserStore::open_in_memory().unwrap();
+        let user = store.register("suica-user", "password1").unwrap();
+
+        let first = UsageRecord {
+            entry_kind: "ｶｰﾄﾞ".to_string(),
+            entry_place: "ﾓﾊﾞｲﾙ".to_string(),
+            ..record("09/16", 1528, 1000)
+        };
+        // 表記の正規化や駅の表で補う列が変わっても同じ行とみなす
+        let second = UsageRecord {
+            entry_kind: "カード".to_string(),
+            entry_place: "モバイル".to_string(),
+            entry_station: Some("モバイル".to_string()),
+            ..record("9/16", 1528, 1000)
+        };
+
+        assert_eq!(store.archive_history(user.id, "main", &[first]).unwrap(), 1);
+        assert_eq!(
+            store.archive_history(user.id, "main", &[second]).unwrap(),
+            0
+        );
+
+        let archived = store.archived_history(user.id, None).unwrap();
+        let today = jst_date(archived[0].archived_at).unwrap();
+
+        assert_eq!(archived.len(), 1);
+        assert_eq!(archived[0].ride_date, archived[0].record.infer_date(today));
+    }
+
+    #[test]
+    fn test_tag_rules() {
+        let store = UserStore::open_in_memory().unwrap();
+        let user = store.register("suica-user", "password1").unwrap();
+
+        assert_eq!(store.tag_rules(user.id).unwrap(), vec![]);
+
+        let rules: Vec<TagRule> = serde_json::from_str(
+            r#"[
+                { "name": "通勤", "match": { "stations": ["東京", "新宿"] }, "category": "business" },
+                { "name": "買い物", "match": { "kind": "物販" }, "category": "personal" }
+            ]"#,
+        )
+        .unwrap();
+
+        store.set_tag_rules(user.id, &rules).unwrap();
+        assert_eq!(store.tag_rules(user.id).unwrap(), rules);
+
+        // 置き換えると以前のルールは残らない
+        store.set_tag_rules(user.id, &rules[1..]).unwrap();
+        assert_eq!(store.tag_rules(user.id).unwrap(), rules[1..]);
+    }
+}
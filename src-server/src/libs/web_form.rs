@@ -0,0 +1,316 @@
+use crate::libs::sjis_urlencoded::serialize_into_sjis;
+use crate::HtmlDocument;
+
+use scraper::{ElementRef, Selector};
+use serde::{Deserialize, Serialize};
+
+const EVENT_TARGET: &str = "__EVENTTARGET";
+const EVENT_ARGUMENT: &str = "__EVENTARGUMENT";
+
+// ASP.NET WebFormsのフォームを汎用的に扱う
+// ページ上の入力項目を順番どおりに保持し、値の上書きやポストバックをしてSJISで送信する
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct WebForm {
+    action: String,
+    method: String,
+    fields: Vec<(String, String)>,
+    // 押されたときだけ送信されるボタン
+    submitters: Vec<(String, String)>,
+}
+
+fn is_disabled(element: &ElementRef) -> bool {
+    element.value().attr("disabled").is_some()
+}
+
+fn select_values(element: &ElementRef) -> Vec<String> {
+    let option_selector = Selector::parse("option").unwrap();
+    let options = element
+        .select(&option_selector)
+        .collect::<Vec<ElementRef>>();
+
+    let option_value = |option: &ElementRef| match option.value().attr("value") {
+        Some(value) => value.to_string(),
+        None => option.text().collect::<String>().trim().to_string(),
+    };
+
+    let selected = options
+        .iter()
+        .filter(|option| option.value().attr("selected").is_some())
+        .map(option_value)
+        .collect::<Vec<String>>();
+
+    // 未選択のシングルセレクトは先頭が選ばれている
+    match (selected.is_empty(), element.value().attr("multiple")) {
+        (true, None) => options.first().map(option_value).into_iter().collect(),
+        _ => selected,
+    }
+}
+
+impl WebForm {
+    // selectorに一致する最初の<form>を読み込む
+    pub fn new(html: &str, selector: &str) -> Option<Self> {
+        let document = HtmlDocument::new(html);
+
+        document
+            .query_selector(selector)
+            .filter(|element| element.value().name() == "form")
+            .map(|form| WebForm::from_element(&form))
+    }
+
+    pub fn from_element(form: &ElementRef) -> Self {
+        let control_selector =
+            Selector::parse("input[name], select[name], textarea[name], button[name]").unwrap();
+
+        let mut fields = vec![];
+        let mut submitters = vec![];
+
+        for control in form.select(&control_selector) {
+            if is_disabled(&control) {
+                continue;
+            }
+
+            let element = control.value();
+            let name = element.attr("name").unwrap_or("").to_string();
+            let value = element.attr("value").unwrap_or("").to_string();
+
+            match element.name() {
+                "select" => {
+                    for value in select_values(&control) {
+                        fields.push((name.clone(), value));
+                    }
+                }
+                "textarea" => fields.push((name, control.text().collect::<String>())),
+                "button" => match element.attr("type").map(|t| t.to_ascii_lowercase()) {
+                    Some(t) if t != "submit" => {}
+                    _ => submitters.push((name, value)),
+                },
+                _ => match element
+                    .attr("type")
+                    .unwrap_or("text")
+                    .to_ascii_lowercase()
+                    .as_str()
+                {
+                    "submit" | "image" => submitters.push((name, value)),
+                    "button" | "reset" | "file" => {}
+                    "checkbox" | "radio" => {
+                        if element.attr("checked").is_some() {
+                            let value = element.attr("value").unwrap_or("on").to_string();
+                            fields.push((name, value));
+                        }
+                    }
+                    _ => fields.push((name, value)),
+                },
+            }
+        }
+
+        WebForm {
+            action: form.value().attr("action").unwrap_or("").to_string(),
+            method: form
+                .value()
+                .attr("method")
+                .unwrap_or("get")
+                .to_ascii_lowercase(),
+            fields,
+            submitters,
+        }
+    }
+
+    pub fn action(&self) -> &str {
+        &self.action
+    }
+
+    pub fn method(&self) -> &str {
+        &self.method
+    }
+
+    pub fn fields(&self) -> &[(String, String)] {
+        &self.fields
+    }
+
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.fields
+            .iter()
+            .find(|(key, _)| key == name)
+            .map(|(_, value)| value.as_str())
+    }
+
+    // 既存の項目は位置を保ったまま上書きし、ない場合は末尾に追加する
+    pub fn set(&mut self, name: &str, value: &str) -> &mut Self {
+        match self.fields.iter_mut().find(|(key, _)| key == name) {
+            Some(field) => field.1 = value.to_string(),
+            None => self.fields.push((name.to_string(), value.to_string())),
+        }
+
+        self
+    }
+
+    pub fn remove(&mut self, name: &str) -> &mut Self {
+        self.fields.retain(|(key, _)| key != name);
+
+        self
+    }
+
+    // 送信ボタンを押したことにする
+    pub fn click(&mut self, name: &str) -> &mut Self {
+        if let Some((name, value)) = self.submitters.iter().find(|(key, _)| key == name).cloned() {
+            self.set(&name, &value);
+        }
+
+        self
+    }
+
+    // __doPostBack(eventTarget, eventArgument)と同じ
+    pub fn postback(&mut self, event_target: &str, event_argument: &str) -> &mut Self {
+        self.set(EVENT_TARGET, event_target)
+            .set(EVENT_ARGUMENT, event_argument)
+    }
+
+    // 各パラメータをSJISにしてURIエンコードする
+    pub fn serialize_into_sjis(&self) -> String {
+        serialize_into_sjis(self.fields.iter().map(|(key, value)| (key, value)))
+    }
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::*;
+
+    #[test]
+    fn test_web_form_new() {
+        let form = WebForm::new(include_str!("../../test/login.html"), "#form1").unwrap();
+
+        assert_eq!(
+            form.action(),
+            "ka/lg/LoginForm.aspx?pguid=af60fc73-558f-460c-a2b1-d830c426f307"
+        );
+        assert_eq!(form.method(), "post");
+
+        let names = form
+            .fields()
+            .iter()
+            .map(|(name, _)| name.as_str())
+            .collect::<Vec<&str>>();
+
+        assert_eq!(
+            names,
+            vec![
+                "__EVENTTARGET",
+                "__EVENTARGUMENT",
+                "__VIEWSTATE",
+                "__VIEWSTATEGENERATOR",
+                "__VIEWSTATEENCRYPTED",
+                "baseVariable",
+                "baseVarLogoutBtn",
+                "MailAddress",
+                "Password",
+                "WebCaptcha1_clientState",
+                "WebCaptcha1__editor_clientState",
+                "WebCaptcha1__editor",
+            ]
+        );
+        assert_eq!(form.get("__VIEWSTATEGENERATOR"), Some("BB3126B1"));
+        assert_eq!(form.get("baseVarLogoutBtn"), Some("off"));
+        assert_eq!(form.get("LOGIN"), None);
+    }
+
+    #[test]
+    fn test_web_form_new_by_name() {
+        let form =
+            WebForm::new(include_str!("../../test/login.html"), "form[name='form2']").unwrap();
+
+        assert_eq!(
+            form.action(),
+            "https://my.jreast.co.jp/web/au/OutsideLoginForm.aspx"
+        );
+        assert_eq!(form.get("ServiceCode"), Some("3"));
+        assert_eq!(form.get("CommonID"), Some(""));
+        assert_eq!(
+            form.get("ReturnUrl"),
+            Some("https://www.mobilesuica.com/ka/lg/LoginForm.aspx?pguid=af60fc73-558f-460c-a2b1-d830c426f307")
+        );
+
+        assert!(WebForm::new(include_str!("../../test/login.html"), "#tab_1").is_none());
+    }
+
+    #[test]
+    fn test_web_form_controls() {
+        let html = r#"
+        <form method="post" action="test.aspx">
+            <input type="hidden" name="hidden" value="1" />
+            <input type="text" name="disabled" value="2" disabled />
+            <input type="checkbox" name="checked" checked />
+            <input type="checkbox" name="unchecked" value="3" />
+            <input type="radio" name="radio" value="a" />
+            <input type="radio" name="radio" value="b" checked />
+            <select name="month">
+                <option value="08">8月</option>
+                <option value="09" selected>9月</option>
+            </select>
+            <select name="first">
+                <option>先頭</option>
+                <option>2番目</option>
+            </select>
+            <textarea name="memo">メモ</textarea>
+            <input type="submit" name="search" value="検索" />
+            <button name="LOGIN" value="ログイン">ログイン</button>
+            <button type="button" name="noop" value="noop">noop</button>
+        </form>
+        "#;
+
+        let mut form = WebForm::new(html, "form").unwrap();
+
+        assert_eq!(
+            form.fields(),
+            &[
+                ("hidden".to_string(), "1".to_string()),
+                ("checked".to_string(), "on".to_string()),
+                ("radio".to_string(), "b".to_string()),
+                ("month".to_string(), "09".to_string()),
+                ("first".to_string(), "先頭".to_string()),
+                ("memo".to_string(), "メモ".to_string()),
+            ]
+        );
+
+        form.click("LOGIN").click("noop");
+
+        assert_eq!(form.get("LOGIN"), Some("ログイン"));
+        assert_eq!(form.get("noop"), None);
+        assert_eq!(form.get("search"), None);
+    }
+
+    #[test]
+    fn test_web_form_set_and_postback() {
+        let mut form = WebForm::new(include_str!("../../test/login.html"), "#form1").unwrap();
+
+        form.set("MailAddress", "test@example.com")
+            .set("ReturnUrl", "/")
+            .remove("WebCaptcha1_clientState")
+            .postback("RefreshButtonLink", "");
+
+        assert_eq!(form.get("MailAddress"), Some("test@example.com"));
+        assert_eq!(form.get("__EVENTTARGET"), Some("RefreshButtonLink"));
+        assert_eq!(form.get("WebCaptcha1_clientState"), None);
+        assert_eq!(form.fields().last().unwrap().0, "ReturnUrl");
+        // 上書きしても順番は変わらない
+        assert_eq!(form.fields()[0].0, "__EVENTTARGET");
+    }
+
+    #[test]
+    fn test_web_form_serialize() {
+        let html = r#"
+        <form method="post" action="test.aspx">
+            <input type="hidden" name="__EVENTTARGET" value="" />
+            <input type="text" name="LOGIN" value="ログイン" />
+            <input type="text" name="mail" value="a b@example.com" />
+        </form>
+        "#;
+
+        let form = WebForm::new(html, "form").unwrap();
+
+        assert_eq!(
+            form.serialize_into_sjis(),
+            "__EVENTTARGET=&LOGIN=%83%8D%83O%83C%83%93&mail=a+b%40example.com"
+        );
+    }
+}
@@ -5,11 +5,48 @@ mod store;
 
 use axum::{middleware as axum_middleware, Router};
 use middleware::session_middleware;
+use mobilesuica_sheet_app_server::HttpClient::{set_client_config, ClientConfig};
+use mobilesuica_sheet_app_server::{
+    captcha_solver_from_env, credentials_provider_from_env, get_credentials_error_message,
+    get_user_store_error_message, set_captcha_solver, set_credentials_provider, set_user_store,
+    shared_credentials_allowed, user_store_from_env, CredentialsError,
+};
 use store::AppState;
 
 #[tokio::main]
 async fn main() {
     tracing_subscriber::fmt::init();
+
+    let client_config = ClientConfig::from_env().expect("failed to load http client config");
+    set_client_config(client_config).expect("http client config is already set");
+
+    let captcha_solver = captcha_solver_from_env().expect("failed to load captcha templates");
+    tracing::info!("captcha solver: {}", captcha_solver.name());
+    if set_captcha_solver(captcha_solver).is_err() {
+        panic!("captcha solver is already set");
+    }
+
+    let credentials_provider = credentials_provider_from_env()
+        .unwrap_or_else(|e| panic!("{}", get_credentials_error_message(&e)));
+    if let Some(provider) = credentials_provider {
+        if !shared_credentials_allowed() {
+            panic!(
+                "{}",
+                get_credentials_error_message(&CredentialsError::SharedCredentialsNotAllowed)
+            );
+        }
+        tracing::info!("credentials provider: {}", provider.name());
+        if set_credentials_provider(provider).is_err() {
+            panic!("credentials provider is already set");
+        }
+    }
+
+    let user_store =
+        user_store_from_env().unwrap_or_else(|e| panic!("{}", get_user_store_error_message(&e)));
+    if set_user_store(user_store).is_err() {
+        panic!("user store is already set");
+    }
+
     let state = AppState::default();
 
     let app = Router::new()
@@ -31,6 +31,9 @@ pub async fn session_middleware(
     // レスポンスここから
     let mut response = next.run(req).await;
 
+    // ログイン・ログアウトでセッションIDが替わった場合は新しいIDを返す
+    let session_id = state.session.lock().unwrap().id().to_string();
+
     response.headers_mut().insert(
         SESSION_ID_HEADER,
         header::HeaderValue::from_str(&session_id).unwrap(),
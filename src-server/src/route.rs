@@ -1,13 +1,37 @@
-use crate::controllers::{auth, captcha};
+use crate::controllers::{accounts, auth, captcha, card, receipt, status, users};
 
 use crate::store::AppState;
 use axum::{
-    routing::{get, post},
+    routing::{delete, get, post},
     Router,
 };
 
 pub fn api_router() -> Router<AppState> {
     Router::new()
         .route("/captcha", get(captcha::handler))
+        .route("/captcha/refresh", post(captcha::refresh_handler))
         .route("/auth", post(auth::handler))
+        .route("/auth/auto", post(auth::auto_handler))
+        .route("/accounts", get(accounts::list_handler).post(auth::handler))
+        .route("/accounts/:label", delete(accounts::delete_handler))
+        .route("/accounts/:label/history", get(accounts::history_handler))
+        .route("/accounts/:label/journeys", get(accounts::journeys_handler))
+        .route("/auto-charge", get(card::auto_charge_handler))
+        .route("/card", get(card::handler))
+        .route("/commuter-pass", get(card::commuter_pass_handler))
+        .route("/green-tickets", get(card::green_tickets_handler))
+        .route("/history", get(accounts::merged_history_handler))
+        .route("/receipt", post(receipt::handler))
+        .route("/status", get(status::handler))
+        .route("/users", post(users::register_handler))
+        .route("/users/login", post(users::login_handler))
+        .route("/users/logout", post(users::logout_handler))
+        .route("/users/me", get(users::me_handler))
+        .route("/users/me/settings", post(users::settings_handler))
+        .route("/users/me/history", get(users::history_handler))
+        .route("/users/me/report", get(users::report_handler))
+        .route(
+            "/users/me/rules",
+            get(users::rules_handler).put(users::update_rules_handler),
+        )
 }